@@ -0,0 +1,47 @@
+use anyhow::Context;
+use gwynn_fs::{export::ExportOptions, query::Query};
+
+fn main() -> anyhow::Result<()> {
+    // Usage: export <output .tar/.tar.zst/.zip> <glob pattern> [--raw|--png|--dds]
+    // Converting textures with --png or --dds needs the `texture` feature
+    let mut args = std::env::args().skip(1);
+    let output = std::path::PathBuf::from(args.next().context("Missing output path")?);
+    let pattern = args.next().context("Missing glob pattern")?;
+    let mode = args.next();
+    let raw = mode.as_deref() == Some("--raw");
+
+    #[cfg(feature = "texture")]
+    let converter = {
+        use gwynn_fs::texture::{TextureConverter, TextureOutput};
+        match mode.as_deref() {
+            Some("--png") => Some(TextureConverter::new(TextureOutput::Image(
+                gwynn_texture::export::ImageFormat::Png,
+            ))),
+            Some("--dds") => Some(TextureConverter::new(TextureOutput::Container(
+                gwynn_texture::container::ContainerFormat::Dds,
+            ))),
+            _ => None,
+        }
+    };
+
+    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
+    let matches = fs.query(&Query::glob(&pattern)?);
+    println!("Exporting {} files to {}", matches.len(), output.display());
+
+    let options = ExportOptions {
+        decompress: !raw,
+        #[cfg(feature = "texture")]
+        converter: converter
+            .as_ref()
+            .map(|c| c as &dyn gwynn_fs::export::ExportConverter),
+        ..Default::default()
+    };
+    let report = fs.export_to_file(matches.iter().map(|m| m.path), &output, &options)?;
+    println!(
+        "Exported {} files, {} failed",
+        report.exported,
+        report.failed.len()
+    );
+
+    Ok(())
+}
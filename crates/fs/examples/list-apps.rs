@@ -4,19 +4,25 @@ use anyhow::Context;
 use gwynn_fs::sources::mumuplayer::MumuPlayer;
 
 fn main() -> anyhow::Result<()> {
-    match MumuPlayer::iter_vms() {
+    match MumuPlayer::list_vms() {
         Ok(vms) => {
             for vm in vms {
-                println!("Found MuMuPlayer VM: {}", vm.display());
-                let fs = MumuPlayer::open_vm_ext4(&vm)
-                    .context("failed to open ext4 filesystem")?
-                    .expect("image does not contain an ext4 filesystem");
-                let apps = gwynn_fs::apk::scan_for_apps(&fs).context("Failed to scan for apps")?;
-                for app in apps {
+                println!(
+                    "Found MuMuPlayer VM: {} (Android {}, {}MB)",
+                    vm.path.display(),
+                    vm.read_android_version().as_deref().unwrap_or("unknown"),
+                    vm.data_size / 1_000_000
+                );
+                let fs = vm.open_ext4().context("failed to open ext4 filesystem")?;
+                let report = gwynn_fs::apk::scan_for_apps(&fs);
+                for app in report.apps {
                     println!("  Found installed app: {}", app.package_name);
                     println!("    Split configs: {:?}", app.split_configs);
                     println!("    Split packs: {:?}", app.split_packs);
                 }
+                for skipped in report.skipped {
+                    println!("  Skipped {}: {}", skipped.path.display(), skipped.reason);
+                }
             }
         }
         Err(e) => {
@@ -1,17 +1,37 @@
 use anyhow::Context;
 
 fn main() -> anyhow::Result<()> {
-    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
-    let apps = gwynn_fs::apk::scan_for_apps(fs.ext4()).context("Failed to scan for apps")?;
+    // Optionally takes a path to a MuMuPlayer VM folder or a disk image, otherwise the selected MuMuPlayer VM is used
+    let fs = match std::env::args().nth(1).map(std::path::PathBuf::from) {
+        Some(path) if path.is_dir() => gwynn_fs::Filesystem::open_mumu_vm(&path),
+        Some(path) => gwynn_fs::Filesystem::open_from_image(&path),
+        None => gwynn_fs::Filesystem::open(),
+    }
+    .context("failed to open filesystem")?;
+    let apps = gwynn_fs::apk::scan_for_apps(fs.ext4()).apps;
+    let profile = fs.profile();
     let app = apps
         .iter()
-        .find(|app| app.package_name == "com.netease.g108na")
-        .context("Destiny: Rising not installed")?;
+        .find(|app| app.package_name == profile.package_name)
+        .with_context(|| format!("{} not installed", profile.name))?;
 
     println!(
-        "Found Destiny: Rising installation at {}",
+        "Found {} installation at {}",
+        profile.name,
         app.path.display()
     );
+    match app.read_metadata(fs.ext4()) {
+        Ok(meta) => {
+            println!(
+                "Version: {} ({})",
+                meta.manifest.version_name.as_deref().unwrap_or("unknown"),
+                meta.manifest.version_code
+            );
+            println!("ABIs: {}", meta.abis.join(", "));
+        }
+        Err(e) => println!("Failed to read app metadata: {e}"),
+    }
+    println!("Storage root: {}", fs.storage_root().display());
     println!("Split packs:");
     for config in &app.split_packs {
         let meta = fs.ext4().metadata(app.split_pack_path(config)).unwrap();
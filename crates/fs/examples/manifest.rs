@@ -0,0 +1,32 @@
+use anyhow::Context;
+use gwynn_fs::{
+    manifest::ManifestOptions,
+    query::{Query, SortBy},
+};
+
+fn main() -> anyhow::Result<()> {
+    // Usage: manifest <output .csv/.json> [glob pattern] [--index-only]
+    // Texture dimensions and formats need the `texture` feature
+    let mut args = std::env::args().skip(1);
+    let output = std::path::PathBuf::from(args.next().context("Missing output path")?);
+    let pattern = args.next().unwrap_or_else(|| "**".to_string());
+    let index_only = args.next().is_some_and(|a| a == "--index-only");
+
+    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
+    let matches = fs.query(&Query::glob(&pattern)?.with_sort(SortBy::Path, false));
+    println!("Building manifest of {} files", matches.len());
+
+    let options = if index_only {
+        ManifestOptions {
+            providers: &[],
+            read_headers: false,
+        }
+    } else {
+        ManifestOptions::default()
+    };
+    let entries = fs.manifest(matches.iter().map(|m| m.path), &options)?;
+    gwynn_fs::manifest::write_to_file(&entries, &output)?;
+    println!("Wrote {}", output.display());
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use gwynn_fs::mount::SyntheticView;
+#[cfg(feature = "texture")]
+use gwynn_fs::texture::{TextureConverter, TextureOutput};
+
+fn main() -> anyhow::Result<()> {
+    // Usage: mount <mountpoint> [--png]
+    // With the `texture` feature, --png shows a PNG copy next to every texture
+    let mut args = std::env::args().skip(1);
+    let mountpoint = std::path::PathBuf::from(args.next().context("Missing mountpoint directory")?);
+    let png = args.next().is_some_and(|a| a == "--png");
+
+    let views = if png { vec![png_view()?] } else { vec![] };
+
+    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
+    println!(
+        "Mounting {} files at {}, unmount with `fusermount -u`",
+        fs.iter_types().map(|(_, paths)| paths.len()).sum::<usize>(),
+        mountpoint.display()
+    );
+    gwynn_fs::mount::mount(Arc::new(fs), &mountpoint, views)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "texture")]
+fn png_view() -> anyhow::Result<Box<dyn SyntheticView>> {
+    Ok(Box::new(TextureConverter::new(TextureOutput::Image(
+        gwynn_texture::export::ImageFormat::Png,
+    ))))
+}
+
+#[cfg(not(feature = "texture"))]
+fn png_view() -> anyhow::Result<Box<dyn SyntheticView>> {
+    anyhow::bail!("Converting textures needs the `texture` feature")
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Context;
+
+/// Reads the same files on one thread and on several threads at once, and checks that the results match
+fn main() -> anyhow::Result<()> {
+    let count: usize = match std::env::args().nth(1) {
+        Some(count) => count.parse().context("Invalid file count")?,
+        None => 512,
+    };
+    let thread_count = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
+    let paths: Vec<_> = fs.iter_paths().take(count).collect();
+
+    let start = std::time::Instant::now();
+    let sequential = paths
+        .iter()
+        .map(|path| fs.read_path_raw(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    println!(
+        "Read {} files on 1 thread in {:?}",
+        paths.len(),
+        start.elapsed()
+    );
+
+    let start = std::time::Instant::now();
+    let next = AtomicUsize::new(0);
+    let mismatches = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..thread_count {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else {
+                        break;
+                    };
+
+                    match fs.read_path_raw(path) {
+                        Ok(data) if data == sequential[i] => {}
+                        Ok(_) => {
+                            eprintln!("{} differs between reads", path.display());
+                            mismatches.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to read {}: {e}", path.display());
+                            mismatches.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    println!(
+        "Read {} files on {thread_count} threads in {:?}",
+        paths.len(),
+        start.elapsed()
+    );
+
+    let mismatches = mismatches.into_inner();
+    anyhow::ensure!(mismatches == 0, "{mismatches} files did not match");
+    println!("All files match");
+
+    Ok(())
+}
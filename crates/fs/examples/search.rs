@@ -0,0 +1,27 @@
+use anyhow::Context;
+use gwynn_fs::query::{Query, SortBy};
+
+fn main() -> anyhow::Result<()> {
+    // Usage: search <glob pattern> [min size in bytes]
+    let mut args = std::env::args().skip(1);
+    let pattern = args.next().context("Missing glob pattern")?;
+    let mut query = Query::glob(&pattern)?.with_sort(SortBy::Path, false);
+    if let Some(min_size) = args.next() {
+        query = query.with_min_size(min_size.parse().context("Invalid minimum size")?);
+    }
+
+    let fs = gwynn_fs::Filesystem::open().context("failed to open filesystem")?;
+    let matches = fs.query(&query);
+    for m in &matches {
+        println!(
+            "{} ({:?}, {} bytes, patch {:?})",
+            m.path.display(),
+            m.file_type,
+            m.size,
+            m.patch_index
+        );
+    }
+    println!("{} files found", matches.len());
+
+    Ok(())
+}
@@ -1,14 +1,45 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    io::{BufReader, Read, Seek},
+};
 
+use anyhow::Context;
 use base64::Engine;
 use bootsector::pio::ReadAt;
 use ext4::Ext4Reader;
-use log::error;
+use log::{error, warn};
+use unix_path::Path as UnixPath;
 
+use crate::{
+    axml,
+    error::{FsError, Result},
+};
+
+// Resource ids of the framework attributes we read from manifests
+const ATTR_VERSION_CODE: u32 = 0x0101_021B;
+const ATTR_VERSION_NAME: u32 = 0x0101_021C;
+const ATTR_MIN_SDK_VERSION: u32 = 0x0101_020C;
+const ATTR_TARGET_SDK_VERSION: u32 = 0x0101_0270;
+const ATTR_VERSION_CODE_MAJOR: u32 = 0x0101_0576;
+const ATTR_IS_SPLIT_REQUIRED: u32 = 0x0101_0591;
+
+/// ABIs as they appear in `lib/<abi>/`, split config names use underscores instead of dashes
+const KNOWN_ABIS: &[&str] = &[
+    "armeabi",
+    "armeabi-v7a",
+    "arm64-v8a",
+    "x86",
+    "x86_64",
+    "mips",
+    "mips64",
+];
+
+#[derive(Debug, Clone)]
 pub struct InstalledApp {
     pub package_name: String,
     pub path: unix_path::PathBuf,
-    pub signature: [u8; 16],
+    /// Signature from the directory name, not present in the legacy `<package>-<number>` layout
+    pub signature: Option<[u8; 16]>,
 
     pub split_configs: HashSet<String>,
     pub split_packs: HashSet<String>,
@@ -26,79 +57,473 @@ impl InstalledApp {
     pub fn split_pack_path(&self, pack: &str) -> unix_path::PathBuf {
         self.path.join(format!("{}.apk", pack))
     }
-}
 
-pub fn scan_for_apps<R: ReadAt>(fs: &Ext4Reader<R>) -> anyhow::Result<Vec<InstalledApp>> {
-    let mut app_dirs: Vec<ext4::structs::DirectoryEntry> = vec![];
-    let mut add_dir = |path: &str| {
-        if let Ok(entries) = fs.read_dir(path) {
-            app_dirs.extend(entries);
+    /// Reads the manifests of the base and split APKs
+    pub fn read_metadata<R: ReadAt>(&self, fs: &Ext4Reader<R>) -> Result<AppMetadata> {
+        let base_path = self.base_apk_path();
+        let mut apk = open_apk(fs, &base_path)?;
+        let manifest =
+            read_manifest_from_archive(&mut apk).map_err(|source| FsError::InvalidApk {
+                path: base_path.clone(),
+                source,
+            })?;
+
+        let mut abis: Vec<String> = apk
+            .file_names()
+            .filter_map(|name| name.strip_prefix("lib/")?.split('/').next())
+            .filter(|abi| KNOWN_ABIS.contains(abi))
+            .map(str::to_string)
+            .collect();
+        abis.extend(
+            self.split_configs
+                .iter()
+                .map(|config| config.replace('_', "-"))
+                .filter(|abi| KNOWN_ABIS.contains(&abi.as_str())),
+        );
+        abis.sort();
+        abis.dedup();
+
+        let split_paths = self
+            .split_configs
+            .iter()
+            .map(|config| self.split_config_path(config))
+            .chain(
+                self.split_packs
+                    .iter()
+                    .map(|pack| self.split_pack_path(pack)),
+            );
+        let mut splits = vec![];
+        for path in split_paths {
+            match read_manifest(fs, &path) {
+                Ok(split_manifest) => splits.extend(split_manifest.split),
+                Err(e) => warn!("Failed to read split manifest {}: {e}", path.display()),
+            }
         }
-    };
-    add_dir("/data/app");
-    add_dir("/app");
+        splits.sort();
+
+        Ok(AppMetadata {
+            manifest,
+            abis,
+            splits,
+        })
+    }
+}
 
-    let mut apps = Vec::new();
-    for session_dir in app_dirs {
-        if !session_dir.is_dir || !session_dir.name.starts_with("~~") {
+/// Metadata from an APK's `AndroidManifest.xml`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApkManifest {
+    pub package_name: String,
+    /// Full version code, including `versionCodeMajor` in the upper 32 bits
+    pub version_code: u64,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub target_sdk: Option<u32>,
+    /// Name of the split, only set for split APKs
+    pub split: Option<String>,
+    /// Whether the app refuses to run without its split APKs
+    pub is_split_required: bool,
+}
+
+impl ApkManifest {
+    /// Parses a binary `AndroidManifest.xml`
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let elements = axml::parse_elements(data)?;
+        let manifest = elements
+            .iter()
+            .find(|e| e.name == "manifest")
+            .context("Manifest has no <manifest> element")?;
+
+        let version_code = manifest
+            .attribute(ATTR_VERSION_CODE, "versionCode")
+            .and_then(|v| v.as_int())
+            .unwrap_or_default() as u64;
+        let version_code_major = manifest
+            .attribute(ATTR_VERSION_CODE_MAJOR, "versionCodeMajor")
+            .and_then(|v| v.as_int())
+            .unwrap_or_default() as u64;
+
+        let uses_sdk = elements.iter().find(|e| e.name == "uses-sdk");
+        let sdk_version = |id, name| {
+            uses_sdk
+                .and_then(|e| e.attribute(id, name))
+                .and_then(|v| v.as_int())
+        };
+
+        Ok(Self {
+            package_name: manifest
+                .attributes
+                .iter()
+                .find(|a| a.name == "package")
+                .and_then(|a| a.value.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            version_code: (version_code_major << 32) | version_code,
+            version_name: manifest
+                .attribute(ATTR_VERSION_NAME, "versionName")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            min_sdk: sdk_version(ATTR_MIN_SDK_VERSION, "minSdkVersion"),
+            target_sdk: sdk_version(ATTR_TARGET_SDK_VERSION, "targetSdkVersion"),
+            split: manifest
+                .attributes
+                .iter()
+                .find(|a| a.name == "split")
+                .and_then(|a| a.value.as_str())
+                .map(str::to_string),
+            is_split_required: manifest
+                .attribute(ATTR_IS_SPLIT_REQUIRED, "isSplitRequired")
+                .and_then(|v| v.as_bool())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppMetadata {
+    /// Manifest of `base.apk`
+    pub manifest: ApkManifest,
+    /// Native ABIs, from `lib/<abi>/` in the base APK and ABI config splits
+    pub abis: Vec<String>,
+    /// Split names declared by the manifests of the installed split APKs
+    pub splits: Vec<String>,
+}
+
+/// Reads the manifest of the APK at the given path
+pub fn read_manifest<R: ReadAt>(fs: &Ext4Reader<R>, path: &UnixPath) -> Result<ApkManifest> {
+    let mut apk = open_apk(fs, path)?;
+    read_manifest_from_archive(&mut apk).map_err(|source| FsError::InvalidApk {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn open_apk<'a, R: ReadAt>(
+    fs: &'a Ext4Reader<R>,
+    path: &UnixPath,
+) -> Result<zip::ZipArchive<BufReader<impl Read + Seek + 'a>>> {
+    let file = fs.open(path).map_err(FsError::ext4)?;
+    zip::ZipArchive::new(BufReader::new(file)).map_err(|e| FsError::InvalidApk {
+        path: path.to_path_buf(),
+        source: e.into(),
+    })
+}
+
+fn read_manifest_from_archive<T: Read + Seek>(
+    apk: &mut zip::ZipArchive<T>,
+) -> anyhow::Result<ApkManifest> {
+    let mut data = vec![];
+    apk.by_name("AndroidManifest.xml")?.read_to_end(&mut data)?;
+    ApkManifest::parse(&data)
+}
+
+/// Directories containing installed apps
+const APP_BASES: &[&str] = &["/data/app", "/app"];
+
+/// Result of [`scan_for_apps`]
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub apps: Vec<InstalledApp>,
+    /// Directories that looked like app directories but couldn't be read or parsed
+    pub skipped: Vec<SkippedDir>,
+}
+
+#[derive(Debug)]
+pub struct SkippedDir {
+    pub path: unix_path::PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SkipReason {
+    #[error("Failed to read directory")]
+    Unreadable(#[source] FsError),
+    #[error("Directory name is not in the <package>-<suffix> format")]
+    InvalidName,
+    #[error("Invalid signature '{0}'")]
+    InvalidSignature(String),
+    #[error("Directory does not contain a base.apk")]
+    MissingBaseApk,
+}
+
+/// Finds all installed apps, skipping directories that can't be parsed.
+///
+/// Supports both the `/data/app/~~<random>/<package>-<signature>` layout used since Android 11,
+/// and the `/data/app/<package>-<signature or number>` layout used by older versions.
+pub fn scan_for_apps<R: ReadAt>(fs: &Ext4Reader<R>) -> ScanReport {
+    let mut report = ScanReport::default();
+    for base in APP_BASES {
+        if !fs.exists(base) {
             continue;
         }
 
-        for package_dir in fs.read_dir(&session_dir.path)? {
-            if !package_dir.is_dir {
-                continue;
-            }
-
-            let parts = package_dir.name.split("-").collect::<Vec<_>>();
-            if parts.len() != 2 {
-                error!("Invalid app dir name: {}", package_dir.name);
-                continue;
-            }
+        let Some(entries) = read_dir(fs, UnixPath::new(base), &mut report) else {
+            continue;
+        };
 
-            let package_name = parts[0].to_string();
-            let signature = base64::prelude::BASE64_URL_SAFE.decode(parts[1])?;
-            if signature.len() != 16 {
-                error!(
-                    "Invalid signature length for app {}: {}",
-                    package_name,
-                    signature.len()
-                );
+        for entry in entries {
+            if !entry.is_dir {
                 continue;
             }
 
-            let signature: [u8; 16] = signature
-                .try_into()
-                .expect("unreachable: length is checked");
-
-            let mut split_configs = HashSet::new();
-            let mut split_packs = HashSet::new();
-            for entry in fs.read_dir(&package_dir.path)? {
-                if entry.is_file
-                    && entry.name.starts_with("split_config.")
-                    && entry.name.ends_with(".apk")
-                {
-                    let config = entry.name["split_config.".len()..entry.name.len() - ".apk".len()]
-                        .to_string();
-                    split_configs.insert(config);
-                }
+            if entry.name.starts_with("~~") {
+                let Some(package_dirs) = read_dir(fs, &entry.path, &mut report) else {
+                    continue;
+                };
 
-                if entry.is_file
-                    && entry.name.starts_with("split_pack")
-                    && entry.name.ends_with(".apk")
-                {
-                    split_packs.insert(entry.name.trim_end_matches(".apk").to_string());
+                for package_dir in package_dirs.into_iter().filter(|e| e.is_dir) {
+                    scan_package_dir(fs, package_dir, &mut report);
                 }
+            } else {
+                scan_package_dir(fs, entry, &mut report);
             }
+        }
+    }
+
+    for skipped in &report.skipped {
+        error!(
+            "Skipping app directory {}: {}",
+            skipped.path.display(),
+            skipped.reason
+        );
+    }
 
-            apps.push(InstalledApp {
-                package_name,
-                path: package_dir.path,
-                signature,
-                split_configs,
-                split_packs,
+    report
+}
+
+fn read_dir<R: ReadAt>(
+    fs: &Ext4Reader<R>,
+    path: &UnixPath,
+    report: &mut ScanReport,
+) -> Option<Vec<ext4::structs::DirectoryEntry>> {
+    match fs.read_dir(path) {
+        Ok(entries) => Some(entries),
+        Err(e) => {
+            report.skipped.push(SkippedDir {
+                path: path.to_path_buf(),
+                reason: SkipReason::Unreadable(FsError::ext4(e)),
             });
+            None
         }
     }
+}
+
+fn scan_package_dir<R: ReadAt>(
+    fs: &Ext4Reader<R>,
+    package_dir: ext4::structs::DirectoryEntry,
+    report: &mut ScanReport,
+) {
+    let mut skip = |reason| {
+        report.skipped.push(SkippedDir {
+            path: package_dir.path.clone(),
+            reason,
+        })
+    };
+
+    // Package names can't contain dashes, but the URL-safe base64 signature can
+    let Some((package_name, suffix)) = package_dir.name.split_once('-') else {
+        return skip(SkipReason::InvalidName);
+    };
+
+    // Older versions use a plain install counter (`<package>-1`) instead of a signature
+    let signature = if suffix.chars().all(|c| c.is_ascii_digit()) {
+        None
+    } else {
+        match base64::prelude::BASE64_URL_SAFE
+            .decode(suffix)
+            .ok()
+            .and_then(|s| <[u8; 16]>::try_from(s).ok())
+        {
+            Some(signature) => Some(signature),
+            None => return skip(SkipReason::InvalidSignature(suffix.to_string())),
+        }
+    };
+
+    let entries = match fs.read_dir(&package_dir.path) {
+        Ok(entries) => entries,
+        Err(e) => return skip(SkipReason::Unreadable(FsError::ext4(e))),
+    };
+    if !entries.iter().any(|e| e.is_file && e.name == "base.apk") {
+        return skip(SkipReason::MissingBaseApk);
+    }
+
+    let mut split_configs = HashSet::new();
+    let mut split_packs = HashSet::new();
+    for entry in entries.iter().filter(|e| e.is_file) {
+        let Some(name) = entry.name.strip_suffix(".apk") else {
+            continue;
+        };
 
-    Ok(apps)
+        if let Some(config) = name.strip_prefix("split_config.") {
+            split_configs.insert(config.to_string());
+        } else if name.starts_with("split_pack") {
+            split_packs.insert(name.to_string());
+        }
+    }
+
+    report.apps.push(InstalledApp {
+        package_name: package_name.to_string(),
+        path: package_dir.path,
+        signature,
+        split_configs,
+        split_packs,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use ext4::Ext4Reader;
+
+    use super::*;
+    use crate::{
+        axml::Value,
+        sources::BlockDevice,
+        testing::{AxmlBuilder, Ext4Builder},
+    };
+
+    #[test]
+    fn parses_manifest() {
+        for utf8 in [true, false] {
+            let data = AxmlBuilder::new(utf8)
+                .element(
+                    "manifest",
+                    &[
+                        ("versionCode", Some(ATTR_VERSION_CODE), Value::Int(123)),
+                        (
+                            "versionName",
+                            Some(ATTR_VERSION_NAME),
+                            Value::String("1.2.3".into()),
+                        ),
+                        (
+                            "versionCodeMajor",
+                            Some(ATTR_VERSION_CODE_MAJOR),
+                            Value::Int(1),
+                        ),
+                        ("package", None, Value::String("com.netease.g108na".into())),
+                        ("split", None, Value::String("config.arm64_v8a".into())),
+                        // Obfuscated attribute name, only the resource id is left
+                        ("", Some(ATTR_IS_SPLIT_REQUIRED), Value::Bool(true)),
+                    ],
+                )
+                .element(
+                    "uses-sdk",
+                    &[
+                        ("minSdkVersion", Some(ATTR_MIN_SDK_VERSION), Value::Int(24)),
+                        // Some tools write numeric attributes as strings
+                        (
+                            "targetSdkVersion",
+                            Some(ATTR_TARGET_SDK_VERSION),
+                            Value::String("34".into()),
+                        ),
+                    ],
+                )
+                .build();
+
+            assert_eq!(
+                ApkManifest::parse(&data).unwrap(),
+                ApkManifest {
+                    package_name: "com.netease.g108na".into(),
+                    version_code: (1 << 32) | 123,
+                    version_name: Some("1.2.3".into()),
+                    min_sdk: Some(24),
+                    target_sdk: Some(34),
+                    split: Some("config.arm64_v8a".into()),
+                    is_split_required: true,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn manifest_without_optional_attributes() {
+        let data = AxmlBuilder::new(true)
+            .element(
+                "manifest",
+                &[("package", None, Value::String("com.example".into()))],
+            )
+            .build();
+        assert_eq!(
+            ApkManifest::parse(&data).unwrap(),
+            ApkManifest {
+                package_name: "com.example".into(),
+                ..Default::default()
+            }
+        );
+
+        let data = AxmlBuilder::new(true).element("application", &[]).build();
+        assert!(ApkManifest::parse(&data).is_err());
+    }
+
+    #[test]
+    fn scans_app_directories() {
+        let signature: [u8; 16] = std::array::from_fn(|i| i as u8 * 17);
+        let encoded = base64::prelude::BASE64_URL_SAFE.encode(signature);
+        let modern = format!("/data/app/~~Xk3hfQ2mZpLw9vBn==/com.netease.g108na-{encoded}");
+        let disk = Ext4Builder::default()
+            .file(&format!("{modern}/base.apk"), b"apk")
+            .file(&format!("{modern}/split_config.arm64_v8a.apk"), b"apk")
+            .file(&format!("{modern}/split_pack_textures.apk"), b"apk")
+            .file(&format!("{modern}/lib/arm64/libgame.so"), b"so")
+            .file(
+                "/data/app/~~Xk3hfQ2mZpLw9vBn==/com.example.bad-AAAA/base.apk",
+                b"apk",
+            )
+            .file("/data/app/com.example.legacy-2/base.apk", b"apk")
+            .file(
+                &format!("/data/app/com.example.nobase-{encoded}/oat/arm64/base.odex"),
+                b"odex",
+            )
+            .file("/data/app/unnamed/base.apk", b"apk")
+            .file("/data/app/packages.list", b"")
+            .build();
+        let fs = Ext4Reader::new(BlockDevice::new(disk)).unwrap();
+
+        let mut report = scan_for_apps(&fs);
+        report.apps.sort_by(|a, b| a.path.cmp(&b.path));
+        let apps: Vec<_> = report
+            .apps
+            .iter()
+            .map(|app| {
+                (
+                    app.package_name.as_str(),
+                    app.path.to_str().unwrap(),
+                    app.signature,
+                )
+            })
+            .collect();
+        assert_eq!(
+            apps,
+            [
+                ("com.example.legacy", "/data/app/com.example.legacy-2", None),
+                ("com.netease.g108na", modern.as_str(), Some(signature)),
+            ]
+        );
+        let app = &report.apps[1];
+        assert_eq!(app.split_configs, HashSet::from(["arm64_v8a".to_string()]));
+        assert_eq!(
+            app.split_packs,
+            HashSet::from(["split_pack_textures".to_string()])
+        );
+
+        report.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        let skipped: Vec<_> = report
+            .skipped
+            .iter()
+            .map(|s| (s.path.to_str().unwrap(), &s.reason))
+            .collect();
+        assert_eq!(skipped.len(), 3, "{skipped:?}");
+        assert_eq!(
+            skipped[0].0,
+            format!("/data/app/com.example.nobase-{encoded}")
+        );
+        assert!(matches!(skipped[0].1, SkipReason::MissingBaseApk));
+        assert!(matches!(
+            skipped[1],
+            ("/data/app/unnamed", SkipReason::InvalidName)
+        ));
+        assert!(matches!(
+            skipped[2],
+            ("/data/app/~~Xk3hfQ2mZpLw9vBn==/com.example.bad-AAAA", SkipReason::InvalidSignature(s))
+                if s == "AAAA"
+        ));
+    }
 }
@@ -0,0 +1,373 @@
+//! Parser for Android binary XML, as used by `AndroidManifest.xml` inside APKs
+
+use std::io::{Cursor, Seek, SeekFrom};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const STRING_POOL_UTF8: u32 = 1 << 8;
+const NO_STRING: u32 = 0xFFFF_FFFF;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone, Copy)]
+struct ChunkHeader {
+    chunk_type: u16,
+    header_size: u16,
+    size: u32,
+}
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone, Copy)]
+struct StringPoolHeader {
+    string_count: u32,
+    _style_count: u32,
+    flags: u32,
+    strings_start: u32,
+    _styles_start: u32,
+}
+
+/// `ResXMLTree_attrExt`, following the node header of start element chunks
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone, Copy)]
+struct StartElementHeader {
+    _namespace: u32,
+    name: u32,
+    attribute_start: u16,
+    attribute_size: u16,
+    attribute_count: u16,
+}
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone, Copy)]
+struct RawAttribute {
+    _namespace: u32,
+    name: u32,
+    raw_value: u32,
+    _value_size: u16,
+    _res0: u8,
+    data_type: u8,
+    data: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Int(u32),
+    Bool(bool),
+    /// Any other typed value (references, dimensions, ...), as its raw type and data
+    Other {
+        data_type: u8,
+        data: u32,
+    },
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<u32> {
+        match self {
+            Value::Int(v) => Some(*v),
+            // Some tools write numeric attributes as strings
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    /// Android resource id of the attribute (eg. `0x0101021b` for `android:versionCode`), if known
+    pub resource_id: Option<u32>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Element {
+    /// Finds an attribute by resource id, falling back to its name.
+    ///
+    /// Obfuscated APKs often strip attribute names, but the resource ids are always present for framework attributes.
+    pub fn attribute(&self, resource_id: u32, name: &str) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|a| a.resource_id == Some(resource_id))
+            .or_else(|| self.attributes.iter().find(|a| a.name == name))
+            .map(|a| &a.value)
+    }
+}
+
+/// Parses the start elements of a binary XML document in document order.
+///
+/// Nesting isn't tracked, which is enough for reading manifests.
+pub fn parse_elements(data: &[u8]) -> anyhow::Result<Vec<Element>> {
+    let mut cursor = Cursor::new(data);
+    let header: ChunkHeader = cursor.read_le()?;
+    anyhow::ensure!(
+        header.chunk_type == RES_XML_TYPE,
+        "Not a binary XML document"
+    );
+
+    let end = (header.size as u64).min(data.len() as u64);
+    let mut strings = vec![];
+    let mut resource_ids = vec![];
+    let mut elements = vec![];
+
+    let mut offset = header.header_size as u64;
+    while offset + 8 <= end {
+        cursor.seek(SeekFrom::Start(offset))?;
+        let chunk: ChunkHeader = cursor.read_le()?;
+        anyhow::ensure!(chunk.size >= 8, "Invalid chunk size at {offset:#x}");
+        let body = offset + chunk.header_size as u64;
+
+        match chunk.chunk_type {
+            RES_STRING_POOL_TYPE => {
+                strings =
+                    read_string_pool(data, offset, chunk).context("Failed to read string pool")?;
+            }
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = data
+                    .get(body as usize..(offset + chunk.size as u64) as usize)
+                    .context("Resource map out of bounds")?
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                    .collect();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                cursor.seek(SeekFrom::Start(body))?;
+                let element: StartElementHeader = cursor.read_le()?;
+                let attributes_offset = body + element.attribute_start as u64;
+
+                let mut attributes = vec![];
+                for i in 0..element.attribute_count as u64 {
+                    cursor.seek(SeekFrom::Start(
+                        attributes_offset + i * element.attribute_size as u64,
+                    ))?;
+                    let raw: RawAttribute = cursor.read_le()?;
+                    let value = match raw.data_type {
+                        TYPE_STRING => Value::String(get_string(&strings, raw.data)),
+                        TYPE_INT_DEC | TYPE_INT_HEX => Value::Int(raw.data),
+                        TYPE_INT_BOOLEAN => Value::Bool(raw.data != 0),
+                        _ if raw.raw_value != NO_STRING => {
+                            Value::String(get_string(&strings, raw.raw_value))
+                        }
+                        data_type => Value::Other {
+                            data_type,
+                            data: raw.data,
+                        },
+                    };
+
+                    attributes.push(Attribute {
+                        name: get_string(&strings, raw.name),
+                        resource_id: resource_ids.get(raw.name as usize).copied(),
+                        value,
+                    });
+                }
+
+                elements.push(Element {
+                    name: get_string(&strings, element.name),
+                    attributes,
+                });
+            }
+            _ => {}
+        }
+
+        offset += chunk.size as u64;
+    }
+
+    Ok(elements)
+}
+
+fn get_string(strings: &[String], index: u32) -> String {
+    strings.get(index as usize).cloned().unwrap_or_default()
+}
+
+fn read_string_pool(data: &[u8], offset: u64, chunk: ChunkHeader) -> anyhow::Result<Vec<String>> {
+    let mut cursor = Cursor::new(data);
+    cursor.seek(SeekFrom::Start(offset + 8))?;
+    let header: StringPoolHeader = cursor.read_le()?;
+
+    let utf8 = header.flags & STRING_POOL_UTF8 != 0;
+    let strings_start = (offset + header.strings_start as u64) as usize;
+    let chunk_end = ((offset + chunk.size as u64) as usize).min(data.len());
+    let offsets_start = (offset + chunk.header_size as u64) as usize;
+
+    // Every string has a 4 byte offset, which bounds the count to what fits in the chunk
+    let string_count = header.string_count as usize;
+    let mut strings =
+        Vec::with_capacity(string_count.min(chunk_end.saturating_sub(offsets_start) / 4));
+    for i in 0..string_count {
+        let entry = offsets_start + i * 4;
+        let string_offset = data[..chunk_end]
+            .get(entry..entry + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+            .context("String offset out of bounds")?;
+        let string_data = data
+            .get(strings_start + string_offset..chunk_end)
+            .context("String out of bounds")?;
+
+        let string = if utf8 {
+            read_utf8_string(string_data)
+        } else {
+            read_utf16_string(string_data)
+        };
+        strings.push(string.unwrap_or_default());
+    }
+
+    Ok(strings)
+}
+
+/// UTF-8 strings are prefixed by their length in UTF-16 code units and in bytes, each 1 or 2 bytes long
+fn read_utf8_string(data: &[u8]) -> Option<String> {
+    let read_length = |data: &[u8]| -> Option<(usize, usize)> {
+        let first = *data.first()? as usize;
+        if first & 0x80 != 0 {
+            Some((((first & 0x7F) << 8) | *data.get(1)? as usize, 2))
+        } else {
+            Some((first, 1))
+        }
+    };
+
+    let (_, skip) = read_length(data)?;
+    let (len, skip2) = read_length(&data[skip..])?;
+    let start = skip + skip2;
+    let bytes = data.get(start..start + len)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// UTF-16 strings are prefixed by their length in code units, either 1 or 2 u16s long
+fn read_utf16_string(data: &[u8]) -> Option<String> {
+    let read_u16 = |i: usize| -> Option<u16> {
+        Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().unwrap()))
+    };
+
+    let first = read_u16(0)? as usize;
+    let (len, start) = if first & 0x8000 != 0 {
+        (((first & 0x7FFF) << 16) | read_u16(2)? as usize, 4)
+    } else {
+        (first, 2)
+    };
+
+    let units = (0..len)
+        .map(|i| read_u16(start + i * 2))
+        .collect::<Option<Vec<_>>>()?;
+    Some(String::from_utf16_lossy(&units))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::AxmlBuilder;
+
+    fn document(utf8: bool) -> Vec<u8> {
+        AxmlBuilder::new(utf8)
+            .element(
+                "manifest",
+                &[
+                    ("versionCode", Some(0x0101_021B), Value::Int(42)),
+                    ("package", None, Value::String("com.example.gäme".into())),
+                ],
+            )
+            .element(
+                "application",
+                &[
+                    ("debuggable", Some(0x0101_000F), Value::Bool(true)),
+                    (
+                        "icon",
+                        Some(0x0101_0002),
+                        Value::Other {
+                            data_type: 0x01,
+                            data: 0x7F08_0000,
+                        },
+                    ),
+                ],
+            )
+            .build()
+    }
+
+    #[test]
+    fn parses_utf8_and_utf16_string_pools() {
+        for utf8 in [true, false] {
+            let elements = parse_elements(&document(utf8)).unwrap();
+            assert_eq!(elements.len(), 2);
+
+            let manifest = &elements[0];
+            assert_eq!(manifest.name, "manifest");
+            assert_eq!(manifest.attributes[0].resource_id, Some(0x0101_021B));
+            assert_eq!(manifest.attributes[1].resource_id, None);
+            assert_eq!(
+                manifest.attribute(0x0101_021B, "versionCode"),
+                Some(&Value::Int(42))
+            );
+            assert_eq!(
+                manifest.attribute(0, "package").and_then(Value::as_str),
+                Some("com.example.gäme")
+            );
+
+            let application = &elements[1];
+            assert_eq!(application.name, "application");
+            assert_eq!(
+                application.attribute(0x0101_000F, "debuggable"),
+                Some(&Value::Bool(true))
+            );
+            assert_eq!(
+                application.attributes[1].value,
+                Value::Other {
+                    data_type: 0x01,
+                    data: 0x7F08_0000
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn attributes_are_found_by_resource_id_without_names() {
+        // Obfuscated manifests keep the resource ids but empty the names
+        let data = AxmlBuilder::new(true)
+            .element("manifest", &[("", Some(0x0101_021B), Value::Int(7))])
+            .build();
+        let elements = parse_elements(&data).unwrap();
+        assert_eq!(
+            elements[0].attribute(0x0101_021B, "versionCode"),
+            Some(&Value::Int(7))
+        );
+    }
+
+    #[test]
+    fn rejects_huge_string_counts() {
+        let mut data = document(true);
+        // The string count follows the headers of the document and string pool
+        data[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_elements(&data).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! On-disk cache of the patch index.
+//!
+//! Parsing every `Patch*.mpkinfo` on a large image is slow, so the parsed entries are stored per patch file
+//! together with the size and checksum of the mpkinfo they came from. On the next start only patches whose
+//! mpkinfo changed are parsed again.
+
+use std::{
+    collections::HashMap,
+    io::{BufReader, BufWriter, Cursor},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, BinWriterExt, binrw};
+use unix_path::Path as UnixPath;
+
+use crate::{filetype::FileType, profile::GameProfile};
+
+/// Overrides the directory the index cache is stored in
+pub const CACHE_DIR_ENV: &str = "GWYNN_CACHE_DIR";
+
+/// Bump when the layout of the cache (or the `FileType` discriminants) change
+pub const CACHE_VERSION: u32 = 3;
+
+#[binrw]
+#[brw(little, magic = b"GWYNNIDX")]
+#[derive(Debug, Clone, Default)]
+pub struct IndexCache {
+    #[br(temp, assert(version == CACHE_VERSION, "Unsupported index cache version {}", version))]
+    #[bw(calc = CACHE_VERSION)]
+    version: u32,
+    #[br(temp)]
+    #[bw(calc = patches.len() as u32)]
+    patch_count: u32,
+    #[br(count = patch_count)]
+    pub patches: Vec<CachedPatch>,
+}
+
+/// Parsed entries of a single mpkinfo file
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone)]
+pub struct CachedPatch {
+    /// File name of the mpkinfo, eg. `Patch1.mpkinfo`
+    #[br(temp)]
+    #[bw(calc = name.len() as u16)]
+    name_len: u16,
+    #[br(count = name_len, try_map = String::from_utf8)]
+    #[bw(map = |s: &String| s.as_bytes().to_vec())]
+    pub name: String,
+    /// Size of the mpkinfo in bytes
+    pub size: u64,
+    /// CRC32 of the mpkinfo contents
+    pub checksum: u32,
+    #[br(temp)]
+    #[bw(calc = entries.len() as u32)]
+    entry_count: u32,
+    #[br(count = entry_count)]
+    pub entries: Vec<CachedEntry>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone)]
+pub struct CachedEntry {
+    #[br(temp)]
+    #[bw(calc = path.len() as u16)]
+    path_len: u16,
+    #[br(count = path_len, try_map = String::from_utf8)]
+    #[bw(map = |s: &String| s.as_bytes().to_vec())]
+    pub path: String,
+    pub asset_id: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// Content hash from the mpkinfo
+    #[br(temp)]
+    #[bw(calc = hash.len() as u8)]
+    hash_len: u8,
+    #[br(count = hash_len, try_map = String::from_utf8)]
+    #[bw(map = |s: &String| s.as_bytes().to_vec())]
+    pub hash: String,
+    /// Type guessed from the path, or sniffed from the file contents (see `Filesystem::classify_messiah_files`)
+    pub file_type: FileType,
+}
+
+impl IndexCache {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(BufReader::new(file).read_le()?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so an interrupted save doesn't leave a truncated cache behind
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+        writer.write_le(self)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Removes and returns the cached patch with the given name, if its size and checksum still match
+    pub fn take_patch(&mut self, name: &str, size: u64, checksum: u32) -> Option<CachedPatch> {
+        let index = self
+            .patches
+            .iter()
+            .position(|p| p.name == name && p.size == size && p.checksum == checksum)?;
+
+        Some(self.patches.remove(index))
+    }
+
+    /// Updates the type of all entries whose path is in the given map
+    pub fn set_file_types(&mut self, file_types: &HashMap<String, FileType>) {
+        for entry in self.patches.iter_mut().flat_map(|p| p.entries.iter_mut()) {
+            if let Some(file_type) = file_types.get(&entry.path) {
+                entry.file_type = *file_type;
+            }
+        }
+    }
+}
+
+impl CachedPatch {
+    /// Parses the entries of an mpkinfo file, skipping directories
+    pub fn parse(name: &str, data: &[u8]) -> anyhow::Result<Self> {
+        let mut entries = vec![];
+        let mut cursor = Cursor::new(data);
+        loop {
+            let entry = match cursor.read_le::<gwynn_mpk::EntryHeader>() {
+                Ok(o) => o,
+                Err(e) => {
+                    if e.is_eof() {
+                        break;
+                    }
+
+                    return Err(e).with_context(|| format!("Failed to parse {name}"));
+                }
+            };
+
+            if entry.is_directory() {
+                continue;
+            }
+
+            let file_type = FileType::guess_from_path(&entry.path).unwrap_or(FileType::Unknown);
+            entries.push(CachedEntry {
+                path: entry.path,
+                asset_id: entry.asset_id,
+                offset: entry.offset,
+                size: entry.length,
+                hash: entry.hash,
+                file_type,
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            size: data.len() as u64,
+            checksum: crc32fast::hash(data),
+            entries,
+        })
+    }
+}
+
+/// Returns the directory index caches are stored in, if one can be determined
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(CACHE_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+
+    let base = std::env::var_os("LOCALAPPDATA")
+        .or_else(|| std::env::var_os("XDG_CACHE_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    Some(base.join("gwynn"))
+}
+
+/// Returns the cache file for the game data of a profile under the given storage root.
+///
+/// Different disk images with the same game and user share a cache file, the checksums make sure stale entries aren't used.
+pub fn cache_path(profile: &GameProfile, storage_root: &UnixPath) -> Option<PathBuf> {
+    let root = storage_root
+        .to_string_lossy()
+        .trim_matches('/')
+        .replace('/', "_");
+
+    Some(cache_dir()?.join(format!("index-{}-{root}.bin", profile.package_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Ext4Builder, open_filesystem};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gwynn-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trips() {
+        let dir = test_dir("cache-round-trip");
+        let path = dir.join("index.bin");
+        let cache = IndexCache {
+            patches: vec![CachedPatch {
+                name: "Patch1.mpkinfo".into(),
+                size: 1234,
+                checksum: 0xDEAD_BEEF,
+                entries: vec![CachedEntry {
+                    path: "tx/a.1".into(),
+                    asset_id: 42,
+                    offset: 4096,
+                    size: 100,
+                    hash: "0123456789abcdef".into(),
+                    file_type: FileType::Texture,
+                }],
+            }],
+        };
+        cache.save(&path).unwrap();
+
+        let loaded = IndexCache::load(&path).unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{cache:?}"));
+
+        // Caches written by other versions are rejected
+        let mut data = std::fs::read(&path).unwrap();
+        data[8..12].copy_from_slice(&(CACHE_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+        assert!(IndexCache::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reparses_changed_patches_only() {
+        let dir = test_dir("cache-refresh");
+        let path = dir.join("index.bin");
+        let first: &[(&str, &[u8])] = &[("tx/a.json", b"a")];
+
+        open_filesystem(
+            Ext4Builder::default()
+                .patch(0, first)
+                .patch(1, &[("tx/b.json", b"b")])
+                .build(),
+            Some(path.clone()),
+        );
+        let cache = IndexCache::load(&path).unwrap();
+        let names: Vec<_> = cache.patches.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Patch.mpkinfo", "Patch1.mpkinfo"]);
+
+        // Mark the cached entries, so reused ones can be told apart from reparsed ones
+        let mut cache = cache;
+        cache.set_file_types(&HashMap::from([
+            ("tx/a.json".to_string(), FileType::Model),
+            ("tx/b.json".to_string(), FileType::Model),
+        ]));
+        cache.save(&path).unwrap();
+
+        let fs = open_filesystem(
+            Ext4Builder::default()
+                .patch(0, first)
+                .patch(1, &[("tx/c.json", b"c")])
+                .build(),
+            Some(path.clone()),
+        );
+        assert_eq!(fs.file_type("tx/a.json"), Some(FileType::Model));
+        assert_eq!(fs.file_type("tx/b.json"), None);
+        assert_eq!(fs.file_type("tx/c.json"), Some(FileType::Json));
+
+        let cache = IndexCache::load(&path).unwrap();
+        let entries: Vec<_> = cache
+            .patches
+            .iter()
+            .flat_map(|p| p.entries.iter().map(|e| (e.path.as_str(), e.file_type)))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("tx/a.json", FileType::Model),
+                ("tx/c.json", FileType::Json)
+            ]
+        );
+
+        // Removing a patch rewrites the cache without it
+        open_filesystem(
+            Ext4Builder::default().patch(0, first).build(),
+            Some(path.clone()),
+        );
+        let cache = IndexCache::load(&path).unwrap();
+        assert_eq!(cache.patches.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use unix_path::PathBuf as UnixPathBuf;
+use uuid::Uuid;
+
+/// Errors returned by [`Filesystem`](crate::Filesystem), [`MumuPlayer`](crate::sources::mumuplayer::MumuPlayer)
+/// and reported by [`scan_for_apps`](crate::apk::scan_for_apps)
+#[derive(Debug, thiserror::Error)]
+pub enum FsError {
+    /// The MuMuPlayer install directory couldn't be found
+    #[error("MuMuPlayer installation not found")]
+    MumuNotInstalled(#[source] anyhow::Error),
+    #[error("No MuMuPlayer VMs found")]
+    NoVms,
+    #[error("MuMuPlayer VM not found: {}", .0.display())]
+    VmNotFound(PathBuf),
+    /// The disk image couldn't be opened, or doesn't contain an ext4 filesystem
+    #[error("Failed to open disk image {}", path.display())]
+    DiskImage {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Reading from the ext4 filesystem failed, it may be corrupt
+    #[error("Failed to read ext4 filesystem")]
+    Ext4(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// An APK or its `AndroidManifest.xml` couldn't be read
+    #[error("Invalid APK {}", path.display())]
+    InvalidApk {
+        path: UnixPathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// None of the user storage roots contain data for the game
+    #[error("No game data found for {0}")]
+    GameDataNotFound(String),
+    /// The patch index (`Patch*.mpkinfo`) couldn't be parsed
+    #[error("Invalid patch index {}", path.display())]
+    InvalidIndex {
+        path: UnixPathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("Path not in index: {}", .0.display())]
+    PathNotFound(UnixPathBuf),
+    #[error("No file found for UUID {0}")]
+    UuidNotFound(Uuid),
+    #[error("Failed to decompress {}", path.display())]
+    Decompression {
+        path: UnixPathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Files in resource packages are indexed, but can't be read yet
+    #[error("Reading from resource packages is not supported")]
+    UnsupportedResourcePackage,
+    /// The format of an output file couldn't be determined from its extension
+    #[error("Unknown output format for {}, expected {expected}", path.display())]
+    UnknownOutputFormat {
+        path: PathBuf,
+        /// Supported extensions
+        expected: &'static str,
+    },
+    /// A file couldn't be read or converted for exporting
+    #[error("Failed to export {}", path.display())]
+    Export {
+        path: UnixPathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Writing the export archive failed
+    #[error("Failed to write archive")]
+    Archive(#[source] anyhow::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl FsError {
+    pub(crate) fn ext4<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Self {
+        Self::Ext4(error.into())
+    }
+}
+
+pub type Result<T, E = FsError> = std::result::Result<T, E>;
@@ -0,0 +1,415 @@
+//! Exporting files from a [`Filesystem`] into tar, tar.zst or zip archives.
+//!
+//! Files are read one at a time and written straight into the archive, nothing is staged on disk.
+
+use std::{
+    io::{Seek, Write},
+    path::Path,
+};
+
+use log::warn;
+use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
+
+use crate::{
+    Filesystem,
+    error::{FsError, Result},
+    filetype::FileType,
+};
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarZstd,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from a file name, eg. `textures.tar.zst`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZstd)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Converts files while exporting, eg. textures to PNG with `texture::TextureConverter` (`texture` feature)
+pub trait ExportConverter {
+    /// Returns the path and contents of the converted file, or `None` to export the file as-is
+    fn convert(
+        &self,
+        path: &UnixPath,
+        file_type: FileType,
+        data: &[u8],
+    ) -> anyhow::Result<Option<(UnixPathBuf, Vec<u8>)>>;
+}
+
+pub struct ExportOptions<'a> {
+    /// Decompress files, otherwise they're exported as stored in the package
+    pub decompress: bool,
+    /// Only used when decompressing
+    pub converter: Option<&'a dyn ExportConverter>,
+    /// Skip files that fail to read or convert instead of aborting the export
+    pub skip_errors: bool,
+}
+
+impl Default for ExportOptions<'_> {
+    fn default() -> Self {
+        Self {
+            decompress: true,
+            converter: None,
+            skip_errors: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub exported: usize,
+    /// Files that were skipped because of [`ExportOptions::skip_errors`]
+    pub failed: Vec<(UnixPathBuf, anyhow::Error)>,
+}
+
+/// An archive being written
+trait ArchiveSink {
+    fn add(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()>;
+    fn finish(self) -> anyhow::Result<()>;
+}
+
+/// Output of a tar archive, optionally compressed with zstd
+enum TarWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Ends the zstd frame if compressing and flushes the output
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(mut writer) => writer.flush(),
+            Self::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl<W: Write> Write for TarWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+struct TarSink<W: Write>(tar::Builder<TarWriter<W>>);
+
+impl<W: Write> ArchiveSink for TarSink<W> {
+    fn add(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        self.0.append_data(&mut header, path, data)?;
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        self.0.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+struct ZipSink<W: Write + Seek> {
+    writer: zip::ZipWriter<W>,
+    /// Raw files are already compressed, so deflating them again is a waste of time
+    compression: zip::CompressionMethod,
+}
+
+impl<W: Write + Seek> ArchiveSink for ZipSink<W> {
+    fn add(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()> {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(self.compression)
+            .large_file(data.len() as u64 >= u32::MAX as u64);
+        self.writer.start_file(path, options)?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        self.writer.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+impl Filesystem {
+    /// Exports the given files into a tar archive, optionally compressed with zstd
+    pub fn export_tar<P, W>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        writer: W,
+        zstd: bool,
+        options: &ExportOptions,
+    ) -> Result<ExportReport>
+    where
+        P: AsRef<UnixPath>,
+        W: Write,
+    {
+        let writer = if zstd {
+            TarWriter::Zstd(zstd::Encoder::new(writer, ZSTD_LEVEL)?)
+        } else {
+            TarWriter::Plain(writer)
+        };
+        self.export_into(paths, TarSink(tar::Builder::new(writer)), options)
+    }
+
+    /// Exports the given files into a zip archive
+    pub fn export_zip<P, W>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        writer: W,
+        options: &ExportOptions,
+    ) -> Result<ExportReport>
+    where
+        P: AsRef<UnixPath>,
+        W: Write + Seek,
+    {
+        let sink = ZipSink {
+            writer: zip::ZipWriter::new(writer),
+            compression: if options.decompress {
+                zip::CompressionMethod::Deflated
+            } else {
+                zip::CompressionMethod::Stored
+            },
+        };
+        self.export_into(paths, sink, options)
+    }
+
+    /// Exports the given files into an archive file, the format is picked from its extension
+    pub fn export_to_file<P: AsRef<UnixPath>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        path: &Path,
+        options: &ExportOptions,
+    ) -> Result<ExportReport> {
+        let format =
+            ArchiveFormat::from_path(path).ok_or_else(|| FsError::UnknownOutputFormat {
+                path: path.to_path_buf(),
+                expected: ".tar, .tar.zst or .zip",
+            })?;
+
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            ArchiveFormat::Tar => self.export_tar(paths, file, false, options),
+            ArchiveFormat::TarZstd => self.export_tar(paths, file, true, options),
+            ArchiveFormat::Zip => self.export_zip(paths, file, options),
+        }
+    }
+
+    fn export_into<P: AsRef<UnixPath>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        mut sink: impl ArchiveSink,
+        options: &ExportOptions,
+    ) -> Result<ExportReport> {
+        let mut report = ExportReport::default();
+        for path in paths {
+            let path = path.as_ref();
+            match self.export_file(path, options) {
+                Ok((archive_path, data)) => {
+                    let archive_path = archive_path.to_string_lossy();
+                    sink.add(archive_path.trim_start_matches('/'), &data)
+                        .map_err(FsError::Archive)?;
+                    report.exported += 1;
+                }
+                Err(e) if options.skip_errors => {
+                    warn!("Failed to export {}: {e}", path.display());
+                    report.failed.push((path.to_path_buf(), e));
+                }
+                Err(source) => {
+                    return Err(FsError::Export {
+                        path: path.to_path_buf(),
+                        source,
+                    });
+                }
+            }
+        }
+
+        sink.finish().map_err(FsError::Archive)?;
+        Ok(report)
+    }
+
+    /// Reads a single file for exporting, returning its path in the archive and contents
+    fn export_file(
+        &self,
+        path: &UnixPath,
+        options: &ExportOptions,
+    ) -> anyhow::Result<(UnixPathBuf, Vec<u8>)> {
+        if !options.decompress {
+            return Ok((path.to_path_buf(), self.read_path_raw(path)?));
+        }
+
+        let data = self.read_path(path)?;
+        if let Some(converter) = options.converter {
+            let file_type = self.file_type(path).unwrap_or_default();
+            if let Some(converted) = converter.convert(path, file_type, &data)? {
+                return Ok(converted);
+            }
+        }
+
+        Ok((path.to_path_buf(), data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+    use crate::testing::{Ext4Builder, open_filesystem};
+
+    const TEXT: &[u8] = b"stored without compression";
+    const SOUND: &[u8] = b"compressed with zstd, compressed with zstd";
+
+    fn zstd_file(data: &[u8]) -> Vec<u8> {
+        let mut file = b"ZSTD".to_vec();
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend(zstd::encode_all(data, ZSTD_LEVEL).unwrap());
+        file
+    }
+
+    /// A text file, a zstd compressed sound and a sound with a corrupt zstd stream
+    fn test_filesystem() -> Filesystem {
+        let mut broken = zstd_file(SOUND);
+        broken.truncate(12);
+        let disk = Ext4Builder::default()
+            .patch(
+                0,
+                &[
+                    ("tx/a.json", TEXT),
+                    ("au/b.wem", &zstd_file(SOUND)),
+                    ("au/broken.wem", &broken),
+                ],
+            )
+            .build();
+        open_filesystem(disk, None)
+    }
+
+    fn read_tar(archive: impl Read) -> Vec<(String, Vec<u8>)> {
+        let mut archive = tar::Archive::new(archive);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = vec![];
+                entry.read_to_end(&mut data).unwrap();
+                (path, data)
+            })
+            .collect()
+    }
+
+    fn read_zip(archive: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut data = vec![];
+                file.read_to_end(&mut data).unwrap();
+                (file.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    fn expected(sound: &[u8]) -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("tx/a.json".to_string(), TEXT.to_vec()),
+            ("au/b.wem".to_string(), sound.to_vec()),
+        ]
+    }
+
+    #[test]
+    fn exports_tar() {
+        let fs = test_filesystem();
+        let paths = ["tx/a.json", "au/b.wem"];
+
+        let mut archive = vec![];
+        let report = fs
+            .export_tar(paths, &mut archive, false, &ExportOptions::default())
+            .unwrap();
+        assert_eq!(report.exported, 2);
+        assert_eq!(read_tar(archive.as_slice()), expected(SOUND));
+
+        let mut archive = vec![];
+        fs.export_tar(paths, &mut archive, true, &ExportOptions::default())
+            .unwrap();
+        let decoder = zstd::Decoder::new(archive.as_slice()).unwrap();
+        assert_eq!(read_tar(decoder), expected(SOUND));
+    }
+
+    #[test]
+    fn exports_zip() {
+        let fs = test_filesystem();
+        let paths = ["tx/a.json", "au/b.wem"];
+
+        let mut archive = Cursor::new(vec![]);
+        fs.export_zip(paths, &mut archive, &ExportOptions::default())
+            .unwrap();
+        assert_eq!(read_zip(archive.into_inner()), expected(SOUND));
+
+        // Files are exported as stored in the package
+        let options = ExportOptions {
+            decompress: false,
+            ..Default::default()
+        };
+        let mut archive = Cursor::new(vec![]);
+        fs.export_zip(paths, &mut archive, &options).unwrap();
+        assert_eq!(read_zip(archive.into_inner()), expected(&zstd_file(SOUND)));
+    }
+
+    #[test]
+    fn skips_errors() {
+        let fs = test_filesystem();
+        let paths = ["tx/a.json", "au/broken.wem", "au/missing.wem", "au/b.wem"];
+
+        let mut archive = vec![];
+        let report = fs
+            .export_tar(paths, &mut archive, false, &ExportOptions::default())
+            .unwrap();
+        assert_eq!(report.exported, 2);
+        let failed: Vec<_> = report
+            .failed
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect();
+        assert_eq!(failed, ["au/broken.wem", "au/missing.wem"]);
+        assert_eq!(read_tar(archive.as_slice()), expected(SOUND));
+
+        let options = ExportOptions {
+            skip_errors: false,
+            ..Default::default()
+        };
+        let error = fs
+            .export_tar(paths, &mut vec![], false, &options)
+            .unwrap_err();
+        assert!(
+            matches!(&error, FsError::Export { path, .. } if path == "au/broken.wem"),
+            "{error:?}"
+        );
+    }
+}
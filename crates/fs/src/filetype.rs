@@ -3,9 +3,16 @@
 //     ICON_MUSIC, ICON_PACKAGE, ICON_PERSON_STANDING, ICON_SETTINGS, ICON_SHAPES,
 // };
 
+use std::io::Cursor;
+
+use binrw::{BinReaderExt, binrw};
+use gwynn_model::header::{MessiahFileType, MessiahHeader};
 use unix_path::Path;
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+/// Stored as a single byte in the index cache, bump `cache::CACHE_VERSION` when changing the variants
+#[binrw]
+#[brw(repr = u8)]
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum FileType {
     #[default]
     Unknown,
@@ -20,6 +27,9 @@ pub enum FileType {
     WwiseStream,
     WwisePack,
 
+    /// Compiled python bytecode
+    Pyc,
+
     // Messiah files
     UnknownMessiah,
     Model,
@@ -44,11 +54,43 @@ impl FileType {
             }
         }
 
-        // TODO(cohae): Potential future check: Messiah files tend to be UUIDs with hyphens, PYC files are UUIDs without hyphens.
+        // Messiah files are named after hyphenated UUIDs, PYC files after UUIDs without hyphens. Like
+        // `uuid_from_path`, any other extension is ignored.
+        // The exact Messiah type can only be determined from the file header, see `guess_from_messiah_header`.
+        let stem = path.file_stem()?.to_string_lossy();
+        if crate::uuid_from_path(path).is_some() {
+            match stem.len() {
+                36 => return Some(Self::UnknownMessiah),
+                32 => return Some(Self::Pyc),
+                _ => {}
+            }
+        }
 
         None
     }
 
+    /// Determines the type of a Messiah file from its `.MESSIAH` header.
+    ///
+    /// Returns `None` if the data doesn't start with a Messiah header.
+    pub fn guess_from_messiah_header(data: &[u8]) -> Option<Self> {
+        if !data.starts_with(b".MESSIAH") {
+            return None;
+        }
+
+        match Cursor::new(data).read_le::<MessiahHeader>() {
+            Ok(header) => Some(match header.file_type {
+                MessiahFileType::Model => Self::Model,
+                MessiahFileType::Material => Self::Material,
+                _ => Self::UnknownMessiah,
+            }),
+            Err(_) => Some(Self::UnknownMessiah),
+        }
+    }
+
+    pub fn is_messiah(&self) -> bool {
+        matches!(self, Self::UnknownMessiah | Self::Model | Self::Material)
+    }
+
     // pub fn icon(&self) -> char {
     //     match self {
     //         FileType::Unknown => ICON_FILE_QUESTION,
@@ -66,3 +108,33 @@ impl FileType {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid_names_agree_with_uuid_from_path() {
+        for (path, file_type) in [
+            (
+                "a/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10",
+                FileType::UnknownMessiah,
+            ),
+            (
+                "a/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10.bak",
+                FileType::UnknownMessiah,
+            ),
+            ("a/0b2e4b4c8f4e4b8e9f532f0d1f6f2a10", FileType::Pyc),
+            (
+                "a/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10.1",
+                FileType::Texture,
+            ),
+        ] {
+            assert_eq!(FileType::guess_from_path(path), Some(file_type), "{path}");
+            assert!(crate::uuid_from_path(Path::new(path)).is_some(), "{path}");
+        }
+
+        assert_eq!(FileType::guess_from_path("a/not-a-uuid"), None);
+        assert_eq!(crate::uuid_from_path(Path::new("a/not-a-uuid")), None);
+    }
+}
@@ -1,38 +1,212 @@
-use std::{collections::HashMap, io::Read};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
-use anyhow::Context;
-use binrw::BinReaderExt;
 use ext4::Ext4Reader;
+use gwynn_mpk::compression::CompressionType;
+use log::{debug, info, warn};
 use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
+use uuid::Uuid;
 
-use crate::{filetype::FileType, sources::mumuplayer::MumuPlayer};
+use crate::{
+    cache::{CachedPatch, IndexCache},
+    error::{FsError, Result},
+    filetype::FileType,
+    profile::GameProfile,
+    sources::{BlockDevice, image::DiskImage, mumuplayer::MumuPlayer},
+};
 
 pub mod apk;
+pub mod axml;
+pub mod cache;
+pub mod error;
+pub mod export;
 pub mod filetype;
+pub mod manifest;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub mod mount;
+pub mod profile;
+pub mod query;
 pub mod sources;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "texture")]
+pub mod texture;
 
+/// Index of the game files on an Android filesystem.
+///
+/// Reads go through positional reads on the underlying disk, so a `Filesystem` can be shared between threads
+/// and read from concurrently.
 pub struct Filesystem {
-    ext4: Ext4Reader<vdi::slice::OwnedSlice>,
+    ext4: Ext4Reader<BlockDevice>,
+    profile: GameProfile,
+    storage_root: UnixPathBuf,
 
     patch_basepath: UnixPathBuf,
     patch_paths: Vec<UnixPathBuf>,
 
     paths: HashMap<UnixPathBuf, FilePointer>,
     paths_by_filetype: HashMap<FileType, Vec<UnixPathBuf>>,
+    paths_by_uuid: HashMap<Uuid, Vec<UnixPathBuf>>,
+
+    /// Index cache file, see [`cache`]
+    cache_path: Option<PathBuf>,
 }
 
 impl Filesystem {
-    pub fn ext4(&self) -> &Ext4Reader<vdi::slice::OwnedSlice> {
+    pub fn ext4(&self) -> &Ext4Reader<BlockDevice> {
         &self.ext4
     }
 
-    pub fn read_path<P: AsRef<UnixPath>>(&self, path: P) -> anyhow::Result<Vec<u8>> {
-        todo!()
+    pub fn profile(&self) -> &GameProfile {
+        &self.profile
+    }
+
+    /// The user storage root the game data was loaded from, eg. `/media/0`
+    pub fn storage_root(&self) -> &UnixPath {
+        &self.storage_root
+    }
+
+    /// Reads and decompresses the file at the given path
+    pub fn read_path<P: AsRef<UnixPath>>(&self, path: P) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        let mut data = self.read_path_raw(path)?;
+        let data = gwynn_mpk::compression::decompress_with(&mut data, self.profile.encryption)
+            .map_err(|source| FsError::Decompression {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        Ok(data.into_owned())
+    }
+
+    /// Reads the file at the given path as it is stored in the package, without decompressing it
+    pub fn read_path_raw<P: AsRef<UnixPath>>(&self, path: P) -> Result<Vec<u8>> {
+        let pointer = self.pointer(path.as_ref())?;
+        self.read_pointer(pointer, pointer.size())
+    }
+
+    /// Detects the compression of the file at the given path by reading its header
+    pub fn compression_type<P: AsRef<UnixPath>>(&self, path: P) -> Result<Option<CompressionType>> {
+        let pointer = self.pointer(path.as_ref())?;
+        let header = self.read_pointer(pointer, pointer.size().min(4))?;
+
+        Ok(CompressionType::detect_from_slice(&header))
+    }
+
+    /// Size of the file after decompressing it, read from its compression header.
+    ///
+    /// Returns `None` for zlib compressed files, which don't store their size.
+    pub fn decompressed_size<P: AsRef<UnixPath>>(&self, path: P) -> Result<Option<u64>> {
+        let pointer = self.pointer(path.as_ref())?;
+        let header = self.read_pointer(pointer, pointer.size().min(COMPRESSION_HEADER_SIZE))?;
+
+        Ok(decompressed_size_from_header(
+            &header,
+            pointer.size() as u64,
+        ))
+    }
+
+    fn pointer(&self, path: &UnixPath) -> Result<&FilePointer> {
+        self.paths
+            .get(path)
+            .ok_or_else(|| FsError::PathNotFound(path.to_path_buf()))
     }
 
-    // pub fn read_uuid(&self, uuid: Uuid) -> anyhow::Result<Vec<u8>> {
-    //     todo!()
-    // }
+    /// Reads the first `len` bytes of the file
+    fn read_pointer(&self, pointer: &FilePointer, len: usize) -> Result<Vec<u8>> {
+        match pointer {
+            FilePointer::Patch { index, offset, .. } => {
+                let mpk_path = self.patch_paths[*index].with_extension("mpk");
+                let mut file = self.ext4.open(&mpk_path).map_err(FsError::ext4)?;
+                file.seek(SeekFrom::Start(*offset))?;
+
+                let mut data = vec![0u8; len];
+                file.read_exact(&mut data)?;
+                Ok(data)
+            }
+            FilePointer::Resource { .. } => Err(FsError::UnsupportedResourcePackage),
+        }
+    }
+
+    /// Reads and decompresses the file named after the given UUID.
+    ///
+    /// If multiple files share the UUID (eg. with different extensions), the first one is read.
+    pub fn read_uuid(&self, uuid: Uuid) -> Result<Vec<u8>> {
+        let path = self
+            .iter_by_uuid(uuid)
+            .next()
+            .ok_or(FsError::UuidNotFound(uuid))?;
+
+        self.read_path(path)
+    }
+
+    /// Returns all files named after the given UUID
+    pub fn iter_by_uuid(&self, uuid: Uuid) -> impl Iterator<Item = &UnixPathBuf> {
+        self.paths_by_uuid
+            .get(&uuid)
+            .map(|v| v.iter())
+            .into_iter()
+            .flatten()
+    }
+
+    pub fn exists<P: AsRef<UnixPath>>(&self, path: P) -> bool {
+        self.paths.contains_key(path.as_ref())
+    }
+
+    pub fn file_type<P: AsRef<UnixPath>>(&self, path: P) -> Option<FileType> {
+        self.paths.get(path.as_ref()).map(FilePointer::file_type)
+    }
+
+    /// Reads the header of every file classified as [`FileType::UnknownMessiah`] to determine its exact type.
+    ///
+    /// This has to read and decompress every Messiah file, so it isn't done when opening the filesystem.
+    /// The results are stored in the index cache. Returns the number of reclassified files.
+    pub fn classify_messiah_files(&mut self) -> Result<usize> {
+        let Some(unknown) = self.paths_by_filetype.remove(&FileType::UnknownMessiah) else {
+            return Ok(0);
+        };
+
+        let mut reclassified = HashMap::new();
+        for path in unknown {
+            let filetype = match self.read_path(&path) {
+                Ok(data) => FileType::guess_from_messiah_header(&data),
+                Err(e) => {
+                    warn!("Failed to read {}: {e}", path.display());
+                    None
+                }
+            }
+            .unwrap_or(FileType::UnknownMessiah);
+
+            if filetype != FileType::UnknownMessiah {
+                reclassified.insert(path.to_string_lossy().to_string(), filetype);
+                if let Some(pointer) = self.paths.get_mut(&path) {
+                    *pointer.file_type_mut() = filetype;
+                }
+            }
+            self.paths_by_filetype
+                .entry(filetype)
+                .or_default()
+                .push(path);
+        }
+
+        if !reclassified.is_empty()
+            && let Some(cache_path) = &self.cache_path
+        {
+            let result = IndexCache::load(cache_path).and_then(|mut cache| {
+                cache.set_file_types(&reclassified);
+                cache.save(cache_path)
+            });
+            if let Err(e) = result {
+                warn!("Failed to update index cache {}: {e}", cache_path.display());
+            }
+        }
+
+        Ok(reclassified.len())
+    }
 
     pub fn iter_by_type(&self, filetype: FileType) -> impl Iterator<Item = &UnixPathBuf> {
         self.paths_by_filetype
@@ -56,90 +230,337 @@ impl Filesystem {
 }
 
 impl Filesystem {
-    pub fn open() -> anyhow::Result<Self> {
-        let Some(vm) =
-            MumuPlayer::open_biggest_vm_ext4().context("Failed to open mumuplayer VM")?
-        else {
-            anyhow::bail!("No MuMuPlayer VMs found");
+    /// Opens the MuMuPlayer VM selected through [`sources::mumuplayer::VM_ENV`], or the one with the biggest data disk
+    pub fn open() -> Result<Self> {
+        let Some(vm) = MumuPlayer::open_selected_vm_ext4()? else {
+            return Err(FsError::NoVms);
         };
 
         Self::open_from_ext4(vm)
     }
 
-    pub fn open_from_ext4(ext4: Ext4Reader<vdi::slice::OwnedSlice>) -> anyhow::Result<Self> {
-        let patch_basepath =
-            UnixPathBuf::from("/media/0/Android/data/com.netease.g108na/files/LocalData/Patch/");
+    /// Opens a specific MuMuPlayer VM folder (`vms/<name>`)
+    pub fn open_mumu_vm(path: &Path) -> Result<Self> {
+        Self::open_from_ext4(MumuPlayer::open_vm_ext4(path)?)
+    }
+
+    /// Opens a raw ext4, partitioned or Android sparse disk image, such as a `userdata.img` dump
+    pub fn open_from_image(path: &Path) -> Result<Self> {
+        let ext4 = DiskImage::open_ext4(path).map_err(|source| FsError::DiskImage {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Self::open_from_ext4(ext4)
+    }
+
+    /// Opens the game data on the given filesystem, detecting the game profile from the installed apps
+    pub fn open_from_ext4(ext4: Ext4Reader<BlockDevice>) -> Result<Self> {
+        let report = apk::scan_for_apps(&ext4);
+        let profile = GameProfile::detect_from_apps(&report.apps).unwrap_or_else(|| {
+            warn!("No known game installation found, falling back to the global profile");
+            GameProfile::global()
+        });
+        info!("Using game profile '{}'", profile.name);
+
+        Self::open_from_ext4_with_profile(ext4, profile)
+    }
+
+    /// Opens the game data of the given profile, using the first user storage root that contains it
+    pub fn open_from_ext4_with_profile(
+        ext4: Ext4Reader<BlockDevice>,
+        profile: GameProfile,
+    ) -> Result<Self> {
+        let roots = storage::probe_storage_roots(&ext4, &profile);
+        for root in &roots {
+            debug!(
+                "Found storage root {} (user {}, game data: {})",
+                root.path.display(),
+                root.user_id,
+                root.has_game_data
+            );
+        }
+
+        let root = roots
+            .into_iter()
+            .find(|r| r.has_game_data)
+            .ok_or_else(|| FsError::GameDataNotFound(profile.name.clone()))?;
+
+        Self::open_from_ext4_at(ext4, profile, root.path)
+    }
+
+    /// Opens the game data of the given profile under a specific user storage root, eg. `/media/10`
+    ///
+    /// See [`storage::probe_storage_roots`] for finding the available roots.
+    pub fn open_from_ext4_at(
+        ext4: Ext4Reader<BlockDevice>,
+        profile: GameProfile,
+        storage_root: UnixPathBuf,
+    ) -> Result<Self> {
+        let cache_path = cache::cache_path(&profile, &storage_root);
+        Self::open_indexed(ext4, profile, storage_root, cache_path)
+    }
+
+    /// Indexes the patches under the storage root, going through the index cache at `cache_path` if given
+    fn open_indexed(
+        ext4: Ext4Reader<BlockDevice>,
+        profile: GameProfile,
+        storage_root: UnixPathBuf,
+        cache_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let patch_basepath = profile.patch_dir(&storage_root);
+        if !ext4.exists(&patch_basepath) {
+            return Err(FsError::GameDataNotFound(profile.name.clone()));
+        }
+
+        let mut cache = cache_path.as_deref().and_then(|path| {
+            IndexCache::load(path)
+                .inspect_err(|e| debug!("Not using index cache {}: {e}", path.display()))
+                .ok()
+        });
+        let mut cache_dirty = cache.is_none();
 
         let mut patch_paths = vec![];
-        let mut paths = HashMap::new();
-        let mut paths_by_filetype: HashMap<FileType, Vec<UnixPathBuf>> = HashMap::new();
+        let mut patches = vec![];
         for i in 0.. {
-            let filename = match i {
-                0 => "Patch.mpkinfo",
-                _ => &format!("Patch{i}.mpkinfo"),
-            };
-            let patch_path = patch_basepath.join(filename);
+            let name = profile.patch_naming.file_name(i, "mpkinfo");
+            let patch_path = patch_basepath.join(&name);
             if !ext4.exists(&patch_path) {
                 break;
             }
 
-            patch_paths.push(patch_path.clone());
-
             let mut buf = vec![];
-            ext4.open(&patch_path)?.read_to_end(&mut buf)?;
-            let mut cursor = std::io::Cursor::new(&buf);
-            loop {
-                let entry = match cursor.read_le::<gwynn_mpk::EntryHeader>() {
-                    Ok(o) => o,
-                    Err(e) => {
-                        if e.is_eof() {
-                            break;
-                        }
-
-                        return Err(e.into());
-                    }
-                };
+            ext4.open(&patch_path)
+                .map_err(FsError::ext4)?
+                .read_to_end(&mut buf)?;
+            let checksum = crc32fast::hash(&buf);
 
-                if entry.flags & 1 != 0 {
-                    // This is a directory entry, skip it.
-                    continue;
+            let cached = cache
+                .as_mut()
+                .and_then(|c| c.take_patch(&name, buf.len() as u64, checksum));
+            let patch = match cached {
+                Some(patch) => patch,
+                None => {
+                    debug!("Indexing {}", patch_path.display());
+                    cache_dirty = true;
+                    CachedPatch::parse(&name, &buf).map_err(|source| FsError::InvalidIndex {
+                        path: patch_path.clone(),
+                        source,
+                    })?
                 }
+            };
+
+            patch_paths.push(patch_path);
+            patches.push(patch);
+        }
 
+        // Patches that were removed since the cache was written
+        if cache.is_some_and(|c| !c.patches.is_empty()) {
+            cache_dirty = true;
+        }
+
+        let mut paths = HashMap::new();
+        let mut paths_by_filetype: HashMap<FileType, Vec<UnixPathBuf>> = HashMap::new();
+        let mut paths_by_uuid: HashMap<Uuid, Vec<UnixPathBuf>> = HashMap::new();
+        for (i, patch) in patches.iter().enumerate() {
+            for entry in &patch.entries {
                 let path = UnixPathBuf::from(&entry.path);
-                paths.insert(
+                let previous = paths.insert(
                     path.clone(),
                     FilePointer::Patch {
                         index: i,
                         offset: entry.offset,
-                        size: entry.length as usize,
+                        size: entry.size as usize,
+                        asset_id: entry.asset_id,
+                        hash: entry.hash.as_str().into(),
+                        file_type: entry.file_type,
                     },
                 );
+                if let Some(previous) = previous {
+                    // Newer patches override files from older ones, the path is already indexed but may have
+                    // changed type
+                    if previous.file_type() != entry.file_type {
+                        if let Some(paths) = paths_by_filetype.get_mut(&previous.file_type()) {
+                            paths.retain(|p| p != &path);
+                        }
+                        paths_by_filetype
+                            .entry(entry.file_type)
+                            .or_default()
+                            .push(path);
+                    }
+                    continue;
+                }
+
+                if let Some(uuid) = uuid_from_path(&path) {
+                    paths_by_uuid.entry(uuid).or_default().push(path.clone());
+                }
+
+                paths_by_filetype
+                    .entry(entry.file_type)
+                    .or_default()
+                    .push(path);
+            }
+        }
 
-                let filetype = FileType::guess_from_path(&entry.path).unwrap_or(FileType::Unknown);
-                paths_by_filetype.entry(filetype).or_default().push(path);
+        if cache_dirty && let Some(cache_path) = &cache_path {
+            let cache = IndexCache { patches };
+            match cache.save(cache_path) {
+                Ok(()) => debug!("Saved index cache to {}", cache_path.display()),
+                Err(e) => warn!(
+                    "Failed to save index cache to {}: {e}",
+                    cache_path.display()
+                ),
             }
         }
 
         Ok(Self {
             ext4,
+            profile,
+            storage_root,
             patch_basepath,
             patch_paths,
 
             paths,
             paths_by_filetype,
+            paths_by_uuid,
+            cache_path,
         })
     }
 }
 
+// Texture loader threads share a single filesystem
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Filesystem>();
+};
+
+/// Enough for the compression magic and the decompressed size following it
+const COMPRESSION_HEADER_SIZE: usize = 8;
+
+/// Decompressed size of a file from the start of its data and its stored size
+fn decompressed_size_from_header(header: &[u8], stored_size: u64) -> Option<u64> {
+    match CompressionType::detect_from_slice(header) {
+        // Not compressed at all
+        None => Some(stored_size),
+        Some(CompressionType::None) => Some(stored_size.saturating_sub(4)),
+        Some(_) => CompressionType::decompressed_size_from_slice(header).map(|size| size as u64),
+    }
+}
+
+/// Parses the UUID a file is named after, ignoring its extension
+pub fn uuid_from_path(path: &UnixPath) -> Option<Uuid> {
+    let stem = path.file_stem()?.to_string_lossy();
+    Uuid::try_parse(&stem).ok()
+}
+
 enum FilePointer {
     Resource {
         index: usize,
         offset: u64,
         size: usize,
+        file_type: FileType,
     },
     Patch {
         index: usize,
         offset: u64,
         size: usize,
+        asset_id: u64,
+        hash: Box<str>,
+        file_type: FileType,
     },
 }
+
+impl FilePointer {
+    fn size(&self) -> usize {
+        match self {
+            FilePointer::Resource { size, .. } | FilePointer::Patch { size, .. } => *size,
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self {
+            FilePointer::Resource { file_type, .. } | FilePointer::Patch { file_type, .. } => {
+                *file_type
+            }
+        }
+    }
+
+    fn file_type_mut(&mut self) -> &mut FileType {
+        match self {
+            FilePointer::Resource { file_type, .. } | FilePointer::Patch { file_type, .. } => {
+                file_type
+            }
+        }
+    }
+
+    /// Index of the patch the file is stored in, `None` for files in resource packages
+    fn patch_index(&self) -> Option<usize> {
+        match self {
+            FilePointer::Resource { .. } => None,
+            FilePointer::Patch { index, .. } => Some(*index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Ext4Builder, open_filesystem};
+
+    /// A single patch with files of different sizes, most of which cross block boundaries of the image
+    fn test_filesystem() -> (Filesystem, Vec<(String, Vec<u8>)>) {
+        let files: Vec<(String, Vec<u8>)> = [1, 100, 4095, 4097, 10_000, 20_000, 65_536]
+            .into_iter()
+            .enumerate()
+            .map(|(i, len)| {
+                let data = (0..len).map(|b| ((b * 7 + i * 13) % 251) as u8).collect();
+                (format!("tx/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a1{i}.1"), data)
+            })
+            .collect();
+
+        let patch: Vec<(&str, &[u8])> = files
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+            .collect();
+        let fs = open_filesystem(Ext4Builder::default().patch(0, &patch).build(), None);
+
+        (fs, files)
+    }
+
+    #[test]
+    fn decompressed_size_matches_read_path() {
+        let (fs, files) = test_filesystem();
+        for (path, data) in &files {
+            assert_eq!(fs.decompressed_size(path).unwrap(), Some(data.len() as u64));
+        }
+    }
+
+    #[test]
+    fn parallel_reads_match_serial_reads() {
+        let (fs, files) = test_filesystem();
+        let serial: Vec<Vec<u8>> = files
+            .iter()
+            .map(|(path, _)| fs.read_path(path).unwrap())
+            .collect();
+        for ((path, data), read) in files.iter().zip(&serial) {
+            assert_eq!(read, data, "{path}");
+        }
+
+        std::thread::scope(|s| {
+            for thread in 0..8 {
+                let (fs, files, serial) = (&fs, &files, &serial);
+                s.spawn(move || {
+                    // Every thread starts at a different file, so different files are read at the same time
+                    for i in (0..files.len() * 16).map(|i| (i + thread) % files.len()) {
+                        assert_eq!(
+                            fs.read_path(&files[i].0).unwrap(),
+                            serial[i],
+                            "{}",
+                            files[i].0
+                        );
+                    }
+                });
+            }
+        });
+    }
+}
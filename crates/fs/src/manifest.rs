@@ -0,0 +1,328 @@
+//! Manifests listing every asset of a [`Filesystem`] with its location, sizes, compression and hash, as CSV or JSON.
+//!
+//! [`MetadataProvider`]s add type-specific columns, eg. vertex counts for models. Those need the decompressed file,
+//! so they make building a manifest as slow as reading every file they apply to.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::Path,
+};
+
+use binrw::BinReaderExt;
+use gwynn_model::header::{MessiahHeader, ModelHeader};
+use gwynn_mpk::compression::CompressionType;
+use log::warn;
+use serde_json::{Value, json};
+use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
+
+use crate::{
+    COMPRESSION_HEADER_SIZE, FilePointer, Filesystem, decompressed_size_from_header,
+    error::{FsError, Result},
+    filetype::FileType,
+};
+
+/// Columns written before the metadata columns in CSV manifests
+const CSV_COLUMNS: &[&str] = &[
+    "path",
+    "asset_id",
+    "patch",
+    "offset",
+    "compressed_size",
+    "decompressed_size",
+    "compression",
+    "file_type",
+    "hash",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    /// Guesses the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Extracts type-specific metadata from the decompressed contents of a file
+pub trait MetadataProvider {
+    fn applies_to(&self, file_type: FileType) -> bool;
+    fn metadata(&self, path: &UnixPath, data: &[u8]) -> anyhow::Result<BTreeMap<String, Value>>;
+}
+
+/// Mesh, vertex and index counts of [`FileType::Model`] files
+pub struct ModelMetadata;
+
+impl MetadataProvider for ModelMetadata {
+    fn applies_to(&self, file_type: FileType) -> bool {
+        file_type == FileType::Model
+    }
+
+    fn metadata(&self, _path: &UnixPath, data: &[u8]) -> anyhow::Result<BTreeMap<String, Value>> {
+        let mut cursor = std::io::Cursor::new(data);
+        let _header: MessiahHeader = cursor.read_le()?;
+        let model: ModelHeader = cursor.read_le()?;
+
+        let layouts: Vec<&str> = model
+            .buffer_layouts
+            .iter()
+            .map(|l| l.string.as_str())
+            .filter(|l| *l != "None")
+            .collect();
+
+        Ok(BTreeMap::from([
+            ("mesh_count".to_string(), json!(model.mesh_count)),
+            ("vertex_count".to_string(), json!(model.vertex_count)),
+            ("index_count".to_string(), json!(model.index_count)),
+            ("buffer_layouts".to_string(), json!(layouts.join(" "))),
+        ]))
+    }
+}
+
+pub struct ManifestOptions<'a> {
+    pub providers: &'a [&'a dyn MetadataProvider],
+    /// Read the compression header of every file. Without it only the index is used and the
+    /// compression and decompressed size are left empty
+    pub read_headers: bool,
+}
+
+/// Providers of [`ManifestOptions::default`], textures need the `texture` feature
+#[cfg(feature = "texture")]
+const DEFAULT_PROVIDERS: &[&dyn MetadataProvider] =
+    &[&ModelMetadata, &crate::texture::TextureMetadata];
+#[cfg(not(feature = "texture"))]
+const DEFAULT_PROVIDERS: &[&dyn MetadataProvider] = &[&ModelMetadata];
+
+impl Default for ManifestOptions<'_> {
+    fn default() -> Self {
+        Self {
+            providers: DEFAULT_PROVIDERS,
+            read_headers: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: UnixPathBuf,
+    pub asset_id: Option<u64>,
+    /// `None` for files in resource packages
+    pub patch_index: Option<usize>,
+    pub offset: u64,
+    /// Size as stored in the package
+    pub compressed_size: u64,
+    /// Only known if the compression header stores it, or the file was decompressed for its metadata
+    pub decompressed_size: Option<u64>,
+    pub compression: Option<CompressionType>,
+    pub file_type: FileType,
+    pub hash: Option<String>,
+    pub metadata: BTreeMap<String, Value>,
+}
+
+impl ManifestEntry {
+    fn to_json(&self) -> Value {
+        json!({
+            "path": self.path.to_string_lossy(),
+            "asset_id": self.asset_id,
+            "patch": self.patch_index,
+            "offset": self.offset,
+            "compressed_size": self.compressed_size,
+            "decompressed_size": self.decompressed_size,
+            "compression": self.compression.map(|c| format!("{c:?}")),
+            "file_type": format!("{:?}", self.file_type),
+            "hash": self.hash,
+            "metadata": self.metadata,
+        })
+    }
+
+    /// Values of the fixed [`CSV_COLUMNS`]
+    fn csv_fields(&self) -> [String; CSV_COLUMNS.len()] {
+        fn opt<T: ToString>(v: Option<T>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+
+        [
+            self.path.to_string_lossy().into_owned(),
+            opt(self.asset_id),
+            opt(self.patch_index),
+            self.offset.to_string(),
+            self.compressed_size.to_string(),
+            opt(self.decompressed_size),
+            self.compression
+                .map(|c| format!("{c:?}"))
+                .unwrap_or_default(),
+            format!("{:?}", self.file_type),
+            self.hash.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Filesystem {
+    /// Builds manifest entries for the given files, in the given order
+    pub fn manifest<P: AsRef<UnixPath>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        options: &ManifestOptions,
+    ) -> Result<Vec<ManifestEntry>> {
+        paths
+            .into_iter()
+            .map(|path| self.manifest_entry(path.as_ref(), options))
+            .collect()
+    }
+
+    fn manifest_entry(&self, path: &UnixPath, options: &ManifestOptions) -> Result<ManifestEntry> {
+        let pointer = self.pointer(path)?;
+        let file_type = pointer.file_type();
+        let mut entry = match pointer {
+            FilePointer::Patch {
+                index,
+                offset,
+                size,
+                asset_id,
+                hash,
+                ..
+            } => ManifestEntry {
+                path: path.to_path_buf(),
+                asset_id: Some(*asset_id),
+                patch_index: Some(*index),
+                offset: *offset,
+                compressed_size: *size as u64,
+                decompressed_size: None,
+                compression: None,
+                file_type,
+                hash: Some(hash.to_string()),
+                metadata: BTreeMap::new(),
+            },
+            FilePointer::Resource { offset, size, .. } => ManifestEntry {
+                path: path.to_path_buf(),
+                asset_id: None,
+                patch_index: None,
+                offset: *offset,
+                compressed_size: *size as u64,
+                decompressed_size: None,
+                compression: None,
+                file_type,
+                hash: None,
+                metadata: BTreeMap::new(),
+            },
+        };
+
+        if options.read_headers {
+            match self.read_pointer(pointer, pointer.size().min(COMPRESSION_HEADER_SIZE)) {
+                Ok(header) => {
+                    entry.compression = CompressionType::detect_from_slice(&header);
+                    entry.decompressed_size =
+                        decompressed_size_from_header(&header, entry.compressed_size);
+                }
+                Err(e) => warn!("Failed to read header of {}: {e}", path.display()),
+            }
+        }
+
+        let providers: Vec<_> = options
+            .providers
+            .iter()
+            .filter(|p| p.applies_to(file_type))
+            .collect();
+        if !providers.is_empty() {
+            match self.read_path(path) {
+                Ok(data) => {
+                    entry.decompressed_size = Some(data.len() as u64);
+                    for provider in providers {
+                        match provider.metadata(path, &data) {
+                            Ok(metadata) => entry.metadata.extend(metadata),
+                            Err(e) => {
+                                warn!("Failed to read metadata of {}: {e}", path.display())
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to read {}: {e}", path.display()),
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
+/// Writes the entries as a JSON array of objects, metadata is nested under `metadata`
+pub fn write_json<W: Write>(entries: &[ManifestEntry], writer: W) -> std::io::Result<()> {
+    let entries: Vec<Value> = entries.iter().map(ManifestEntry::to_json).collect();
+    serde_json::to_writer_pretty(writer, &entries)?;
+    Ok(())
+}
+
+/// Writes the entries as CSV, with a column for every metadata key that occurs in any entry
+pub fn write_csv<W: Write>(entries: &[ManifestEntry], mut writer: W) -> std::io::Result<()> {
+    let metadata_columns: BTreeSet<&str> = entries
+        .iter()
+        .flat_map(|e| e.metadata.keys().map(String::as_str))
+        .collect();
+
+    let header = CSV_COLUMNS
+        .iter()
+        .copied()
+        .chain(metadata_columns.iter().copied());
+    write_csv_row(&mut writer, header)?;
+
+    for entry in entries {
+        let fields = entry.csv_fields();
+        let metadata = metadata_columns
+            .iter()
+            .map(|key| match entry.metadata.get(*key) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(v) => v.to_string(),
+            });
+        let metadata: Vec<String> = metadata.collect();
+        write_csv_row(
+            &mut writer,
+            fields.iter().chain(metadata.iter()).map(String::as_str),
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_csv_row<'a, W: Write>(
+    writer: &mut W,
+    fields: impl Iterator<Item = &'a str>,
+) -> std::io::Result<()> {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+
+    writer.write_all(b"\n")
+}
+
+/// Writes the entries to a file, the format is picked from its extension
+pub fn write_to_file(entries: &[ManifestEntry], path: &Path) -> Result<()> {
+    let format = ManifestFormat::from_path(path).ok_or_else(|| FsError::UnknownOutputFormat {
+        path: path.to_path_buf(),
+        expected: ".csv or .json",
+    })?;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    match format {
+        ManifestFormat::Csv => write_csv(entries, file)?,
+        ManifestFormat::Json => write_json(entries, file)?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,399 @@
+//! Read-only FUSE mount of a [`Filesystem`], so the game files can be browsed with regular tools.
+//!
+//! Files show up decompressed under their index path. [`SyntheticView`]s add converted copies of files next to them,
+//! eg. `<texture>.png` with `texture::TextureConverter` (`texture` feature).
+//!
+//! Sizes are read from the compression headers, only zlib compressed files have to be decompressed to `stat` them.
+//! Converted copies are only converted when opened, until then their size shows up as 0. They are opened with direct
+//! I/O, so they can be read before their size is known.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use fuser::{
+    FileAttr, FileType as FuseFileType, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, ReplyOpen, Request, consts::FOPEN_DIRECT_IO,
+};
+use log::{debug, warn};
+use unix_path::{Component, PathBuf as UnixPathBuf};
+
+use crate::{Filesystem, error::Result, filetype::FileType};
+
+const ROOT_INO: u64 = 1;
+/// The filesystem never changes while mounted
+const TTL: Duration = Duration::from_secs(60 * 60);
+const BLOCK_SIZE: u32 = 4096;
+
+/// A converted copy of files of a specific type, shown next to the original file
+pub trait SyntheticView: Send + Sync {
+    /// Extension appended to the original file name, eg. `png`
+    fn extension(&self) -> &str;
+    fn applies_to(&self, file_type: FileType) -> bool;
+    /// Converts the decompressed contents of the original file
+    fn convert(&self, path: &unix_path::Path, data: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+enum NodeKind {
+    Directory(BTreeMap<String, u64>),
+    File {
+        path: UnixPathBuf,
+        /// Index into [`GameFs::views`], `None` for the original file
+        view: Option<usize>,
+    },
+}
+
+struct Node {
+    parent: u64,
+    kind: NodeKind,
+}
+
+struct GameFs {
+    fs: Arc<Filesystem>,
+    views: Vec<Box<dyn SyntheticView>>,
+    /// Indexed by inode - 1
+    nodes: Vec<Node>,
+    sizes: HashMap<u64, u64>,
+    open_files: HashMap<u64, Arc<Vec<u8>>>,
+    next_fh: u64,
+    mount_time: SystemTime,
+    /// Files are owned by the owner of the mountpoint
+    uid: u32,
+    gid: u32,
+}
+
+impl GameFs {
+    fn new(
+        fs: Arc<Filesystem>,
+        views: Vec<Box<dyn SyntheticView>>,
+        mountpoint: &Path,
+    ) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(mountpoint)?;
+        let mut this = Self {
+            fs: fs.clone(),
+            views,
+            nodes: vec![Node {
+                parent: ROOT_INO,
+                kind: NodeKind::Directory(BTreeMap::new()),
+            }],
+            sizes: HashMap::new(),
+            open_files: HashMap::new(),
+            next_fh: 1,
+            mount_time: SystemTime::now(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        };
+
+        for (file_type, paths) in fs.iter_types() {
+            for path in paths {
+                let components: Vec<String> = path
+                    .components()
+                    .filter_map(|c| match c {
+                        Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                        _ => None,
+                    })
+                    .collect();
+                let Some((file_name, dirs)) = components.split_last() else {
+                    continue;
+                };
+
+                let Some(parent) = dirs
+                    .iter()
+                    .try_fold(ROOT_INO, |dir, name| this.get_or_create_dir(dir, name))
+                else {
+                    warn!("Not mounting {}, a file is in the way", path.display());
+                    continue;
+                };
+
+                this.add_file(parent, file_name.clone(), path.clone(), None);
+                for view in 0..this.views.len() {
+                    if this.views[view].applies_to(file_type) {
+                        let name = format!("{file_name}.{}", this.views[view].extension());
+                        this.add_file(parent, name, path.clone(), Some(view));
+                    }
+                }
+            }
+        }
+
+        Ok(this)
+    }
+
+    fn add_node(&mut self, parent: u64, name: String, kind: NodeKind) -> Option<u64> {
+        let ino = self.nodes.len() as u64 + 1;
+        let NodeKind::Directory(children) = &mut self.nodes[parent as usize - 1].kind else {
+            return None;
+        };
+        if children.contains_key(&name) {
+            return None;
+        }
+
+        children.insert(name, ino);
+        self.nodes.push(Node { parent, kind });
+        Some(ino)
+    }
+
+    fn get_or_create_dir(&mut self, parent: u64, name: &str) -> Option<u64> {
+        let NodeKind::Directory(children) = &self.nodes[parent as usize - 1].kind else {
+            return None;
+        };
+
+        match children.get(name).copied() {
+            Some(ino) => {
+                matches!(self.nodes[ino as usize - 1].kind, NodeKind::Directory(_)).then_some(ino)
+            }
+            None => self.add_node(
+                parent,
+                name.to_string(),
+                NodeKind::Directory(BTreeMap::new()),
+            ),
+        }
+    }
+
+    fn add_file(&mut self, parent: u64, name: String, path: UnixPathBuf, view: Option<usize>) {
+        if self
+            .add_node(parent, name, NodeKind::File { path, view })
+            .is_none()
+        {
+            debug!("Duplicate entry in directory inode {parent}");
+        }
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get((ino as usize).checked_sub(1)?)
+    }
+
+    /// Reads, decompresses and converts the contents of a file node
+    fn load(&mut self, ino: u64) -> anyhow::Result<Arc<Vec<u8>>> {
+        let Some(Node {
+            kind: NodeKind::File { path, view },
+            ..
+        }) = self.node(ino)
+        else {
+            anyhow::bail!("Inode {ino} is not a file");
+        };
+
+        let mut data = self.fs.read_path(path)?;
+        if let Some(view) = view {
+            data = self.views[*view].convert(path, &data)?;
+        }
+
+        self.sizes.insert(ino, data.len() as u64);
+        Ok(Arc::new(data))
+    }
+
+    /// Size of a file node, without converting it
+    fn size(&mut self, ino: u64, path: &UnixPathBuf, view: Option<usize>) -> u64 {
+        if let Some(size) = self.sizes.get(&ino) {
+            return *size;
+        }
+        if view.is_some() {
+            return 0;
+        }
+
+        let size = match self.fs.decompressed_size(path) {
+            Ok(Some(size)) => Ok(size),
+            Ok(None) => self.load(ino).map(|data| data.len() as u64),
+            Err(e) => Err(e.into()),
+        };
+        match size {
+            Ok(size) => {
+                self.sizes.insert(ino, size);
+                size
+            }
+            Err(e) => {
+                warn!("Failed to read size of inode {ino}: {e}");
+                0
+            }
+        }
+    }
+
+    fn attr(&mut self, ino: u64) -> Option<FileAttr> {
+        let (kind, size, perm, nlink) = match &self.node(ino)?.kind {
+            NodeKind::Directory(_) => (FuseFileType::Directory, 0, 0o555, 2),
+            NodeKind::File { path, view } => {
+                let (path, view) = (path.clone(), *view);
+                (
+                    FuseFileType::RegularFile,
+                    self.size(ino, &path, view),
+                    0o444,
+                    1,
+                )
+            }
+        };
+
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mount_time,
+            mtime: self.mount_time,
+            ctime: self.mount_time,
+            crtime: self.mount_time,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        })
+    }
+}
+
+impl fuser::Filesystem for GameFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.node(parent).map(|n| &n.kind) {
+            Some(NodeKind::Directory(children)) => children.get(&*name.to_string_lossy()).copied(),
+            _ => None,
+        };
+
+        match ino.and_then(|ino| self.attr(ino)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+
+        // The size of converted files isn't known until now
+        let direct_io = matches!(
+            self.node(ino).map(|n| &n.kind),
+            Some(NodeKind::File { view: Some(_), .. })
+        );
+        match self.load(ino) {
+            Ok(data) => {
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.open_files.insert(fh, data);
+                reply.opened(fh, if direct_io { FOPEN_DIRECT_IO } else { 0 });
+            }
+            Err(e) => {
+                warn!("Failed to open inode {ino}: {e}");
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(data) = self.open_files.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+
+        let start = (offset.max(0) as usize).min(data.len());
+        let end = (start + size as usize).min(data.len());
+        reply.data(&data[start..end]);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.open_files.remove(&fh);
+        reply.ok();
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Node {
+            parent,
+            kind: NodeKind::Directory(children),
+        }) = self.node(ino)
+        else {
+            return reply.error(libc::ENOTDIR);
+        };
+
+        let entries = [
+            (ino, FuseFileType::Directory, "."),
+            (*parent, FuseFileType::Directory, ".."),
+        ]
+        .into_iter()
+        .chain(children.iter().map(|(name, &child)| {
+            let kind = match self.nodes[child as usize - 1].kind {
+                NodeKind::Directory(_) => FuseFileType::Directory,
+                NodeKind::File { .. } => FuseFileType::RegularFile,
+            };
+            (child, kind, name.as_str())
+        }));
+
+        for (i, (child, kind, name)) in entries.enumerate().skip(offset.max(0) as usize) {
+            // The offset passed to the next call is that of the next entry
+            if reply.add(child, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn mount_options() -> Vec<MountOption> {
+    vec![
+        MountOption::RO,
+        MountOption::FSName("gwynn".to_string()),
+        MountOption::DefaultPermissions,
+    ]
+}
+
+/// Mounts the filesystem read-only at the given directory, blocking until it is unmounted
+pub fn mount(
+    fs: Arc<Filesystem>,
+    mountpoint: &Path,
+    views: Vec<Box<dyn SyntheticView>>,
+) -> Result<()> {
+    fuser::mount2(
+        GameFs::new(fs, views, mountpoint)?,
+        mountpoint,
+        &mount_options(),
+    )?;
+    Ok(())
+}
+
+/// Mounts the filesystem read-only in the background, it is unmounted when the returned session is dropped
+pub fn spawn_mount(
+    fs: Arc<Filesystem>,
+    mountpoint: &Path,
+    views: Vec<Box<dyn SyntheticView>>,
+) -> Result<fuser::BackgroundSession> {
+    Ok(fuser::spawn_mount2(
+        GameFs::new(fs, views, mountpoint)?,
+        mountpoint,
+        &mount_options(),
+    )?)
+}
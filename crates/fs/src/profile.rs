@@ -0,0 +1,146 @@
+use gwynn_mpk::compression::Encryption;
+use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
+
+use crate::apk::InstalledApp;
+
+/// Describes where and how a specific build of the game stores its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameProfile {
+    /// Human readable name of the build
+    pub name: String,
+    pub package_name: String,
+    /// Directory containing the patch files, relative to the app's external data directory (`Android/data/<package>`)
+    pub patch_dir: String,
+    pub patch_naming: PatchNaming,
+    pub encryption: Encryption,
+}
+
+/// Naming scheme of the numbered patch files, eg. `Patch.mpkinfo`, `Patch1.mpkinfo`, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchNaming {
+    pub prefix: String,
+    /// Whether the first patch file omits its index (`Patch.mpkinfo` rather than `Patch0.mpkinfo`)
+    pub unnumbered_first: bool,
+}
+
+impl PatchNaming {
+    /// Returns the name of the patch file with the given index and extension (eg. `mpkinfo`)
+    pub fn file_name(&self, index: usize, extension: &str) -> String {
+        match index {
+            0 if self.unnumbered_first => format!("{}.{extension}", self.prefix),
+            _ => format!("{}{index}.{extension}", self.prefix),
+        }
+    }
+}
+
+impl Default for PatchNaming {
+    fn default() -> Self {
+        Self {
+            prefix: "Patch".to_string(),
+            unnumbered_first: true,
+        }
+    }
+}
+
+impl GameProfile {
+    /// Destiny: Rising, global release
+    pub fn global() -> Self {
+        Self {
+            name: "Destiny: Rising (Global)".to_string(),
+            package_name: "com.netease.g108na".to_string(),
+            patch_dir: "files/LocalData/Patch".to_string(),
+            patch_naming: PatchNaming::default(),
+            encryption: Encryption::Beta,
+        }
+    }
+
+    /// Profiles of the builds whose package name and patch layout are known.
+    ///
+    /// Only the global release is included so far. Regional builds can be described with a custom profile, and passed
+    /// to [`Self::detect`] or [`crate::Filesystem::open_from_ext4_with_profile`].
+    pub fn builtin() -> Vec<Self> {
+        vec![Self::global()]
+    }
+
+    /// Picks the first built-in profile whose package is installed
+    pub fn detect_from_apps(apps: &[InstalledApp]) -> Option<Self> {
+        Self::detect(&Self::builtin(), apps)
+    }
+
+    /// Picks the first of the given profiles whose package is installed
+    pub fn detect(profiles: &[Self], apps: &[InstalledApp]) -> Option<Self> {
+        profiles
+            .iter()
+            .find(|p| apps.iter().any(|app| app.package_name == p.package_name))
+            .cloned()
+    }
+
+    /// Returns the app's external data directory under the given storage root (eg. `/media/0`)
+    pub fn data_dir(&self, storage_root: &UnixPath) -> UnixPathBuf {
+        storage_root
+            .join("Android")
+            .join("data")
+            .join(&self.package_name)
+    }
+
+    /// Returns the patch directory under the given storage root (eg. `/media/0`)
+    pub fn patch_dir(&self, storage_root: &UnixPath) -> UnixPathBuf {
+        self.data_dir(storage_root).join(&self.patch_dir)
+    }
+}
+
+impl Default for GameProfile {
+    fn default() -> Self {
+        Self::global()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn app(package_name: &str) -> InstalledApp {
+        InstalledApp {
+            package_name: package_name.to_string(),
+            path: UnixPathBuf::from(format!("/data/app/{package_name}-1")),
+            signature: None,
+            split_configs: HashSet::new(),
+            split_packs: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn detects_installed_profiles() {
+        let apps = [app("com.example.other"), app("com.netease.g108na")];
+        assert_eq!(
+            GameProfile::detect_from_apps(&apps),
+            Some(GameProfile::global())
+        );
+        assert_eq!(GameProfile::detect_from_apps(&apps[..1]), None);
+
+        // Custom profiles for builds that aren't built in
+        let regional = GameProfile {
+            name: "Regional".to_string(),
+            package_name: "com.example.other".to_string(),
+            ..GameProfile::global()
+        };
+        let profiles = [regional.clone(), GameProfile::global()];
+        assert_eq!(GameProfile::detect(&profiles, &apps), Some(regional));
+    }
+
+    #[test]
+    fn patch_paths() {
+        let profile = GameProfile::global();
+        assert_eq!(
+            profile.patch_dir(UnixPath::new("/media/0")),
+            UnixPath::new("/media/0/Android/data/com.netease.g108na/files/LocalData/Patch")
+        );
+        assert_eq!(
+            profile.patch_naming.file_name(0, "mpkinfo"),
+            "Patch.mpkinfo"
+        );
+        assert_eq!(profile.patch_naming.file_name(2, "mpk"), "Patch2.mpk");
+    }
+}
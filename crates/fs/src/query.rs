@@ -0,0 +1,386 @@
+//! Searching the asset index by path, type, size, compression and patch
+
+use std::cmp::Ordering;
+
+use gwynn_mpk::compression::CompressionType;
+use log::debug;
+use regex::Regex;
+use unix_path::PathBuf as UnixPathBuf;
+
+use crate::{Filesystem, filetype::FileType};
+
+pub enum PathMatcher {
+    /// Glob pattern, `*` doesn't match path separators, use `**` to match across directories
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl PathMatcher {
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            PathMatcher::Glob(pattern) => pattern.matches_with(
+                path,
+                glob::MatchOptions {
+                    require_literal_separator: true,
+                    ..Default::default()
+                },
+            ),
+            PathMatcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// No particular order
+    #[default]
+    None,
+    Path,
+    Size,
+    FileType,
+    PatchIndex,
+}
+
+/// A search over the files of a [`Filesystem`]. All set filters have to match.
+///
+/// ```ignore
+/// let query = Query::glob("**/*.1")?
+///     .with_file_types(&[FileType::Texture])
+///     .with_min_size(1024 * 1024)
+///     .with_sort(SortBy::Size, true);
+/// for m in fs.query(&query) {
+///     println!("{} ({} bytes)", m.path.display(), m.size);
+/// }
+/// ```
+#[derive(Default)]
+pub struct Query {
+    pub path: Option<PathMatcher>,
+    /// Matches any of the given types, all types if empty
+    pub file_types: Vec<FileType>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Matches any of the given compression types, all if empty. Files without a compression header match
+    /// [`CompressionType::None`].
+    ///
+    /// Filtering by compression requires reading the header of every file that passes the other filters.
+    pub compression: Vec<CompressionType>,
+    /// Matches files stored in any of the given patches, all if empty
+    pub patch_indices: Vec<usize>,
+    pub sort_by: SortBy,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches paths against a glob pattern, eg. `**/*.anim`
+    pub fn glob(pattern: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: Some(PathMatcher::Glob(glob::Pattern::new(pattern)?)),
+            ..Default::default()
+        })
+    }
+
+    /// Matches paths against a regular expression
+    pub fn regex(pattern: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: Some(PathMatcher::Regex(Regex::new(pattern)?)),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_file_types(mut self, file_types: &[FileType]) -> Self {
+        self.file_types = file_types.to_vec();
+        self
+    }
+
+    pub fn with_min_size(mut self, size: u64) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    pub fn with_max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: &[CompressionType]) -> Self {
+        self.compression = compression.to_vec();
+        self
+    }
+
+    pub fn with_patch_indices(mut self, indices: &[usize]) -> Self {
+        self.patch_indices = indices.to_vec();
+        self
+    }
+
+    pub fn with_sort(mut self, sort_by: SortBy, descending: bool) -> Self {
+        self.sort_by = sort_by;
+        self.descending = descending;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryMatch<'a> {
+    pub path: &'a UnixPathBuf,
+    pub file_type: FileType,
+    /// Size of the file as stored in the package
+    pub size: u64,
+    /// Index of the patch the file is stored in, `None` for files in resource packages
+    pub patch_index: Option<usize>,
+    /// Only detected when filtering by compression, files without a compression header are [`CompressionType::None`]
+    pub compression: Option<CompressionType>,
+}
+
+impl Filesystem {
+    /// Returns all files matching the query
+    pub fn query(&self, query: &Query) -> Vec<QueryMatch<'_>> {
+        let mut matches = vec![];
+        for (file_type, paths) in self.iter_types() {
+            if !query.file_types.is_empty() && !query.file_types.contains(&file_type) {
+                continue;
+            }
+
+            for path in paths {
+                let Some(pointer) = self.paths.get(path) else {
+                    continue;
+                };
+
+                let size = pointer.size() as u64;
+                if query.min_size.is_some_and(|min| size < min)
+                    || query.max_size.is_some_and(|max| size > max)
+                {
+                    continue;
+                }
+
+                let patch_index = pointer.patch_index();
+                if !query.patch_indices.is_empty()
+                    && !patch_index.is_some_and(|i| query.patch_indices.contains(&i))
+                {
+                    continue;
+                }
+
+                if let Some(matcher) = &query.path
+                    && !matcher.is_match(&path.to_string_lossy())
+                {
+                    continue;
+                }
+
+                let mut compression = None;
+                if !query.compression.is_empty() {
+                    let detected = match self.compression_type(path) {
+                        Ok(c) => c.unwrap_or(CompressionType::None),
+                        Err(e) => {
+                            debug!("Failed to detect compression of {}: {e}", path.display());
+                            continue;
+                        }
+                    };
+                    if !query.compression.contains(&detected) {
+                        continue;
+                    }
+                    compression = Some(detected);
+                }
+
+                matches.push(QueryMatch {
+                    path,
+                    file_type,
+                    size,
+                    patch_index,
+                    compression,
+                });
+            }
+        }
+
+        if query.sort_by != SortBy::None {
+            // Only the sort key is reversed, matches with equal keys stay in ascending path order
+            matches.sort_by(|a, b| {
+                let ordering = match query.sort_by {
+                    SortBy::None => Ordering::Equal,
+                    SortBy::Path => a.path.cmp(b.path),
+                    SortBy::Size => a.size.cmp(&b.size),
+                    SortBy::FileType => a.file_type.cmp(&b.file_type),
+                    SortBy::PatchIndex => a.patch_index.cmp(&b.patch_index),
+                };
+                let ordering = if query.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                };
+                ordering.then_with(|| a.path.cmp(b.path))
+            });
+        }
+
+        if let Some(limit) = query.limit {
+            matches.truncate(limit);
+        }
+
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Ext4Builder, open_filesystem};
+
+    /// Paths start with a two letter directory, which are stored without obfuscation
+    fn test_filesystem() -> Filesystem {
+        let disk = Ext4Builder::default()
+            .patch(
+                0,
+                &[
+                    ("an/walk.anim", &[0; 100]),
+                    ("an/run.anim", &[b"ZZZ4".as_slice(), &[0; 296]].concat()),
+                    ("au/a.wem", &[b"NNNN".as_slice(), &[0; 196]].concat()),
+                    ("da/config.json", &[0; 300]),
+                ],
+            )
+            .patch(
+                1,
+                &[
+                    ("an/idle.anim", &[b"ZSTD".as_slice(), &[0; 196]].concat()),
+                    ("da/config.json", &[0; 50]),
+                ],
+            )
+            .build();
+        open_filesystem(disk, None)
+    }
+
+    fn paths(fs: &Filesystem, query: &Query) -> Vec<String> {
+        fs.query(query)
+            .iter()
+            .map(|m| m.path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn glob() {
+        let fs = test_filesystem();
+        let sorted = |query: Query| query.with_sort(SortBy::Path, false);
+
+        assert_eq!(
+            paths(&fs, &sorted(Query::glob("an/*.anim").unwrap())),
+            ["an/idle.anim", "an/run.anim", "an/walk.anim"]
+        );
+        assert_eq!(
+            paths(&fs, &sorted(Query::glob("**/*.json").unwrap())),
+            ["da/config.json"]
+        );
+        // `*` doesn't cross directories
+        assert!(paths(&fs, &Query::glob("*.anim").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn regex() {
+        let fs = test_filesystem();
+        let query = Query::regex("^(au|da)/")
+            .unwrap()
+            .with_sort(SortBy::Path, false);
+        assert_eq!(paths(&fs, &query), ["au/a.wem", "da/config.json"]);
+    }
+
+    #[test]
+    fn filters() {
+        let fs = test_filesystem();
+        let sorted = |query: Query| query.with_sort(SortBy::Path, false);
+
+        let query = Query::new()
+            .with_file_types(&[FileType::Animation])
+            .with_min_size(150);
+        assert_eq!(paths(&fs, &sorted(query)), ["an/idle.anim", "an/run.anim"]);
+
+        // The newer patch overrides the size of the config
+        let query = Query::new().with_max_size(100);
+        assert_eq!(
+            paths(&fs, &sorted(query)),
+            ["an/walk.anim", "da/config.json"]
+        );
+
+        let query = Query::new().with_patch_indices(&[1]);
+        assert_eq!(
+            paths(&fs, &sorted(query)),
+            ["an/idle.anim", "da/config.json"]
+        );
+    }
+
+    #[test]
+    fn compression_filter() {
+        let fs = test_filesystem();
+        let sorted = |query: Query| query.with_sort(SortBy::Path, false);
+
+        let query = Query::new().with_compression(&[CompressionType::Lz4, CompressionType::Zstd]);
+        let matches = fs.query(&sorted(query));
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.path.to_str().unwrap(), m.compression))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("an/idle.anim", Some(CompressionType::Zstd)),
+                ("an/run.anim", Some(CompressionType::Lz4)),
+            ]
+        );
+
+        // Files with a `NNNN` header and files without any header are both uncompressed
+        let query = Query::new().with_compression(&[CompressionType::None]);
+        assert_eq!(
+            paths(&fs, &sorted(query)),
+            ["an/walk.anim", "au/a.wem", "da/config.json"]
+        );
+    }
+
+    #[test]
+    fn sort() {
+        let fs = test_filesystem();
+
+        // Ties stay in ascending path order when descending
+        let query = Query::new().with_sort(SortBy::Size, true);
+        assert_eq!(
+            paths(&fs, &query),
+            [
+                "an/run.anim",
+                "an/idle.anim",
+                "au/a.wem",
+                "an/walk.anim",
+                "da/config.json"
+            ]
+        );
+
+        let query = Query::new().with_sort(SortBy::Size, false).with_limit(2);
+        assert_eq!(paths(&fs, &query), ["da/config.json", "an/walk.anim"]);
+
+        let query = Query::new().with_sort(SortBy::PatchIndex, true);
+        assert_eq!(
+            paths(&fs, &query),
+            [
+                "an/idle.anim",
+                "da/config.json",
+                "an/run.anim",
+                "an/walk.anim",
+                "au/a.wem"
+            ]
+        );
+
+        let query = Query::new().with_sort(SortBy::Path, true);
+        assert_eq!(
+            paths(&fs, &query),
+            [
+                "da/config.json",
+                "au/a.wem",
+                "an/walk.anim",
+                "an/run.anim",
+                "an/idle.anim"
+            ]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use bootsector::pio::ReadAt;
+use ext4::Ext4Reader;
+use log::{debug, info};
+
+use crate::sources::{
+    BlockDevice, DiskSlice, qcow2::Qcow2Disk, sparse::SparseImage, vdi::VdiDisk, vhd::VhdDisk,
+    vhdx::VhdxDisk, vmdk::VmdkDisk,
+};
+
+/// Offset of the ext4 superblock magic (`s_magic`) from the start of a partition
+const EXT4_MAGIC_OFFSET: u64 = 1024 + 0x38;
+const EXT4_MAGIC: u16 = 0xEF53;
+/// Backing files nested deeper than this are assumed to be a mistake
+const MAX_BACKING_CHAIN: usize = 16;
+
+/// Disk images that aren't tied to a specific emulator, such as `userdata.img` dumps from devices.
+///
+/// Supported layouts:
+/// - Raw ext4 partition images
+/// - Whole-disk images with an MBR or GPT partition table
+/// - Android sparse images containing either of the above
+/// - Virtual machine disks (VDI, VMDK, VHD, VHDX and qcow2) containing either of the above
+pub struct DiskImage;
+
+impl DiskImage {
+    pub fn open_ext4(path: &Path) -> anyhow::Result<Ext4Reader<BlockDevice>> {
+        let device = Self::open_device(path)?;
+        Self::open_ext4_from_device(device)
+    }
+
+    /// Opens a disk image as a block device, unwrapping any sparse or virtual machine disk container format
+    pub fn open_device(path: &Path) -> anyhow::Result<BlockDevice> {
+        Self::open_device_in_chain(path, &mut vec![])
+    }
+
+    /// Opens a disk image that is part of a chain of qcow2 backing files, `chain` holds the images referencing it
+    fn open_device_in_chain(path: &Path, chain: &mut Vec<PathBuf>) -> anyhow::Result<BlockDevice> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open disk image {}", path.display()))?;
+        let file_size = file.metadata()?.len();
+
+        let canonical_path = path.canonicalize()?;
+        anyhow::ensure!(
+            !chain.contains(&canonical_path),
+            "Backing file chain loops back to {}",
+            path.display()
+        );
+        anyhow::ensure!(
+            chain.len() < MAX_BACKING_CHAIN,
+            "Backing file chain is longer than {MAX_BACKING_CHAIN} images"
+        );
+        chain.push(canonical_path);
+
+        if SparseImage::is_sparse(&file) {
+            info!("{} is an Android sparse image", path.display());
+            let sparse = SparseImage::new(file).context("Failed to read sparse image")?;
+            Ok(BlockDevice::new(sparse))
+        } else if VdiDisk::is_vdi(&file) {
+            info!("{} is a VirtualBox disk image", path.display());
+            Ok(BlockDevice::new(
+                VdiDisk::new(file).context("Failed to read VDI")?,
+            ))
+        } else if VmdkDisk::is_vmdk(&file) {
+            info!("{} is a VMware disk image", path.display());
+            Ok(BlockDevice::new(VmdkDisk::open(path)?))
+        } else if VhdxDisk::is_vhdx(&file) {
+            info!("{} is a Hyper-V disk image", path.display());
+            Ok(BlockDevice::new(VhdxDisk::new(file)?))
+        } else if Qcow2Disk::is_qcow2(&file) {
+            info!("{} is a qcow2 disk image", path.display());
+            let backing = match Qcow2Disk::backing_file_name(&file)? {
+                Some(name) => {
+                    let backing_path = path.parent().unwrap_or(Path::new(".")).join(name);
+                    debug!("Opening qcow2 backing file {}", backing_path.display());
+                    Some(
+                        Self::open_device_in_chain(&backing_path, chain).with_context(|| {
+                            format!("Failed to open backing file {}", backing_path.display())
+                        })?,
+                    )
+                }
+                None => None,
+            };
+            Ok(BlockDevice::new(Qcow2Disk::new(file, backing)?))
+        } else if is_vhd(&file, file_size) {
+            info!("{} is a Virtual PC disk image", path.display());
+            Ok(BlockDevice::new(VhdDisk::open(file, file_size)?))
+        } else {
+            Ok(BlockDevice::new(file))
+        }
+    }
+
+    /// Opens an ext4 filesystem from an unsparsed block device.
+    ///
+    /// If the device doesn't start with an ext4 superblock, the largest partition in its partition table is used instead.
+    pub fn open_ext4_from_device<R: ReadAt + Send + Sync + 'static>(
+        device: R,
+    ) -> anyhow::Result<Ext4Reader<BlockDevice>> {
+        if is_ext4(&device) {
+            debug!("Found ext4 superblock at the start of the image");
+            return Ext4Reader::new(BlockDevice::new(device))
+                .context("Failed to open ext4 filesystem");
+        }
+
+        let largest_partition =
+            bootsector::list_partitions(&device, &bootsector::Options::default())
+                .context("Image is not an ext4 filesystem and has no partition table")?
+                .into_iter()
+                .max_by_key(|p| p.len)
+                .context("Disk has no partitions")?;
+
+        let slice = DiskSlice::new(device, largest_partition.first_byte, largest_partition.len);
+        anyhow::ensure!(
+            is_ext4(&slice),
+            "Largest partition does not contain an ext4 filesystem"
+        );
+
+        Ext4Reader::new(BlockDevice::new(slice)).context("Failed to open ext4 filesystem")
+    }
+}
+
+fn is_ext4<R: ReadAt>(reader: &R) -> bool {
+    let mut magic = [0u8; 2];
+    reader.read_exact_at(EXT4_MAGIC_OFFSET, &mut magic).is_ok()
+        && u16::from_le_bytes(magic) == EXT4_MAGIC
+}
+
+/// Fixed VHDs are raw images with a footer at the end, dynamic ones also have a copy of it at the start
+fn is_vhd<R: ReadAt>(reader: &R, file_size: u64) -> bool {
+    let mut cookie = [0u8; 8];
+    VhdDisk::is_vhd(reader)
+        || (file_size >= 512
+            && reader.read_exact_at(file_size - 512, &mut cookie).is_ok()
+            && &cookie == b"conectix")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 2 qcow2 header without any clusters, pointing at the given backing file
+    fn qcow2_header(backing_name: &str) -> Vec<u8> {
+        let mut header = crate::sources::qcow2::QCOW2_MAGIC.to_vec();
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&72u64.to_be_bytes());
+        header.extend_from_slice(&(backing_name.len() as u32).to_be_bytes());
+        header.extend_from_slice(&9u32.to_be_bytes());
+        header.resize(72, 0);
+        header.extend_from_slice(backing_name.as_bytes());
+        header
+    }
+
+    #[test]
+    fn rejects_qcow2_backing_file_loops() {
+        let dir = std::env::temp_dir().join(format!("gwynn-backing-loop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("self.qcow2"), qcow2_header("self.qcow2")).unwrap();
+        std::fs::write(dir.join("a.qcow2"), qcow2_header("b.qcow2")).unwrap();
+        std::fs::write(dir.join("b.qcow2"), qcow2_header("a.qcow2")).unwrap();
+
+        for name in ["self.qcow2", "a.qcow2"] {
+            let error = DiskImage::open_device(&dir.join(name)).err().unwrap();
+            assert!(format!("{error:#}").contains("loops back"), "{error:#}");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
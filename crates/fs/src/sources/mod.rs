@@ -1 +1,106 @@
+use bootsector::pio::ReadAt;
+
+pub mod image;
 pub mod mumuplayer;
+pub mod qcow2;
+pub mod sparse;
+pub mod vdi;
+pub mod vhd;
+pub mod vhdx;
+pub mod vmdk;
+
+/// Type-erased block device that an ext4 filesystem can be read from.
+///
+/// All reads are positional, so the device can be shared between threads.
+pub struct BlockDevice(Box<dyn ReadAt + Send + Sync>);
+
+impl BlockDevice {
+    pub fn new<R: ReadAt + Send + Sync + 'static>(reader: R) -> Self {
+        Self(Box::new(reader))
+    }
+}
+
+impl ReadAt for BlockDevice {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read_at(pos, buf)
+    }
+}
+
+/// A byte range of an underlying block device, such as a single partition of a disk image.
+pub struct DiskSlice<R: ReadAt> {
+    inner: R,
+    offset: u64,
+    len: u64,
+}
+
+impl<R: ReadAt> DiskSlice<R> {
+    pub fn new(inner: R, offset: u64, len: u64) -> Self {
+        Self { inner, offset, len }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<R: ReadAt> ReadAt for DiskSlice<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len {
+            return Ok(0);
+        }
+
+        let available = (self.len - pos).min(buf.len() as u64) as usize;
+        self.inner.read_at(self.offset + pos, &mut buf[..available])
+    }
+}
+
+/// Reads into `buf` until it is full or the end of the reader is reached, returning the number of bytes read.
+pub(crate) fn read_up_to<R: ReadAt + ?Sized>(
+    reader: &R,
+    mut pos: u64,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read_at(pos, &mut buf[total..])? {
+            0 => break,
+            n => {
+                total += n;
+                pos += n as u64;
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+/// Disk image held in memory, for building synthetic images in tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryDisk(pub Vec<u8>);
+
+#[cfg(test)]
+impl MemoryDisk {
+    /// Writes `bytes` at the given offset, growing the disk as needed
+    pub fn write(&mut self, offset: usize, bytes: &[u8]) {
+        let end = offset + bytes.len();
+        if self.0.len() < end {
+            self.0.resize(end, 0);
+        }
+        self.0[offset..end].copy_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+impl ReadAt for MemoryDisk {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.0.get(pos as usize..).unwrap_or_default();
+        let count = data.len().min(buf.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+}
@@ -1,14 +1,107 @@
-use std::path::{Path, PathBuf};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use ext4::Ext4Reader;
+use log::debug;
+
+use crate::{
+    error::{FsError, Result},
+    sources::{BlockDevice, image::DiskImage},
+};
+
+/// Overrides the MuMuPlayer install directory. May point to either the install directory or its `vms` directory.
+pub const INSTALL_DIR_ENV: &str = "GWYNN_MUMU_DIR";
+/// Selects a specific VM folder (`vms/<name>`) instead of the one with the biggest data disk.
+///
+/// May be either a path to the folder, or just its name if the install directory can be found.
+pub const VM_ENV: &str = "GWYNN_MUMU_VM";
+
+/// Locations of `build.prop` on a system partition, with and without the system-as-root layout
+const BUILD_PROP_PATHS: [&str; 2] = ["/system/build.prop", "/build.prop"];
 
 pub struct MumuPlayer;
 
+/// A MuMuPlayer virtual machine folder
+#[derive(Debug, Clone)]
+pub struct MumuVm {
+    /// Name of the VM folder, eg. `MuMuPlayerGlobal-12.0-0`
+    pub name: String,
+    pub path: PathBuf,
+    /// Size of the `data.vdi` disk image on the host
+    pub data_size: u64,
+}
+
+impl MumuVm {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let data_size = std::fs::metadata(path.join("data.vdi"))
+            .map_err(|_| FsError::VmNotFound(path.to_path_buf()))?
+            .len();
+
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            data_size,
+        })
+    }
+
+    /// Reads the Android version from `build.prop` on the VM's system disk.
+    ///
+    /// Every disk image in the VM folder besides the data disk is tried, as the system disk isn't named consistently.
+    /// Returns `None` if none of them holds a `build.prop`.
+    pub fn read_android_version(&self) -> Option<String> {
+        let mut disks: Vec<PathBuf> = std::fs::read_dir(&self.path)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("vdi"))
+                    && path.file_name().is_some_and(|n| n != "data.vdi")
+            })
+            .collect();
+        disks.sort();
+
+        disks.iter().find_map(|path| {
+            let ext4 = DiskImage::open_ext4(path)
+                .inspect_err(|e| debug!("Not reading build.prop from {}: {e:#}", path.display()))
+                .ok()?;
+            BUILD_PROP_PATHS.iter().find_map(|build_prop| {
+                let mut data = String::new();
+                ext4.open(build_prop).ok()?.read_to_string(&mut data).ok()?;
+                android_version_from_build_prop(&data)
+            })
+        })
+    }
+
+    pub fn data_vdi_path(&self) -> PathBuf {
+        self.path.join("data.vdi")
+    }
+
+    pub fn open_ext4(&self) -> Result<Ext4Reader<BlockDevice>> {
+        MumuPlayer::open_vm_ext4(&self.path)
+    }
+}
+
 impl MumuPlayer {
-    fn base_path() -> anyhow::Result<PathBuf> {
-        let appdata =
-            std::env::var_os("APPDATA").context("APPDATA environment variable not set")?;
+    fn base_path() -> Result<PathBuf> {
+        Self::find_base_path().map_err(FsError::MumuNotInstalled)
+    }
+
+    fn find_base_path() -> anyhow::Result<PathBuf> {
+        if let Some(dir) = std::env::var_os(INSTALL_DIR_ENV) {
+            return Ok(PathBuf::from(dir));
+        }
+
+        let appdata = std::env::var_os("APPDATA").with_context(|| {
+            format!("APPDATA environment variable not set, set {INSTALL_DIR_ENV} to the MuMuPlayer install directory instead")
+        })?;
         let path = PathBuf::from(appdata)
             .join("Netease")
             .join("MuMuPlayerGlobal")
@@ -22,14 +115,24 @@ impl MumuPlayer {
             .context("MuMuPlayer install_path not found or invalid")
     }
 
-    fn vms_path() -> anyhow::Result<PathBuf> {
-        Ok(Self::base_path()?.join("vms"))
+    fn vms_path() -> Result<PathBuf> {
+        let base_path = Self::base_path()?;
+        let vms_path = base_path.join("vms");
+        if vms_path.is_dir() {
+            Ok(vms_path)
+        } else {
+            // The override may point at the vms directory directly
+            Ok(base_path)
+        }
     }
 
-    pub fn iter_vms() -> anyhow::Result<impl Iterator<Item = PathBuf>> {
+    pub fn iter_vms() -> Result<impl Iterator<Item = PathBuf>> {
         let vms_path = Self::vms_path()?;
         if !vms_path.exists() {
-            anyhow::bail!("MuMuPlayer vms path does not exist: {}", vms_path.display());
+            return Err(FsError::MumuNotInstalled(anyhow::anyhow!(
+                "MuMuPlayer vms path does not exist: {}",
+                vms_path.display()
+            )));
         }
         let entries = std::fs::read_dir(vms_path)?
             .filter_map(|res| res.ok())
@@ -38,54 +141,104 @@ impl MumuPlayer {
         Ok(entries)
     }
 
-    pub fn get_biggest_vm() -> anyhow::Result<Option<PathBuf>> {
-        let mut biggest: Option<(PathBuf, u64)> = None;
-        for vm_path in Self::iter_vms()? {
-            let mpk_path = vm_path.join("data.vdi");
-            if mpk_path.exists() {
-                let metadata = std::fs::metadata(&mpk_path)?;
-                let size = metadata.len();
-                if biggest.is_none() || size > biggest.as_ref().unwrap().1 {
-                    biggest = Some((vm_path, size));
-                }
-            }
-        }
-        Ok(biggest.map(|(path, _)| path))
+    /// Lists all VMs that have a data disk, sorted by name
+    pub fn list_vms() -> Result<Vec<MumuVm>> {
+        let mut vms: Vec<MumuVm> = Self::iter_vms()?
+            .filter_map(|path| MumuVm::from_path(&path).ok())
+            .collect();
+        vms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(vms)
+    }
+
+    pub fn get_biggest_vm() -> Result<Option<PathBuf>> {
+        Ok(Self::list_vms()?
+            .into_iter()
+            .max_by_key(|vm| vm.data_size)
+            .map(|vm| vm.path))
     }
 
-    pub fn open_vm_ext4(path: &Path) -> anyhow::Result<Option<Ext4Reader<vdi::slice::OwnedSlice>>> {
-        if !path.exists() {
-            anyhow::bail!("MuMuPlayer VM path does not exist: {}", path.display());
+    /// Returns the VM selected through [`VM_ENV`], or the one with the biggest data disk if it isn't set
+    pub fn get_selected_vm() -> Result<Option<PathBuf>> {
+        let Some(vm) = std::env::var_os(VM_ENV) else {
+            return Self::get_biggest_vm();
+        };
+
+        let path = PathBuf::from(&vm);
+        if path.is_dir() {
+            return Ok(Some(path));
         }
+
+        let path = Self::vms_path()?.join(&vm);
+        if !path.is_dir() {
+            return Err(FsError::VmNotFound(path));
+        }
+        Ok(Some(path))
+    }
+
+    pub fn open_vm_ext4(path: &Path) -> Result<Ext4Reader<BlockDevice>> {
         let data_path = path.join("data.vdi");
         if !data_path.exists() {
-            anyhow::bail!(
-                "MuMuPlayer VM data.vdi does not exist: {}",
-                data_path.display()
-            );
+            return Err(FsError::VmNotFound(path.to_path_buf()));
         }
 
-        let file = std::fs::File::open(data_path).context("Failed to open VDI file")?;
-        let mut disk = vdi::VdiDisk::open(Box::new(file))?;
-        let largest_partition =
-            bootsector::list_partitions(&mut disk, &bootsector::Options::default())?
-                .into_iter()
-                .max_by_key(|p| p.len)
-                .context("Disk has no partitions")?;
-
-        let range =
-            largest_partition.first_byte..(largest_partition.first_byte + largest_partition.len);
-        let slice = disk.slice_owned(range)?;
-        let ext4 = Ext4Reader::new(slice).context("Failed to open ext4 filesystem")?;
-        Ok(Some(ext4))
+        let ext4 = DiskImage::open_ext4(&data_path).map_err(|source| FsError::DiskImage {
+            path: data_path,
+            source,
+        })?;
+        Ok(ext4)
     }
 
-    pub fn open_biggest_vm_ext4() -> anyhow::Result<Option<Ext4Reader<vdi::slice::OwnedSlice>>> {
+    pub fn open_biggest_vm_ext4() -> Result<Option<Ext4Reader<BlockDevice>>> {
         let path = Self::get_biggest_vm()?;
         let Some(path) = path else {
             return Ok(None);
         };
 
-        Self::open_vm_ext4(&path)
+        Self::open_vm_ext4(&path).map(Some)
+    }
+
+    /// Opens the VM selected through [`VM_ENV`], or the one with the biggest data disk if it isn't set
+    pub fn open_selected_vm_ext4() -> Result<Option<Ext4Reader<BlockDevice>>> {
+        let Some(path) = Self::get_selected_vm()? else {
+            return Ok(None);
+        };
+
+        Self::open_vm_ext4(&path).map(Some)
+    }
+}
+
+fn android_version_from_build_prop(build_prop: &str) -> Option<String> {
+    let property = |name: &str| {
+        build_prop.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == name && !value.trim().is_empty()).then(|| value.trim().to_string())
+        })
+    };
+
+    property("ro.build.version.release").or_else(|| property("ro.system.build.version.release"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_android_version_from_build_prop() {
+        let build_prop =
+            "# begin build properties\nro.build.version.sdk=32\nro.build.version.release=12\n";
+        assert_eq!(
+            android_version_from_build_prop(build_prop).as_deref(),
+            Some("12")
+        );
+
+        let build_prop = "ro.system.build.version.release=9\nro.build.version.release=\n";
+        assert_eq!(
+            android_version_from_build_prop(build_prop).as_deref(),
+            Some("9")
+        );
+        assert_eq!(
+            android_version_from_build_prop("ro.product.model=MuMu\n"),
+            None
+        );
     }
 }
@@ -0,0 +1,409 @@
+//! Read-only reader for QEMU copy-on-write (qcow2) disk images, as used by Android Studio AVDs.
+//!
+//! AVD userdata images are usually overlays on top of a raw `userdata-qemu.img`, so backing files are supported.
+//! Compressed clusters are supported for the default deflate compression. Encrypted images and zstd compression are not.
+
+use std::{
+    io::{Cursor, Read},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+
+use crate::sources::{BlockDevice, read_up_to};
+
+pub const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+
+const L1_OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+const L2_OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+const L2_COMPRESSED: u64 = 1 << 62;
+const L2_ZERO: u64 = 1;
+
+/// Largest L1 table (in bytes) and backing file name QEMU accepts, anything larger comes from a corrupted image
+const MAX_L1_SIZE: u64 = 32 * 1024 * 1024;
+const MAX_BACKING_FILE_NAME: u32 = 1023;
+
+const INCOMPATIBLE_DIRTY: u64 = 1 << 0;
+const INCOMPATIBLE_COMPRESSION_TYPE: u64 = 1 << 3;
+
+#[binread]
+#[br(big, magic = b"QFI\xfb")]
+#[derive(Debug, Clone)]
+pub struct Qcow2Header {
+    #[br(assert(version == 2 || version == 3, "Unsupported qcow2 version {version}"))]
+    pub version: u32,
+    pub backing_file_offset: u64,
+    pub backing_file_size: u32,
+    #[br(assert((9..=21).contains(&cluster_bits), "Invalid qcow2 cluster size"))]
+    pub cluster_bits: u32,
+    pub size: u64,
+    pub crypt_method: u32,
+    pub l1_size: u32,
+    pub l1_table_offset: u64,
+    pub refcount_table_offset: u64,
+    pub refcount_table_clusters: u32,
+    pub nb_snapshots: u32,
+    pub snapshots_offset: u64,
+
+    // Version 3 fields, these are implicitly 0 for version 2 images
+    #[br(if(version >= 3))]
+    pub incompatible_features: u64,
+    #[br(if(version >= 3))]
+    pub compatible_features: u64,
+    #[br(if(version >= 3))]
+    pub autoclear_features: u64,
+}
+
+pub struct Qcow2Disk<R: ReadAt> {
+    inner: R,
+    header: Qcow2Header,
+    l1_table: Vec<u64>,
+    backing: Option<BlockDevice>,
+
+    /// The most recently decompressed cluster, as (host offset, data)
+    compressed_cache: Mutex<Option<(u64, Vec<u8>)>>,
+}
+
+impl<R: ReadAt> Qcow2Disk<R> {
+    pub fn is_qcow2(reader: &R) -> bool {
+        let mut magic = [0u8; 4];
+        reader.read_exact_at(0, &mut magic).is_ok() && &magic == QCOW2_MAGIC
+    }
+
+    /// Opens a qcow2 image.
+    ///
+    /// `backing` must be provided if the image has a backing file (see [`Qcow2Disk::backing_file_name`]),
+    /// unallocated clusters will read as zeroes otherwise.
+    pub fn new(inner: R, backing: Option<BlockDevice>) -> anyhow::Result<Self> {
+        let header = Self::read_header(&inner)?;
+        anyhow::ensure!(
+            header.crypt_method == 0,
+            "Encrypted qcow2 images are not supported"
+        );
+        anyhow::ensure!(
+            header.incompatible_features & INCOMPATIBLE_COMPRESSION_TYPE == 0,
+            "qcow2 images with non-deflate compression are not supported"
+        );
+        anyhow::ensure!(
+            header.incompatible_features & !INCOMPATIBLE_DIRTY == 0,
+            "qcow2 image uses unsupported features (0x{:X})",
+            header.incompatible_features
+        );
+        if header.incompatible_features & INCOMPATIBLE_DIRTY != 0 {
+            log::warn!("qcow2 image was not closed cleanly, reading anyway");
+        }
+
+        anyhow::ensure!(
+            header.l1_size as u64 * 8 <= MAX_L1_SIZE,
+            "qcow2 L1 table is too large ({} entries)",
+            header.l1_size
+        );
+        let mut l1_data = vec![0u8; header.l1_size as usize * 8];
+        inner
+            .read_exact_at(header.l1_table_offset, &mut l1_data)
+            .context("Failed to read qcow2 L1 table")?;
+        let l1_table = l1_data
+            .chunks_exact(8)
+            .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            inner,
+            header,
+            l1_table,
+            backing,
+            compressed_cache: Mutex::new(None),
+        })
+    }
+
+    fn read_header(inner: &R) -> anyhow::Result<Qcow2Header> {
+        let mut data = [0u8; 104];
+        inner
+            .read_exact_at(0, &mut data[..72])
+            .context("Failed to read qcow2 header")?;
+        // Version 2 headers are only 72 bytes long, the remaining fields are only read for version 3
+        let _ = inner.read_exact_at(72, &mut data[72..]);
+
+        Ok(Cursor::new(&data).read_be()?)
+    }
+
+    /// Reads the name of the backing file from the image header, if there is one.
+    ///
+    /// Relative names are relative to the directory containing the image.
+    pub fn backing_file_name(inner: &R) -> anyhow::Result<Option<String>> {
+        let header = Self::read_header(inner)?;
+        if header.backing_file_offset == 0 || header.backing_file_size == 0 {
+            return Ok(None);
+        }
+        anyhow::ensure!(
+            header.backing_file_size <= MAX_BACKING_FILE_NAME,
+            "qcow2 backing file name is too long ({} bytes)",
+            header.backing_file_size
+        );
+
+        let mut name = vec![0u8; header.backing_file_size as usize];
+        inner
+            .read_exact_at(header.backing_file_offset, &mut name)
+            .context("Failed to read qcow2 backing file name")?;
+        Ok(Some(String::from_utf8_lossy(&name).to_string()))
+    }
+
+    pub fn header(&self) -> &Qcow2Header {
+        &self.header
+    }
+
+    /// Size of the virtual disk in bytes
+    pub fn len(&self) -> u64 {
+        self.header.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.header.cluster_bits
+    }
+
+    /// Looks up the L2 entry for the cluster containing the given guest offset
+    fn l2_entry(&self, pos: u64) -> std::io::Result<u64> {
+        let l2_entries = self.cluster_size() / 8;
+        let cluster = pos >> self.header.cluster_bits;
+        let l1_index = (cluster / l2_entries) as usize;
+        let l2_index = cluster % l2_entries;
+
+        let Some(l2_table_offset) = self.l1_table.get(l1_index).map(|e| e & L1_OFFSET_MASK) else {
+            return Ok(0);
+        };
+        if l2_table_offset == 0 {
+            return Ok(0);
+        }
+
+        let mut entry = [0u8; 8];
+        self.inner
+            .read_exact_at(l2_table_offset + l2_index * 8, &mut entry)?;
+        Ok(u64::from_be_bytes(entry))
+    }
+
+    fn read_compressed(
+        &self,
+        entry: u64,
+        cluster_offset: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<()> {
+        let offset_bits = 62 - (self.header.cluster_bits - 8);
+        let host_offset = entry & ((1 << offset_bits) - 1);
+        let sectors = ((entry & (L2_COMPRESSED - 1)) >> offset_bits) + 1;
+
+        let mut cache = self.compressed_cache.lock().unwrap();
+        if cache.as_ref().map(|(o, _)| *o) != Some(host_offset) {
+            // The compressed length is only known in sectors, and the first sector may start mid-sector
+            let compressed_size = sectors * 512 - (host_offset & 511);
+            let mut compressed = vec![0u8; compressed_size as usize];
+            let read = read_up_to(&self.inner, host_offset, &mut compressed)?;
+            compressed.truncate(read);
+
+            let mut cluster = Vec::with_capacity(self.cluster_size() as usize);
+            flate2::read::DeflateDecoder::new(Cursor::new(compressed))
+                .take(self.cluster_size())
+                .read_to_end(&mut cluster)?;
+            cluster.resize(self.cluster_size() as usize, 0);
+            *cache = Some((host_offset, cluster));
+        }
+
+        let (_, cluster) = cache.as_ref().unwrap();
+        let start = cluster_offset as usize;
+        buf.copy_from_slice(&cluster[start..start + buf.len()]);
+        Ok(())
+    }
+}
+
+impl<R: ReadAt> ReadAt for Qcow2Disk<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let cluster_offset = pos % self.cluster_size();
+        let count = (self.len() - pos)
+            .min(buf.len() as u64)
+            .min(self.cluster_size() - cluster_offset) as usize;
+        let buf = &mut buf[..count];
+
+        let entry = self.l2_entry(pos)?;
+        if entry & L2_COMPRESSED != 0 {
+            self.read_compressed(entry, cluster_offset, buf)?;
+            return Ok(count);
+        }
+
+        let host_offset = entry & L2_OFFSET_MASK;
+        if entry & L2_ZERO != 0 {
+            buf.fill(0);
+            Ok(count)
+        } else if host_offset != 0 {
+            self.inner.read_at(host_offset + cluster_offset, buf)
+        } else if let Some(backing) = &self.backing {
+            // Reads past the end of the backing file return zeroes
+            let read = read_up_to(backing, pos, buf)?;
+            buf[read..].fill(0);
+            Ok(count)
+        } else {
+            buf.fill(0);
+            Ok(count)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::sources::MemoryDisk;
+
+    const CLUSTER_BITS: u32 = 9;
+    const CLUSTER: usize = 1 << CLUSTER_BITS;
+    const DISK_SIZE: usize = 64 * 1024;
+    /// Smaller than the disk, the rest of the disk reads as zeroes
+    const BACKING_SIZE: usize = 48 * 1024;
+
+    fn backing() -> MemoryDisk {
+        MemoryDisk((0..BACKING_SIZE).map(|i| (i % 251) as u8).collect())
+    }
+
+    fn compressed_cluster() -> Vec<u8> {
+        (0..CLUSTER).map(|i| (i * 3) as u8).collect()
+    }
+
+    fn header(l1_size: u32, backing_name: Option<&str>) -> Vec<u8> {
+        let (name_offset, name_size) = match backing_name {
+            Some(name) => (104u64, name.len() as u32),
+            None => (0, 0),
+        };
+
+        let mut header = QCOW2_MAGIC.to_vec();
+        header.extend_from_slice(&3u32.to_be_bytes());
+        header.extend_from_slice(&name_offset.to_be_bytes());
+        header.extend_from_slice(&name_size.to_be_bytes());
+        header.extend_from_slice(&CLUSTER_BITS.to_be_bytes());
+        header.extend_from_slice(&(DISK_SIZE as u64).to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes()); // crypt_method
+        header.extend_from_slice(&l1_size.to_be_bytes());
+        header.extend_from_slice(&(CLUSTER as u64).to_be_bytes()); // l1_table_offset
+        header.resize(104, 0);
+        header.extend_from_slice(backing_name.unwrap_or_default().as_bytes());
+        header
+    }
+
+    /// A v3 image with 512 byte clusters. The first L2 table maps guest cluster 0 and 3 to data clusters, leaves 1
+    /// unallocated, marks 2 as zero and stores 4 compressed. The second L1 entry has no L2 table at all.
+    fn image(backing_name: Option<&str>) -> MemoryDisk {
+        let mut disk = MemoryDisk::default();
+        disk.write(0, &header(2, backing_name));
+
+        // L1 table, with the copied flag set like QEMU does
+        disk.write(CLUSTER, &((2 * CLUSTER) as u64 | 1 << 63).to_be_bytes());
+        disk.write(CLUSTER + 8, &0u64.to_be_bytes());
+
+        let mut compressed = flate2::write::DeflateEncoder::new(vec![], Default::default());
+        compressed.write_all(&compressed_cluster()).unwrap();
+        let compressed = compressed.finish().unwrap();
+        assert!(compressed.len() <= CLUSTER);
+
+        let l2 = [
+            (3 * CLUSTER) as u64,
+            0,
+            L2_ZERO,
+            (4 * CLUSTER) as u64,
+            L2_COMPRESSED | (5 * CLUSTER) as u64,
+        ];
+        for (i, entry) in l2.iter().enumerate() {
+            disk.write(2 * CLUSTER + i * 8, &entry.to_be_bytes());
+        }
+        disk.write(3 * CLUSTER, &[0x11; CLUSTER]);
+        disk.write(4 * CLUSTER, &[0x33; CLUSTER]);
+        disk.write(5 * CLUSTER, &compressed);
+        disk
+    }
+
+    /// Contents of the virtual disk, with or without the backing file
+    fn expected(with_backing: bool) -> Vec<u8> {
+        let backing = backing().0;
+        (0..DISK_SIZE)
+            .map(|pos| match pos / CLUSTER {
+                0 => 0x11,
+                2 => 0,
+                3 => 0x33,
+                4 => compressed_cluster()[pos % CLUSTER],
+                _ if with_backing => backing.get(pos).copied().unwrap_or(0),
+                _ => 0,
+            })
+            .collect()
+    }
+
+    fn read(disk: &impl ReadAt, pos: usize, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        disk.read_exact_at(pos as u64, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_clusters_through_backing_file() {
+        let disk = Qcow2Disk::new(image(None), Some(BlockDevice::new(backing()))).unwrap();
+        assert_eq!(disk.len(), DISK_SIZE as u64);
+
+        let expected = expected(true);
+        assert_eq!(read(&disk, 0, DISK_SIZE), expected);
+        // Allocated into unallocated, zero into allocated and compressed into unallocated clusters
+        for pos in [CLUSTER - 16, 3 * CLUSTER - 16, 5 * CLUSTER - 16] {
+            assert_eq!(read(&disk, pos, 32), expected[pos..pos + 32]);
+        }
+        // Across the L1 entries, into the part of the disk that the backing file doesn't cover
+        let pos = 32 * 1024 - 100;
+        assert_eq!(read(&disk, pos, 200), expected[pos..pos + 200]);
+        let pos = BACKING_SIZE - 100;
+        assert_eq!(read(&disk, pos, 200), expected[pos..pos + 200]);
+    }
+
+    #[test]
+    fn unallocated_clusters_without_backing_file_are_zero() {
+        let disk = Qcow2Disk::new(image(None), None).unwrap();
+        assert_eq!(read(&disk, 0, DISK_SIZE), expected(false));
+    }
+
+    #[test]
+    fn reads_stop_at_the_end_of_the_disk() {
+        let disk = Qcow2Disk::new(image(None), None).unwrap();
+        let mut buf = [0xFFu8; 64];
+        assert_eq!(disk.read_at(DISK_SIZE as u64 - 16, &mut buf).unwrap(), 16);
+        assert_eq!(buf[..16], [0; 16]);
+        assert_eq!(disk.read_at(DISK_SIZE as u64, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn reads_backing_file_name() {
+        assert_eq!(Qcow2Disk::backing_file_name(&image(None)).unwrap(), None);
+        assert_eq!(
+            Qcow2Disk::backing_file_name(&image(Some("userdata-qemu.img"))).unwrap(),
+            Some("userdata-qemu.img".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_tables() {
+        let mut disk = image(None);
+        disk.write(0, &header(u32::MAX, None));
+        let error = Qcow2Disk::new(disk, None).err().unwrap();
+        assert!(
+            error.to_string().contains("L1 table is too large"),
+            "{error:#}"
+        );
+
+        let mut disk = image(Some("userdata-qemu.img"));
+        disk.write(16, &u32::MAX.to_be_bytes());
+        let error = Qcow2Disk::backing_file_name(&disk).unwrap_err();
+        assert!(error.to_string().contains("name is too long"), "{error:#}");
+    }
+}
@@ -0,0 +1,322 @@
+//! Android sparse image (`simg`) reader.
+//!
+//! Sparse images store only the used blocks of a partition. Instead of converting them back to a
+//! raw image on disk, [`SparseImage`] maps reads on the unsparsed image onto the chunks in the
+//! sparse file as they are requested.
+
+use std::io::Cursor;
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+
+pub const SPARSE_MAGIC: u32 = 0xED26FF3A;
+
+/// Sizes of the headers as of version 1.0, later minor versions may append fields
+const FILE_HEADER_SIZE: usize = 28;
+const CHUNK_HEADER_SIZE: usize = 12;
+
+const CHUNK_TYPE_RAW: u16 = 0xCAC1;
+const CHUNK_TYPE_FILL: u16 = 0xCAC2;
+const CHUNK_TYPE_DONT_CARE: u16 = 0xCAC3;
+const CHUNK_TYPE_CRC32: u16 = 0xCAC4;
+
+#[binread]
+#[br(little, magic = 0xED26FF3Au32)]
+#[derive(Debug, Clone)]
+pub struct SparseHeader {
+    #[br(assert(major_version == 1, "Unsupported sparse image version {major_version}"))]
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub file_header_size: u16,
+    pub chunk_header_size: u16,
+    pub block_size: u32,
+    pub total_blocks: u32,
+    pub total_chunks: u32,
+    pub image_checksum: u32,
+}
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone)]
+struct ChunkHeader {
+    chunk_type: u16,
+    _reserved: u16,
+    /// Size of the chunk in the output image, in blocks
+    chunk_size: u32,
+    /// Size of the chunk in the sparse file, including this header
+    total_size: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ChunkData {
+    /// Data is stored verbatim at the given offset in the sparse file
+    Raw(u64),
+    /// The chunk is filled with a repeating 32-bit pattern
+    Fill([u8; 4]),
+    /// The contents are undefined, read as zeroes
+    DontCare,
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    /// Offset of the chunk in the output image
+    start: u64,
+    /// Length of the chunk in the output image
+    len: u64,
+    data: ChunkData,
+}
+
+pub struct SparseImage<R: ReadAt> {
+    inner: R,
+    header: SparseHeader,
+    chunks: Vec<Chunk>,
+}
+
+impl<R: ReadAt> SparseImage<R> {
+    /// Returns true if the given reader starts with the sparse image magic
+    pub fn is_sparse(reader: &R) -> bool {
+        let mut magic = [0u8; 4];
+        reader.read_exact_at(0, &mut magic).is_ok() && u32::from_le_bytes(magic) == SPARSE_MAGIC
+    }
+
+    pub fn new(inner: R) -> anyhow::Result<Self> {
+        let mut header_data = [0u8; FILE_HEADER_SIZE];
+        inner
+            .read_exact_at(0, &mut header_data)
+            .context("Failed to read sparse image header")?;
+        let header: SparseHeader = Cursor::new(&header_data).read_le()?;
+        anyhow::ensure!(
+            header.file_header_size as usize >= FILE_HEADER_SIZE
+                && header.chunk_header_size as usize >= CHUNK_HEADER_SIZE,
+            "Invalid sparse image header sizes {} and {}",
+            header.file_header_size,
+            header.chunk_header_size
+        );
+        anyhow::ensure!(
+            header.block_size != 0 && header.block_size.is_multiple_of(4),
+            "Invalid sparse image block size {}",
+            header.block_size
+        );
+
+        // The chunk count comes from the file, so chunks aren't preallocated
+        let mut chunks = vec![];
+        let mut file_offset = header.file_header_size as u64;
+        let mut output_offset = 0;
+        let mut chunk_data = [0u8; CHUNK_HEADER_SIZE];
+        for i in 0..header.total_chunks {
+            inner
+                .read_exact_at(file_offset, &mut chunk_data)
+                .with_context(|| format!("Failed to read header of sparse chunk {i}"))?;
+            let chunk: ChunkHeader = Cursor::new(&chunk_data).read_le()?;
+            anyhow::ensure!(
+                chunk.total_size >= header.chunk_header_size as u32,
+                "Sparse chunk {i} is smaller than its header"
+            );
+
+            let data_offset = file_offset + header.chunk_header_size as u64;
+            let len = chunk.chunk_size as u64 * header.block_size as u64;
+            let data = match chunk.chunk_type {
+                CHUNK_TYPE_RAW => {
+                    anyhow::ensure!(
+                        chunk.total_size as u64 == header.chunk_header_size as u64 + len,
+                        "Raw sparse chunk {i} has an invalid size"
+                    );
+                    ChunkData::Raw(data_offset)
+                }
+                CHUNK_TYPE_FILL => {
+                    anyhow::ensure!(
+                        chunk.total_size >= header.chunk_header_size as u32 + 4,
+                        "Fill sparse chunk {i} has no fill value"
+                    );
+                    let mut value = [0u8; 4];
+                    inner.read_exact_at(data_offset, &mut value)?;
+                    ChunkData::Fill(value)
+                }
+                CHUNK_TYPE_DONT_CARE => ChunkData::DontCare,
+                CHUNK_TYPE_CRC32 => {
+                    // Checksum chunks don't occupy any space in the output image
+                    file_offset += chunk.total_size as u64;
+                    continue;
+                }
+                t => anyhow::bail!("Unknown sparse chunk type 0x{t:04X} in chunk {i}"),
+            };
+
+            if len != 0 {
+                chunks.push(Chunk {
+                    start: output_offset,
+                    len,
+                    data,
+                });
+            }
+
+            output_offset += len;
+            file_offset += chunk.total_size as u64;
+        }
+
+        anyhow::ensure!(
+            output_offset == header.total_blocks as u64 * header.block_size as u64,
+            "Sparse chunks do not add up to the image size"
+        );
+
+        Ok(Self {
+            inner,
+            header,
+            chunks,
+        })
+    }
+
+    pub fn header(&self) -> &SparseHeader {
+        &self.header
+    }
+
+    /// Size of the unsparsed image in bytes
+    pub fn len(&self) -> u64 {
+        self.header.total_blocks as u64 * self.header.block_size as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: ReadAt> ReadAt for SparseImage<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.chunks.partition_point(|c| c.start + c.len <= pos);
+        let Some(chunk) = self.chunks.get(index) else {
+            return Ok(0);
+        };
+
+        let chunk_offset = pos - chunk.start;
+        let count = (chunk.len - chunk_offset).min(buf.len() as u64) as usize;
+        let buf = &mut buf[..count];
+        match chunk.data {
+            ChunkData::Raw(offset) => self.inner.read_at(offset + chunk_offset, buf),
+            ChunkData::Fill(value) => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = value[(chunk_offset as usize + i) % 4];
+                }
+                Ok(count)
+            }
+            ChunkData::DontCare => {
+                buf.fill(0);
+                Ok(count)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::MemoryDisk;
+
+    const BLOCK_SIZE: usize = 4096;
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i + seed) % 251) as u8).collect()
+    }
+
+    /// Chunk type, output blocks and the data stored in the sparse file
+    type TestChunk = (u16, u32, Vec<u8>);
+
+    fn sparse_image(
+        file_header_size: u16,
+        total_chunks: Option<u32>,
+        chunks: &[TestChunk],
+    ) -> MemoryDisk {
+        let total_blocks: u32 = chunks.iter().map(|(_, blocks, _)| blocks).sum();
+        let mut image = SPARSE_MAGIC.to_le_bytes().to_vec();
+        image.extend_from_slice(&1u16.to_le_bytes());
+        image.extend_from_slice(&0u16.to_le_bytes());
+        image.extend_from_slice(&file_header_size.to_le_bytes());
+        image.extend_from_slice(&(CHUNK_HEADER_SIZE as u16).to_le_bytes());
+        image.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        image.extend_from_slice(&total_blocks.to_le_bytes());
+        image.extend_from_slice(&total_chunks.unwrap_or(chunks.len() as u32).to_le_bytes());
+        image.extend_from_slice(&0u32.to_le_bytes()); // image_checksum
+        image.resize(file_header_size as usize, 0);
+
+        for (chunk_type, blocks, data) in chunks {
+            image.extend_from_slice(&chunk_type.to_le_bytes());
+            image.extend_from_slice(&0u16.to_le_bytes());
+            image.extend_from_slice(&blocks.to_le_bytes());
+            image.extend_from_slice(&((CHUNK_HEADER_SIZE + data.len()) as u32).to_le_bytes());
+            image.extend_from_slice(data);
+        }
+
+        MemoryDisk(image)
+    }
+
+    /// Raw, fill, don't care and checksum chunks, with the unsparsed image they describe
+    fn chunks() -> (Vec<TestChunk>, Vec<u8>) {
+        let chunks = vec![
+            (CHUNK_TYPE_RAW, 2, pattern(2 * BLOCK_SIZE, 0)),
+            (CHUNK_TYPE_FILL, 2, vec![0xDE, 0xAD, 0xBE, 0xEF]),
+            (CHUNK_TYPE_DONT_CARE, 1, vec![]),
+            (CHUNK_TYPE_CRC32, 0, vec![0x12, 0x34, 0x56, 0x78]),
+            (CHUNK_TYPE_RAW, 1, pattern(BLOCK_SIZE, 7)),
+        ];
+
+        let mut expected = pattern(2 * BLOCK_SIZE, 0);
+        expected.extend([0xDE, 0xAD, 0xBE, 0xEF].repeat(2 * BLOCK_SIZE / 4));
+        expected.extend(vec![0; BLOCK_SIZE]);
+        expected.extend(pattern(BLOCK_SIZE, 7));
+        (chunks, expected)
+    }
+
+    #[test]
+    fn reads_every_chunk_type() {
+        let (chunks, expected) = chunks();
+        let image = sparse_image(FILE_HEADER_SIZE as u16, None, &chunks);
+        assert!(SparseImage::is_sparse(&image));
+
+        let sparse = SparseImage::new(image).unwrap();
+        assert_eq!(sparse.len(), expected.len() as u64);
+
+        let mut data = vec![0; expected.len()];
+        sparse.read_exact_at(0, &mut data).unwrap();
+        assert_eq!(data, expected);
+
+        // Reads that span chunks and start in the middle of a fill pattern
+        let mut data = vec![0; BLOCK_SIZE + 10];
+        sparse
+            .read_exact_at(2 * BLOCK_SIZE as u64 - 5, &mut data)
+            .unwrap();
+        assert_eq!(data, expected[2 * BLOCK_SIZE - 5..3 * BLOCK_SIZE + 5]);
+
+        let mut data = vec![0; 16];
+        assert_eq!(sparse.read_at(expected.len() as u64, &mut data).unwrap(), 0);
+    }
+
+    #[test]
+    fn skips_larger_headers() {
+        let (chunks, expected) = chunks();
+        let sparse = SparseImage::new(sparse_image(32, None, &chunks)).unwrap();
+
+        let mut data = vec![0; expected.len()];
+        sparse.read_exact_at(0, &mut data).unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let (chunks, _) = chunks();
+        assert!(SparseImage::new(sparse_image(20, None, &chunks)).is_err());
+
+        // A huge chunk count fails on the missing chunks instead of allocating for all of them
+        assert!(SparseImage::new(sparse_image(28, Some(u32::MAX), &chunks)).is_err());
+
+        let mut image = sparse_image(28, None, &chunks);
+        image.0[10..12].copy_from_slice(&4u16.to_le_bytes()); // chunk_header_size
+        assert!(SparseImage::new(image).is_err());
+
+        // A raw chunk that claims more output blocks than it stores
+        let chunks = [(CHUNK_TYPE_RAW, 2, pattern(BLOCK_SIZE, 0))];
+        assert!(SparseImage::new(sparse_image(28, None, &chunks)).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Read-only reader for VirtualBox (VDI) disk images, as used by MuMuPlayer.
+//!
+//! Unlike the `vdi` crate this reads through [`ReadAt`] without any shared seek position,
+//! so the disk can be read from multiple threads at once.
+//! Normal (dynamic) and fixed images are supported. Differencing and undo images are rejected.
+
+use std::io::Cursor;
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+
+pub const VDI_SIGNATURE_OFFSET: u64 = 0x40;
+pub const VDI_SIGNATURE: u32 = 0xBEDA107F;
+
+const HEADER_SIZE: usize = 0x1C8;
+/// Block that was never written
+const BLOCK_FREE: u32 = 0xFFFF_FFFF;
+/// Block that was discarded, reads as zeroes
+const BLOCK_ZERO: u32 = 0xFFFF_FFFE;
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone)]
+pub struct VdiHeader {
+    _text: [u8; 0x40],
+    #[br(assert(signature == VDI_SIGNATURE, "Invalid VDI signature"))]
+    pub signature: u32,
+    #[br(assert(version >> 16 == 1, "Unsupported VDI version {:#x}", version))]
+    pub version: u32,
+    pub header_size: u32,
+    pub image_type: VdiImageType,
+    pub image_flags: u32,
+    _description: [u8; 256],
+    pub blocks_offset: u32,
+    pub data_offset: u32,
+    _cylinders: u32,
+    _heads: u32,
+    _sectors: u32,
+    pub sector_size: u32,
+    _unused: u32,
+    pub disk_size: u64,
+    pub block_size: u32,
+    /// Per-block metadata stored in front of the data of every block
+    pub block_extra_size: u32,
+    pub block_count: u32,
+    pub blocks_allocated: u32,
+}
+
+#[binread]
+#[br(little, repr(u32))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VdiImageType {
+    Normal = 1,
+    Fixed = 2,
+    Undo = 3,
+    Differencing = 4,
+}
+
+pub struct VdiDisk<R: ReadAt> {
+    inner: R,
+    header: VdiHeader,
+    block_table: Vec<u32>,
+}
+
+impl<R: ReadAt> VdiDisk<R> {
+    pub fn is_vdi(reader: &R) -> bool {
+        let mut signature = [0u8; 4];
+        reader
+            .read_exact_at(VDI_SIGNATURE_OFFSET, &mut signature)
+            .is_ok()
+            && u32::from_le_bytes(signature) == VDI_SIGNATURE
+    }
+
+    pub fn new(inner: R) -> anyhow::Result<Self> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        inner
+            .read_exact_at(0, &mut data)
+            .context("Failed to read VDI header")?;
+        let header: VdiHeader = Cursor::new(&data).read_le()?;
+
+        anyhow::ensure!(
+            matches!(
+                header.image_type,
+                VdiImageType::Normal | VdiImageType::Fixed
+            ),
+            "Unsupported VDI image type {:?}",
+            header.image_type
+        );
+        anyhow::ensure!(
+            header.block_size != 0,
+            "Invalid VDI block size {}",
+            header.block_size
+        );
+
+        let mut table_data = vec![0u8; header.block_count as usize * 4];
+        inner
+            .read_exact_at(header.blocks_offset as u64, &mut table_data)
+            .context("Failed to read VDI block map")?;
+        let block_table = table_data
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            inner,
+            header,
+            block_table,
+        })
+    }
+
+    pub fn header(&self) -> &VdiHeader {
+        &self.header
+    }
+
+    /// Size of the virtual disk in bytes
+    pub fn len(&self) -> u64 {
+        self.header.disk_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: ReadAt> ReadAt for VdiDisk<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.header.block_size as u64;
+        let block = pos / block_size;
+        let block_offset = pos % block_size;
+        let count = (self.len() - pos)
+            .min(buf.len() as u64)
+            .min(block_size - block_offset) as usize;
+        let buf = &mut buf[..count];
+
+        match self
+            .block_table
+            .get(block as usize)
+            .copied()
+            .unwrap_or(BLOCK_FREE)
+        {
+            BLOCK_FREE | BLOCK_ZERO => {
+                buf.fill(0);
+                Ok(count)
+            }
+            index => {
+                let block_stride = block_size + self.header.block_extra_size as u64;
+                let file_offset = self.header.data_offset as u64
+                    + index as u64 * block_stride
+                    + self.header.block_extra_size as u64
+                    + block_offset;
+                self.inner.read_at(file_offset, buf)
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Read-only reader for Microsoft Virtual PC (VHD) disk images.
+//!
+//! Fixed and dynamic disks are supported. Differencing disks need their parent image and are rejected.
+
+use std::io::Cursor;
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+
+const SECTOR_SIZE: u64 = 512;
+const FOOTER_SIZE: u64 = 512;
+const DYNAMIC_HEADER_SIZE: usize = 1024;
+const BAT_ENTRY_UNALLOCATED: u32 = 0xFFFF_FFFF;
+
+#[binread]
+#[br(big, magic = b"conectix")]
+#[derive(Debug, Clone)]
+pub struct VhdFooter {
+    pub features: u32,
+    pub format_version: u32,
+    pub data_offset: u64,
+    pub timestamp: u32,
+    pub creator_application: [u8; 4],
+    pub creator_version: u32,
+    pub creator_host_os: u32,
+    pub original_size: u64,
+    pub current_size: u64,
+    pub disk_geometry: u32,
+    pub disk_type: VhdDiskType,
+    pub checksum: u32,
+    pub unique_id: [u8; 16],
+    pub saved_state: u8,
+}
+
+#[binread]
+#[br(big, repr(u32))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdDiskType {
+    None = 0,
+    Fixed = 2,
+    Dynamic = 3,
+    Differencing = 4,
+}
+
+#[binread]
+#[br(big, magic = b"cxsparse")]
+#[derive(Debug, Clone)]
+struct DynamicHeader {
+    _data_offset: u64,
+    table_offset: u64,
+    _header_version: u32,
+    max_table_entries: u32,
+    block_size: u32,
+}
+
+enum Layout {
+    Fixed,
+    Dynamic {
+        block_size: u64,
+        /// Size of the sector bitmap preceding every data block, in bytes
+        bitmap_size: u64,
+        block_table: Vec<u32>,
+    },
+}
+
+pub struct VhdDisk<R: ReadAt> {
+    inner: R,
+    footer: VhdFooter,
+    layout: Layout,
+}
+
+impl<R: ReadAt> VhdDisk<R> {
+    /// Returns true if the given reader starts with a VHD footer copy, as dynamic disks do.
+    ///
+    /// Fixed disks only have a footer at the end of the file, use [`VhdDisk::open`] with the file size for those.
+    pub fn is_vhd(reader: &R) -> bool {
+        let mut magic = [0u8; 8];
+        reader.read_exact_at(0, &mut magic).is_ok() && &magic == b"conectix"
+    }
+
+    /// Opens a VHD image. `file_size` is the size of the image file, which is needed to locate the footer.
+    pub fn open(inner: R, file_size: u64) -> anyhow::Result<Self> {
+        anyhow::ensure!(file_size >= FOOTER_SIZE, "File is too small to be a VHD");
+
+        // Some tools write a 511 byte footer, so we fall back to the copy at the start of the file for dynamic disks
+        let footer = read_footer(&inner, file_size - FOOTER_SIZE)
+            .or_else(|_| read_footer(&inner, 0))
+            .context("Failed to read VHD footer")?;
+
+        let layout = match footer.disk_type {
+            VhdDiskType::Fixed => {
+                anyhow::ensure!(
+                    footer.current_size <= file_size - FOOTER_SIZE,
+                    "Fixed VHD is smaller than its disk size"
+                );
+                Layout::Fixed
+            }
+            VhdDiskType::Dynamic => {
+                let mut data = vec![0u8; DYNAMIC_HEADER_SIZE];
+                inner
+                    .read_exact_at(footer.data_offset, &mut data)
+                    .context("Failed to read VHD dynamic disk header")?;
+                let header: DynamicHeader = Cursor::new(&data).read_be()?;
+                anyhow::ensure!(
+                    header.block_size != 0
+                        && (header.block_size as u64).is_multiple_of(SECTOR_SIZE),
+                    "Invalid VHD block size {}",
+                    header.block_size
+                );
+
+                // Entries past the end of the disk are never read, and the table has to fit in the file
+                let entries = (header.max_table_entries as u64)
+                    .min(footer.current_size.div_ceil(header.block_size as u64));
+                anyhow::ensure!(
+                    header.table_offset.saturating_add(entries * 4) <= file_size,
+                    "VHD block allocation table of {entries} entries is out of bounds"
+                );
+
+                let mut table_data = vec![0u8; entries as usize * 4];
+                inner
+                    .read_exact_at(header.table_offset, &mut table_data)
+                    .context("Failed to read VHD block allocation table")?;
+                let block_table = table_data
+                    .chunks_exact(4)
+                    .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+                    .collect();
+
+                let sectors_per_block = header.block_size as u64 / SECTOR_SIZE;
+                Layout::Dynamic {
+                    block_size: header.block_size as u64,
+                    bitmap_size: sectors_per_block.div_ceil(8).next_multiple_of(SECTOR_SIZE),
+                    block_table,
+                }
+            }
+            VhdDiskType::Differencing => {
+                anyhow::bail!("Differencing VHDs are not supported")
+            }
+            VhdDiskType::None => anyhow::bail!("VHD has no disk type"),
+        };
+
+        Ok(Self {
+            inner,
+            footer,
+            layout,
+        })
+    }
+
+    pub fn footer(&self) -> &VhdFooter {
+        &self.footer
+    }
+
+    /// Size of the virtual disk in bytes
+    pub fn len(&self) -> u64 {
+        self.footer.current_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: ReadAt> ReadAt for VhdDisk<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        let count = (self.len() - pos).min(buf.len() as u64) as usize;
+
+        match &self.layout {
+            Layout::Fixed => self.inner.read_at(pos, &mut buf[..count]),
+            Layout::Dynamic {
+                block_size,
+                bitmap_size,
+                block_table,
+            } => {
+                let block = (pos / block_size) as usize;
+                let block_offset = pos % block_size;
+                let count = count.min((block_size - block_offset) as usize);
+                let buf = &mut buf[..count];
+
+                match block_table.get(block).copied() {
+                    Some(sector) if sector != BAT_ENTRY_UNALLOCATED => {
+                        let offset = sector as u64 * SECTOR_SIZE + bitmap_size + block_offset;
+                        self.inner.read_at(offset, buf)
+                    }
+                    _ => {
+                        buf.fill(0);
+                        Ok(count)
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read_footer<R: ReadAt>(reader: &R, offset: u64) -> anyhow::Result<VhdFooter> {
+    let mut data = [0u8; FOOTER_SIZE as usize];
+    reader.read_exact_at(offset, &mut data)?;
+    Ok(Cursor::new(&data).read_be()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::MemoryDisk;
+
+    const BLOCK_SIZE: usize = 4096;
+    const DISK_SIZE: usize = 3 * BLOCK_SIZE;
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i + seed) % 251) as u8).collect()
+    }
+
+    fn footer(disk_type: u32, data_offset: u64, size: u64) -> Vec<u8> {
+        let mut footer = b"conectix".to_vec();
+        footer.extend_from_slice(&2u32.to_be_bytes()); // features
+        footer.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // format_version
+        footer.extend_from_slice(&data_offset.to_be_bytes());
+        footer.extend_from_slice(&[0; 16]); // timestamp and creator
+        footer.extend_from_slice(&size.to_be_bytes()); // original_size
+        footer.extend_from_slice(&size.to_be_bytes()); // current_size
+        footer.extend_from_slice(&0u32.to_be_bytes()); // disk_geometry
+        footer.extend_from_slice(&disk_type.to_be_bytes());
+        footer.resize(FOOTER_SIZE as usize, 0);
+        footer
+    }
+
+    /// A dynamic disk of three blocks, the middle one of which is unallocated
+    fn dynamic_image(disk_size: u64) -> (MemoryDisk, u64) {
+        let mut disk = MemoryDisk::default();
+        disk.write(0, &footer(3, 512, disk_size));
+
+        let mut header = b"cxsparse".to_vec();
+        header.extend_from_slice(&u64::MAX.to_be_bytes());
+        header.extend_from_slice(&1536u64.to_be_bytes()); // table_offset
+        header.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+        header.extend_from_slice(&3u32.to_be_bytes()); // max_table_entries
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+        header.resize(DYNAMIC_HEADER_SIZE, 0);
+        disk.write(512, &header);
+
+        // Every block is preceded by a sector bitmap, which is a single sector for 8 sector blocks
+        for (i, sector) in [4u32, BAT_ENTRY_UNALLOCATED, 13].iter().enumerate() {
+            disk.write(1536 + i * 4, &sector.to_be_bytes());
+        }
+        disk.write(2048, &[0xFF; 512]);
+        disk.write(2560, &pattern(BLOCK_SIZE, 0));
+        disk.write(6656, &[0xFF; 512]);
+        disk.write(7168, &pattern(BLOCK_SIZE, 2 * BLOCK_SIZE));
+        disk.write(11264, &footer(3, 512, disk_size));
+
+        let file_size = disk.0.len() as u64;
+        (disk, file_size)
+    }
+
+    fn read(disk: &impl ReadAt, pos: usize, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        disk.read_exact_at(pos as u64, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_fixed_disk() {
+        let mut disk = MemoryDisk(pattern(DISK_SIZE, 0));
+        disk.write(DISK_SIZE, &footer(2, u64::MAX, DISK_SIZE as u64));
+        let file_size = disk.0.len() as u64;
+
+        assert!(!VhdDisk::is_vhd(&disk));
+        let vhd = VhdDisk::open(disk, file_size).unwrap();
+        assert_eq!(vhd.len(), DISK_SIZE as u64);
+        assert_eq!(read(&vhd, 0, DISK_SIZE), pattern(DISK_SIZE, 0));
+
+        // The footer isn't part of the disk
+        let mut buf = [0u8; 64];
+        assert_eq!(vhd.read_at(DISK_SIZE as u64 - 16, &mut buf).unwrap(), 16);
+        assert_eq!(vhd.read_at(DISK_SIZE as u64, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn reads_dynamic_disk() {
+        let (disk, file_size) = dynamic_image(DISK_SIZE as u64);
+        assert!(VhdDisk::is_vhd(&disk));
+        let vhd = VhdDisk::open(disk, file_size).unwrap();
+
+        let mut expected = pattern(DISK_SIZE, 0);
+        expected[BLOCK_SIZE..2 * BLOCK_SIZE].fill(0);
+        assert_eq!(read(&vhd, 0, DISK_SIZE), expected);
+        // Across the allocated and unallocated blocks
+        for pos in [BLOCK_SIZE - 100, 2 * BLOCK_SIZE - 100] {
+            assert_eq!(read(&vhd, pos, 200), expected[pos..pos + 200]);
+        }
+    }
+
+    #[test]
+    fn rejects_block_table_outside_of_file() {
+        // A terabyte disk would need a much larger table than the file holds
+        let (mut disk, file_size) = dynamic_image(1 << 40);
+        disk.write(512 + 28, &u32::MAX.to_be_bytes()); // max_table_entries
+
+        let error = VhdDisk::open(disk, file_size).err().unwrap();
+        assert!(error.to_string().contains("out of bounds"), "{error:#}");
+    }
+}
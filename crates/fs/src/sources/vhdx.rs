@@ -0,0 +1,390 @@
+//! Read-only reader for Hyper-V (VHDX) disk images, as used by BlueStacks.
+//!
+//! Fixed and dynamic disks are supported. Differencing disks need their parent image and are rejected.
+//! Pending log entries are not replayed, so images that weren't closed cleanly may contain stale data.
+
+use std::io::Cursor;
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+use log::warn;
+use uuid::{Uuid, uuid};
+
+pub const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const HEADER_OFFSETS: [u64; 2] = [64 * KIB, 128 * KIB];
+const REGION_TABLE_OFFSETS: [u64; 2] = [192 * KIB, 256 * KIB];
+/// Largest virtual disk the format allows, 64 TiB
+const MAX_DISK_SIZE: u64 = 64 * 1024 * 1024 * MIB;
+
+const BAT_REGION: Uuid = uuid!("2DC27766-F623-4200-9D64-115E9BFD4A08");
+const METADATA_REGION: Uuid = uuid!("8B7CA206-4790-4B9A-B8FE-575F050F886E");
+
+const FILE_PARAMETERS: Uuid = uuid!("CAA16737-FA36-4D43-B3B6-33F0AA44E76B");
+const VIRTUAL_DISK_SIZE: Uuid = uuid!("2FA54224-CD1B-4876-B211-5DBED83BF4B8");
+const LOGICAL_SECTOR_SIZE: Uuid = uuid!("8141BF1D-A96F-4709-BA47-F233A8FAAB5F");
+
+const FILE_PARAMETERS_HAS_PARENT: u32 = 1 << 1;
+
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+
+/// GUIDs are stored in the mixed-endian Microsoft layout
+fn read_guid(bytes: [u8; 16]) -> Uuid {
+    Uuid::from_bytes_le(bytes)
+}
+
+#[binread]
+#[br(little, magic = b"head")]
+#[derive(Debug, Clone)]
+struct VhdxHeader {
+    _checksum: u32,
+    sequence_number: u64,
+    _file_write_guid: [u8; 16],
+    _data_write_guid: [u8; 16],
+    #[br(map = read_guid)]
+    log_guid: Uuid,
+    _log_version: u16,
+    version: u16,
+}
+
+#[binread]
+#[br(little, magic = b"regi")]
+#[derive(Debug, Clone)]
+struct RegionTable {
+    _checksum: u32,
+    #[br(temp)]
+    entry_count: u32,
+    _reserved: u32,
+    #[br(count = entry_count.min(2047))]
+    entries: Vec<RegionTableEntry>,
+}
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone)]
+struct RegionTableEntry {
+    #[br(map = read_guid)]
+    guid: Uuid,
+    file_offset: u64,
+    length: u32,
+    _required: u32,
+}
+
+#[binread]
+#[br(little, magic = b"metadata")]
+#[derive(Debug, Clone)]
+struct MetadataTable {
+    _reserved: u16,
+    #[br(temp)]
+    entry_count: u16,
+    _reserved2: [u8; 20],
+    #[br(count = entry_count.min(2047))]
+    entries: Vec<MetadataTableEntry>,
+}
+
+#[binread]
+#[br(little)]
+#[derive(Debug, Clone)]
+struct MetadataTableEntry {
+    #[br(map = read_guid)]
+    item_id: Uuid,
+    offset: u32,
+    length: u32,
+    _flags: u32,
+    _reserved: u32,
+}
+
+pub struct VhdxDisk<R: ReadAt> {
+    inner: R,
+    disk_size: u64,
+    block_size: u64,
+    /// Number of payload blocks per sector bitmap block, BAT entries for the latter are interleaved with payload entries
+    chunk_ratio: u64,
+    block_table: Vec<u64>,
+}
+
+impl<R: ReadAt> VhdxDisk<R> {
+    pub fn is_vhdx(reader: &R) -> bool {
+        let mut magic = [0u8; 8];
+        reader.read_exact_at(0, &mut magic).is_ok() && &magic == VHDX_SIGNATURE
+    }
+
+    pub fn new(inner: R) -> anyhow::Result<Self> {
+        anyhow::ensure!(Self::is_vhdx(&inner), "File is not a VHDX image");
+
+        // There are two copies of the header, the one with the highest sequence number is current
+        let header = HEADER_OFFSETS
+            .iter()
+            .filter_map(|&offset| {
+                read_struct::<_, VhdxHeader>(&inner, offset, 4 * KIB as usize).ok()
+            })
+            .max_by_key(|h| h.sequence_number)
+            .context("VHDX has no valid headers")?;
+        anyhow::ensure!(
+            header.version == 1,
+            "Unsupported VHDX version {}",
+            header.version
+        );
+        if !header.log_guid.is_nil() {
+            warn!("VHDX has pending log entries which will not be replayed");
+        }
+
+        let region_table = REGION_TABLE_OFFSETS
+            .iter()
+            .find_map(|&offset| {
+                read_struct::<_, RegionTable>(&inner, offset, 64 * KIB as usize).ok()
+            })
+            .context("VHDX has no valid region tables")?;
+        let find_region = |guid: Uuid| {
+            region_table
+                .entries
+                .iter()
+                .find(|e| e.guid == guid)
+                .with_context(|| format!("VHDX region {guid} not found"))
+        };
+        let bat_region = find_region(BAT_REGION)?;
+        let metadata_region = find_region(METADATA_REGION)?;
+
+        let metadata: MetadataTable =
+            read_struct(&inner, metadata_region.file_offset, 64 * KIB as usize)
+                .context("Failed to read VHDX metadata table")?;
+        let read_metadata = |guid: Uuid| -> anyhow::Result<Vec<u8>> {
+            let entry = metadata
+                .entries
+                .iter()
+                .find(|e| e.item_id == guid)
+                .with_context(|| format!("VHDX metadata item {guid} not found"))?;
+            anyhow::ensure!(
+                entry.length as u64 <= MIB,
+                "VHDX metadata item {guid} is too large"
+            );
+            let mut data = vec![0u8; entry.length as usize];
+            inner.read_exact_at(metadata_region.file_offset + entry.offset as u64, &mut data)?;
+            Ok(data)
+        };
+
+        let file_parameters = read_metadata(FILE_PARAMETERS)?;
+        anyhow::ensure!(file_parameters.len() >= 8, "Invalid VHDX file parameters");
+        let block_size = u32::from_le_bytes(file_parameters[0..4].try_into().unwrap()) as u64;
+        let flags = u32::from_le_bytes(file_parameters[4..8].try_into().unwrap());
+        anyhow::ensure!(
+            flags & FILE_PARAMETERS_HAS_PARENT == 0,
+            "Differencing VHDX images are not supported"
+        );
+
+        let disk_size = read_metadata(VIRTUAL_DISK_SIZE)?
+            .get(..8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .context("Invalid VHDX virtual disk size")?;
+        let sector_size = read_metadata(LOGICAL_SECTOR_SIZE)?
+            .get(..4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as u64)
+            .context("Invalid VHDX logical sector size")?;
+        anyhow::ensure!(
+            block_size.is_power_of_two() && (MIB..=256 * MIB).contains(&block_size),
+            "Invalid VHDX block size {block_size}"
+        );
+        anyhow::ensure!(
+            sector_size == 512 || sector_size == 4096,
+            "Invalid VHDX logical sector size {sector_size}"
+        );
+        anyhow::ensure!(
+            disk_size <= MAX_DISK_SIZE,
+            "VHDX virtual disk size {disk_size} is too large"
+        );
+
+        // Only the entries covering the virtual disk are read, the region may be larger
+        let chunk_ratio = ((1 << 23) * sector_size) / block_size;
+        let data_blocks = disk_size.div_ceil(block_size);
+        let bat_entries = data_blocks + data_blocks.saturating_sub(1) / chunk_ratio;
+        let bat_length = (bat_entries * 8).min(bat_region.length as u64);
+        let mut bat_data = vec![0u8; bat_length as usize];
+        inner
+            .read_exact_at(bat_region.file_offset, &mut bat_data)
+            .context("Failed to read VHDX block allocation table")?;
+        let block_table = bat_data
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            inner,
+            disk_size,
+            block_size,
+            chunk_ratio,
+            block_table,
+        })
+    }
+
+    /// Size of the virtual disk in bytes
+    pub fn len(&self) -> u64 {
+        self.disk_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: ReadAt> ReadAt for VhdxDisk<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block = pos / self.block_size;
+        let block_offset = pos % self.block_size;
+        let count = (self.len() - pos)
+            .min(buf.len() as u64)
+            .min(self.block_size - block_offset) as usize;
+        let buf = &mut buf[..count];
+
+        let entry = self
+            .block_table
+            .get((block + block / self.chunk_ratio) as usize)
+            .copied()
+            .unwrap_or(0);
+        match entry & 0b111 {
+            PAYLOAD_BLOCK_FULLY_PRESENT | PAYLOAD_BLOCK_PARTIALLY_PRESENT => {
+                let file_offset = (entry >> 20) * MIB;
+                self.inner.read_at(file_offset + block_offset, buf)
+            }
+            // Not present, undefined, zero and unmapped blocks
+            _ => {
+                buf.fill(0);
+                Ok(count)
+            }
+        }
+    }
+}
+
+fn read_struct<R: ReadAt, T>(reader: &R, offset: u64, size: usize) -> anyhow::Result<T>
+where
+    T: for<'a> binrw::BinRead<Args<'a> = ()>,
+{
+    let mut data = vec![0u8; size];
+    reader.read_exact_at(offset, &mut data)?;
+    Ok(Cursor::new(&data).read_le()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::MemoryDisk;
+
+    const BLOCK_SIZE: usize = MIB as usize;
+    const DISK_SIZE: usize = 3 * BLOCK_SIZE;
+    const METADATA_OFFSET: usize = MIB as usize;
+    const BAT_OFFSET: usize = 2 * MIB as usize;
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i + seed) % 251) as u8).collect()
+    }
+
+    /// A dynamic disk of three 1 MiB blocks, the middle one of which is not present
+    fn image(disk_size: u64, bat_length: u32) -> MemoryDisk {
+        let mut disk = MemoryDisk::default();
+        disk.write(0, VHDX_SIGNATURE);
+
+        let mut header = b"head".to_vec();
+        header.extend_from_slice(&0u32.to_le_bytes()); // checksum
+        header.extend_from_slice(&1u64.to_le_bytes()); // sequence_number
+        header.extend_from_slice(&[0; 48]); // file write, data write and log GUIDs
+        header.extend_from_slice(&0u16.to_le_bytes()); // log_version
+        header.extend_from_slice(&1u16.to_le_bytes()); // version
+        disk.write(HEADER_OFFSETS[0] as usize, &header);
+
+        let mut regions = b"regi".to_vec();
+        regions.extend_from_slice(&0u32.to_le_bytes()); // checksum
+        regions.extend_from_slice(&2u32.to_le_bytes()); // entry_count
+        regions.extend_from_slice(&0u32.to_le_bytes());
+        for (guid, offset, length) in [
+            (BAT_REGION, BAT_OFFSET, bat_length),
+            (METADATA_REGION, METADATA_OFFSET, MIB as u32),
+        ] {
+            regions.extend_from_slice(&guid.to_bytes_le());
+            regions.extend_from_slice(&(offset as u64).to_le_bytes());
+            regions.extend_from_slice(&length.to_le_bytes());
+            regions.extend_from_slice(&1u32.to_le_bytes()); // required
+        }
+        disk.write(REGION_TABLE_OFFSETS[0] as usize, &regions);
+
+        let mut block_size_and_flags = (BLOCK_SIZE as u32).to_le_bytes().to_vec();
+        block_size_and_flags.extend_from_slice(&0u32.to_le_bytes());
+        let items = [
+            (FILE_PARAMETERS, block_size_and_flags),
+            (VIRTUAL_DISK_SIZE, disk_size.to_le_bytes().to_vec()),
+            (LOGICAL_SECTOR_SIZE, 512u32.to_le_bytes().to_vec()),
+        ];
+        let mut metadata = b"metadata".to_vec();
+        metadata.extend_from_slice(&0u16.to_le_bytes());
+        metadata.extend_from_slice(&(items.len() as u16).to_le_bytes());
+        metadata.extend_from_slice(&[0; 20]);
+        let mut item_offset = 64 * KIB as usize;
+        for (guid, data) in &items {
+            metadata.extend_from_slice(&guid.to_bytes_le());
+            metadata.extend_from_slice(&(item_offset as u32).to_le_bytes());
+            metadata.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            metadata.extend_from_slice(&[0; 8]); // flags and reserved
+            disk.write(METADATA_OFFSET + item_offset, data);
+            item_offset += data.len();
+        }
+        disk.write(METADATA_OFFSET, &metadata);
+
+        // Entries hold the file offset in MiB and the block state
+        let bat = [
+            3 << 20 | PAYLOAD_BLOCK_FULLY_PRESENT,
+            0,
+            4 << 20 | PAYLOAD_BLOCK_FULLY_PRESENT,
+        ];
+        for (i, entry) in bat.iter().enumerate() {
+            disk.write(BAT_OFFSET + i * 8, &entry.to_le_bytes());
+        }
+        disk.write(3 * BLOCK_SIZE, &pattern(BLOCK_SIZE, 0));
+        disk.write(4 * BLOCK_SIZE, &pattern(BLOCK_SIZE, 2 * BLOCK_SIZE));
+        disk
+    }
+
+    fn read(disk: &impl ReadAt, pos: usize, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        disk.read_exact_at(pos as u64, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_dynamic_disk() {
+        let vhdx = VhdxDisk::new(image(DISK_SIZE as u64, MIB as u32)).unwrap();
+        assert_eq!(vhdx.len(), DISK_SIZE as u64);
+
+        let mut expected = pattern(DISK_SIZE, 0);
+        expected[BLOCK_SIZE..2 * BLOCK_SIZE].fill(0);
+        assert_eq!(read(&vhdx, 0, DISK_SIZE), expected);
+        // Across the present and missing blocks
+        for pos in [BLOCK_SIZE - 100, 2 * BLOCK_SIZE - 100] {
+            assert_eq!(read(&vhdx, pos, 200), expected[pos..pos + 200]);
+        }
+
+        let mut buf = [0u8; 64];
+        assert_eq!(vhdx.read_at(DISK_SIZE as u64 - 16, &mut buf).unwrap(), 16);
+        assert_eq!(vhdx.read_at(DISK_SIZE as u64, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn only_reads_block_table_entries_of_the_disk() {
+        // The region claims to be 4 GiB, far more than the file holds
+        let vhdx = VhdxDisk::new(image(DISK_SIZE as u64, u32::MAX)).unwrap();
+        assert_eq!(vhdx.block_table.len(), 3);
+    }
+
+    #[test]
+    fn rejects_oversized_disk() {
+        let error = VhdxDisk::new(image(MAX_DISK_SIZE + 1, MIB as u32))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("too large"), "{error:#}");
+    }
+}
@@ -0,0 +1,553 @@
+//! Read-only reader for VMware (VMDK) disk images, as used by LDPlayer.
+//!
+//! Supported are monolithic sparse images (optionally stream-optimized with compressed grains), and descriptor files
+//! referencing `SPARSE`, `FLAT` and `ZERO` extents. Snapshot chains (images with a parent) are not supported.
+
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use binrw::{BinReaderExt, binread};
+use bootsector::pio::ReadAt;
+use log::warn;
+
+use crate::sources::BlockDevice;
+
+pub const VMDK_SPARSE_MAGIC: &[u8; 4] = b"KDMV";
+const DESCRIPTOR_SIGNATURE: &[u8] = b"# Disk DescriptorFile";
+
+const SECTOR_SIZE: u64 = 512;
+const GD_AT_END: u64 = u64::MAX;
+const FLAG_COMPRESSED: u32 = 1 << 16;
+const COMPRESSION_DEFLATE: u16 = 1;
+/// Grain table entry for grains that are known to be all zeroes
+const GRAIN_ZERO: u32 = 1;
+/// Largest grain size accepted, in sectors, the same limit QEMU uses
+const MAX_GRAIN_SIZE: u64 = 0x200000;
+
+#[binread]
+#[br(little, magic = b"KDMV")]
+#[derive(Debug, Clone)]
+pub struct SparseExtentHeader {
+    pub version: u32,
+    pub flags: u32,
+    /// Capacity of the extent, in sectors
+    pub capacity: u64,
+    /// Size of a grain, in sectors
+    pub grain_size: u64,
+    pub descriptor_offset: u64,
+    pub descriptor_size: u64,
+    pub num_gtes_per_gt: u32,
+    pub rgd_offset: u64,
+    pub gd_offset: u64,
+    pub overhead: u64,
+    pub unclean_shutdown: u8,
+    pub single_end_line_char: u8,
+    pub non_end_line_char: u8,
+    pub double_end_line_char1: u8,
+    pub double_end_line_char2: u8,
+    pub compress_algorithm: u16,
+}
+
+struct SparseExtent<R: ReadAt> {
+    inner: R,
+    file_size: u64,
+    header: SparseExtentHeader,
+    grain_directory: Vec<u32>,
+
+    /// The most recently decompressed grain, as (sector, data)
+    grain_cache: Mutex<Option<(u32, Vec<u8>)>>,
+}
+
+impl<R: ReadAt> SparseExtent<R> {
+    fn new(inner: R, file_size: u64) -> anyhow::Result<Self> {
+        let mut header = read_sparse_header(&inner, 0)?;
+        if header.gd_offset == GD_AT_END {
+            // Stream-optimized images have the real header in a footer, followed by an end-of-stream marker
+            anyhow::ensure!(file_size >= 1024, "VMDK is too small to contain a footer");
+            header = read_sparse_header(&inner, file_size - 1024)
+                .context("Failed to read stream-optimized VMDK footer")?;
+        }
+
+        anyhow::ensure!(
+            header.grain_size != 0
+                && header.grain_size <= MAX_GRAIN_SIZE
+                && header.num_gtes_per_gt != 0,
+            "Invalid VMDK grain size"
+        );
+        if header.flags & FLAG_COMPRESSED != 0 {
+            anyhow::ensure!(
+                header.compress_algorithm == COMPRESSION_DEFLATE,
+                "Unsupported VMDK compression algorithm {}",
+                header.compress_algorithm
+            );
+        }
+
+        let grains = header.capacity.div_ceil(header.grain_size);
+        let tables = grains.div_ceil(header.num_gtes_per_gt as u64);
+        let gd_end =
+            (header.gd_offset.saturating_mul(SECTOR_SIZE)).saturating_add(tables.saturating_mul(4));
+        anyhow::ensure!(
+            gd_end <= file_size,
+            "VMDK grain directory of {tables} tables is out of bounds"
+        );
+        let mut gd_data = vec![0u8; tables as usize * 4];
+        inner
+            .read_exact_at(header.gd_offset * SECTOR_SIZE, &mut gd_data)
+            .context("Failed to read VMDK grain directory")?;
+        let grain_directory = gd_data
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            inner,
+            file_size,
+            header,
+            grain_directory,
+            grain_cache: Mutex::new(None),
+        })
+    }
+
+    fn len(&self) -> u64 {
+        self.header.capacity * SECTOR_SIZE
+    }
+
+    fn grain_bytes(&self) -> u64 {
+        self.header.grain_size * SECTOR_SIZE
+    }
+
+    /// Returns the sector of the grain containing the given offset, 0 if it is unallocated
+    fn grain_sector(&self, pos: u64) -> std::io::Result<u32> {
+        let grain = pos / self.grain_bytes();
+        let table = (grain / self.header.num_gtes_per_gt as u64) as usize;
+        let entry = grain % self.header.num_gtes_per_gt as u64;
+
+        let table_sector = self.grain_directory.get(table).copied().unwrap_or(0);
+        if table_sector == 0 {
+            return Ok(0);
+        }
+
+        let mut sector = [0u8; 4];
+        self.inner
+            .read_exact_at(table_sector as u64 * SECTOR_SIZE + entry * 4, &mut sector)?;
+        Ok(u32::from_le_bytes(sector))
+    }
+
+    fn read_compressed(
+        &self,
+        sector: u32,
+        grain_offset: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<()> {
+        let mut cache = self.grain_cache.lock().unwrap();
+        if cache.as_ref().map(|(s, _)| *s) != Some(sector) {
+            // Compressed grains are prefixed by a marker containing the LBA (u64) and compressed size (u32)
+            let mut marker = [0u8; 12];
+            self.inner
+                .read_exact_at(sector as u64 * SECTOR_SIZE, &mut marker)?;
+            let size = u32::from_le_bytes(marker[8..12].try_into().unwrap()) as u64;
+            let start = sector as u64 * SECTOR_SIZE + 12;
+            if size > zlib_bound(self.grain_bytes()) || start + size > self.file_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Compressed VMDK grain at sector {sector} has an invalid size of {size} bytes"
+                    ),
+                ));
+            }
+
+            let mut compressed = vec![0u8; size as usize];
+            self.inner.read_exact_at(start, &mut compressed)?;
+
+            let mut grain = Vec::with_capacity(self.grain_bytes() as usize);
+            flate2::read::ZlibDecoder::new(Cursor::new(compressed))
+                .take(self.grain_bytes())
+                .read_to_end(&mut grain)?;
+            grain.resize(self.grain_bytes() as usize, 0);
+            *cache = Some((sector, grain));
+        }
+
+        let (_, grain) = cache.as_ref().unwrap();
+        let start = grain_offset as usize;
+        buf.copy_from_slice(&grain[start..start + buf.len()]);
+        Ok(())
+    }
+}
+
+/// Largest size `len` bytes can have after zlib compression, like zlib's `compressBound`
+fn zlib_bound(len: u64) -> u64 {
+    len + (len >> 12) + (len >> 14) + (len >> 25) + 13
+}
+
+impl<R: ReadAt> ReadAt for SparseExtent<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let grain_offset = pos % self.grain_bytes();
+        let count = (self.len() - pos)
+            .min(buf.len() as u64)
+            .min(self.grain_bytes() - grain_offset) as usize;
+        let buf = &mut buf[..count];
+
+        match self.grain_sector(pos)? {
+            0 | GRAIN_ZERO => {
+                buf.fill(0);
+                Ok(count)
+            }
+            sector if self.header.flags & FLAG_COMPRESSED != 0 => {
+                self.read_compressed(sector, grain_offset, buf)?;
+                Ok(count)
+            }
+            sector => self
+                .inner
+                .read_at(sector as u64 * SECTOR_SIZE + grain_offset, buf),
+        }
+    }
+}
+
+enum ExtentData {
+    Sparse(SparseExtent<BlockDevice>),
+    Flat { reader: BlockDevice, offset: u64 },
+    Zero,
+}
+
+struct Extent {
+    /// Offset of the extent in the virtual disk
+    start: u64,
+    len: u64,
+    data: ExtentData,
+}
+
+pub struct VmdkDisk {
+    extents: Vec<Extent>,
+    len: u64,
+}
+
+impl VmdkDisk {
+    /// Returns true if the given reader is either a sparse extent or a descriptor file
+    pub fn is_vmdk<R: ReadAt>(reader: &R) -> bool {
+        let mut magic = [0u8; DESCRIPTOR_SIGNATURE.len()];
+        reader.read_exact_at(0, &mut magic).is_ok()
+            && (magic.starts_with(VMDK_SPARSE_MAGIC) || magic == DESCRIPTOR_SIGNATURE)
+    }
+
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open VMDK {}", path.display()))?;
+        let file_size = file.metadata()?.len();
+
+        let mut magic = [0u8; 4];
+        file.read_exact_at(0, &mut magic)?;
+        if &magic == VMDK_SPARSE_MAGIC {
+            let extent = SparseExtent::new(BlockDevice::new(file), file_size)?;
+            let len = extent.len();
+            return Ok(Self {
+                extents: vec![Extent {
+                    start: 0,
+                    len,
+                    data: ExtentData::Sparse(extent),
+                }],
+                len,
+            });
+        }
+
+        anyhow::ensure!(
+            file_size < 1024 * 1024,
+            "File is neither a VMDK sparse extent nor a descriptor"
+        );
+        let descriptor = std::fs::read_to_string(path).context("Failed to read VMDK descriptor")?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::open_descriptor(&descriptor, base_dir)
+    }
+
+    /// Opens the extents listed in a descriptor file. Extent file names are relative to `base_dir`.
+    pub fn open_descriptor(descriptor: &str, base_dir: &Path) -> anyhow::Result<Self> {
+        let mut extents = vec![];
+        let mut start = 0;
+        for line in descriptor.lines().map(str::trim) {
+            if let Some(parent) = line.strip_prefix("parentCID=") {
+                if !parent.eq_ignore_ascii_case("ffffffff") {
+                    warn!("VMDK has a parent image, data from the parent will read as zeroes");
+                }
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(access), Some(sectors), Some(kind)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            if !matches!(access, "RW" | "RDONLY" | "NOACCESS") {
+                continue;
+            }
+
+            let sectors: u64 = sectors
+                .parse()
+                .with_context(|| format!("Invalid VMDK extent size in '{line}'"))?;
+            let len = sectors * SECTOR_SIZE;
+
+            // File names are quoted and may contain spaces
+            let filename = line.split('"').nth(1);
+            let open_extent_file = || -> anyhow::Result<std::fs::File> {
+                let filename =
+                    filename.with_context(|| format!("VMDK extent has no file: '{line}'"))?;
+                let path = base_dir.join(filename);
+                std::fs::File::open(&path)
+                    .with_context(|| format!("Failed to open VMDK extent {}", path.display()))
+            };
+
+            let data = match kind {
+                "SPARSE" => {
+                    let file = open_extent_file()?;
+                    let file_size = file.metadata()?.len();
+                    ExtentData::Sparse(SparseExtent::new(BlockDevice::new(file), file_size)?)
+                }
+                "FLAT" | "VMFS" => {
+                    let offset_sectors: u64 = line
+                        .rsplit('"')
+                        .next()
+                        .and_then(|s| s.trim().parse().ok())
+                        .unwrap_or(0);
+                    ExtentData::Flat {
+                        reader: BlockDevice::new(open_extent_file()?),
+                        offset: offset_sectors * SECTOR_SIZE,
+                    }
+                }
+                "ZERO" => ExtentData::Zero,
+                k => anyhow::bail!("Unsupported VMDK extent type {k}"),
+            };
+
+            extents.push(Extent { start, len, data });
+            start += len;
+        }
+
+        anyhow::ensure!(!extents.is_empty(), "VMDK descriptor has no extents");
+        Ok(Self {
+            extents,
+            len: start,
+        })
+    }
+
+    /// Size of the virtual disk in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl ReadAt for VmdkDisk {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        if pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.extents.partition_point(|e| e.start + e.len <= pos);
+        let Some(extent) = self.extents.get(index) else {
+            return Ok(0);
+        };
+
+        let extent_offset = pos - extent.start;
+        let count = (extent.len - extent_offset).min(buf.len() as u64) as usize;
+        let buf = &mut buf[..count];
+        match &extent.data {
+            ExtentData::Sparse(sparse) => sparse.read_at(extent_offset, buf),
+            ExtentData::Flat { reader, offset } => reader.read_at(offset + extent_offset, buf),
+            ExtentData::Zero => {
+                buf.fill(0);
+                Ok(count)
+            }
+        }
+    }
+}
+
+fn read_sparse_header<R: ReadAt>(reader: &R, offset: u64) -> anyhow::Result<SparseExtentHeader> {
+    let mut data = [0u8; SECTOR_SIZE as usize];
+    reader
+        .read_exact_at(offset, &mut data)
+        .context("Failed to read VMDK sparse extent header")?;
+    Ok(Cursor::new(&data).read_le()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::sources::MemoryDisk;
+
+    const SECTOR: usize = SECTOR_SIZE as usize;
+    /// 8 sector grains and 4 grains per grain table, so the 8 grain disk needs two tables
+    const GRAIN: usize = 8 * SECTOR;
+    const DISK_SIZE: usize = 8 * GRAIN;
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i + seed) % 251) as u8).collect()
+    }
+
+    fn header(capacity: u64, flags: u32) -> Vec<u8> {
+        let mut header = VMDK_SPARSE_MAGIC.to_vec();
+        header.extend_from_slice(&1u32.to_le_bytes()); // version
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.extend_from_slice(&capacity.to_le_bytes());
+        header.extend_from_slice(&8u64.to_le_bytes()); // grain_size
+        header.extend_from_slice(&[0; 16]); // descriptor offset and size
+        header.extend_from_slice(&4u32.to_le_bytes()); // num_gtes_per_gt
+        header.extend_from_slice(&0u64.to_le_bytes()); // rgd_offset
+        header.extend_from_slice(&1u64.to_le_bytes()); // gd_offset
+        header.extend_from_slice(&4u64.to_le_bytes()); // overhead
+        header.extend_from_slice(&[0, b'\n', b' ', b'\r', b'\n']);
+        let compression = if flags & FLAG_COMPRESSED != 0 {
+            COMPRESSION_DEFLATE
+        } else {
+            0
+        };
+        header.extend_from_slice(&compression.to_le_bytes());
+        header
+    }
+
+    /// Grain 0 and 3 are allocated, 1 is unallocated and 2 is a zero grain. The second grain table is missing.
+    fn sparse_image() -> MemoryDisk {
+        let mut disk = MemoryDisk::default();
+        disk.write(0, &header((DISK_SIZE / SECTOR) as u64, 0));
+        disk.write(SECTOR, &2u32.to_le_bytes());
+        disk.write(SECTOR + 4, &0u32.to_le_bytes());
+        for (i, sector) in [4u32, 0, GRAIN_ZERO, 12].iter().enumerate() {
+            disk.write(2 * SECTOR + i * 4, &sector.to_le_bytes());
+        }
+        disk.write(4 * SECTOR, &pattern(GRAIN, 0));
+        disk.write(12 * SECTOR, &pattern(GRAIN, 3 * GRAIN));
+        disk
+    }
+
+    fn sparse_expected() -> Vec<u8> {
+        let mut expected = vec![0u8; DISK_SIZE];
+        expected[..GRAIN].copy_from_slice(&pattern(GRAIN, 0));
+        expected[3 * GRAIN..4 * GRAIN].copy_from_slice(&pattern(GRAIN, 3 * GRAIN));
+        expected
+    }
+
+    fn open_sparse(disk: MemoryDisk) -> anyhow::Result<SparseExtent<BlockDevice>> {
+        let file_size = disk.0.len() as u64;
+        SparseExtent::new(BlockDevice::new(disk), file_size)
+    }
+
+    fn read(disk: &impl ReadAt, pos: usize, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        disk.read_exact_at(pos as u64, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_sparse_extent() {
+        let extent = open_sparse(sparse_image()).unwrap();
+        assert_eq!(extent.len(), DISK_SIZE as u64);
+
+        let expected = sparse_expected();
+        assert_eq!(read(&extent, 0, DISK_SIZE), expected);
+        // Into an unallocated grain, out of a zero grain and from the first into the missing grain table
+        for pos in [GRAIN - 100, 3 * GRAIN - 100, 4 * GRAIN - 100] {
+            assert_eq!(read(&extent, pos, 200), expected[pos..pos + 200]);
+        }
+
+        let mut buf = [0u8; 64];
+        assert_eq!(extent.read_at(DISK_SIZE as u64 - 16, &mut buf).unwrap(), 16);
+        assert_eq!(extent.read_at(DISK_SIZE as u64, &mut buf).unwrap(), 0);
+    }
+
+    /// Image with the first grain compressed, the grain marker stores `size` as the compressed size
+    fn compressed_image(compressed: &[u8], size: u32) -> MemoryDisk {
+        let mut disk = MemoryDisk::default();
+        disk.write(0, &header((DISK_SIZE / SECTOR) as u64, FLAG_COMPRESSED));
+        disk.write(SECTOR, &2u32.to_le_bytes());
+        disk.write(2 * SECTOR, &4u32.to_le_bytes());
+        // Grain marker with the LBA and compressed size
+        disk.write(4 * SECTOR, &0u64.to_le_bytes());
+        disk.write(4 * SECTOR + 8, &size.to_le_bytes());
+        disk.write(4 * SECTOR + 12, compressed);
+        disk
+    }
+
+    fn compressed_grain() -> Vec<u8> {
+        let mut compressed = flate2::write::ZlibEncoder::new(vec![], Default::default());
+        compressed.write_all(&pattern(GRAIN, 0)).unwrap();
+        compressed.finish().unwrap()
+    }
+
+    #[test]
+    fn reads_compressed_grains() {
+        let compressed = compressed_grain();
+        let extent = open_sparse(compressed_image(&compressed, compressed.len() as u32)).unwrap();
+        let mut expected = vec![0u8; DISK_SIZE];
+        expected[..GRAIN].copy_from_slice(&pattern(GRAIN, 0));
+        assert_eq!(read(&extent, 0, DISK_SIZE), expected);
+        assert_eq!(read(&extent, 100, 200), expected[100..300]);
+    }
+
+    #[test]
+    fn rejects_invalid_compressed_sizes() {
+        let compressed = compressed_grain();
+        // Larger than any compressed grain, and within the bound of a grain but past the end of the file
+        for size in [u32::MAX, GRAIN as u32] {
+            let extent = open_sparse(compressed_image(&compressed, size)).unwrap();
+            let mut buf = [0u8; 16];
+            let error = extent.read_at(0, &mut buf).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{size}");
+        }
+    }
+
+    #[test]
+    fn reads_across_extents() {
+        let flat = MemoryDisk(pattern(3 * GRAIN, 7));
+        let extents = vec![
+            Extent {
+                start: 0,
+                len: DISK_SIZE as u64,
+                data: ExtentData::Sparse(open_sparse(sparse_image()).unwrap()),
+            },
+            Extent {
+                start: DISK_SIZE as u64,
+                len: GRAIN as u64,
+                data: ExtentData::Zero,
+            },
+            Extent {
+                start: (DISK_SIZE + GRAIN) as u64,
+                len: 2 * GRAIN as u64,
+                data: ExtentData::Flat {
+                    reader: BlockDevice::new(flat),
+                    offset: GRAIN as u64,
+                },
+            },
+        ];
+        let vmdk = VmdkDisk {
+            extents,
+            len: (DISK_SIZE + 3 * GRAIN) as u64,
+        };
+
+        let mut expected = sparse_expected();
+        expected.extend_from_slice(&[0; GRAIN]);
+        expected.extend_from_slice(&pattern(3 * GRAIN, 7)[GRAIN..]);
+        assert_eq!(read(&vmdk, 0, expected.len()), expected);
+        for pos in [DISK_SIZE - 100, DISK_SIZE + GRAIN - 100] {
+            assert_eq!(read(&vmdk, pos, 200), expected[pos..pos + 200]);
+        }
+    }
+
+    #[test]
+    fn rejects_grain_directory_outside_of_file() {
+        let mut disk = sparse_image();
+        disk.write(0, &header(u64::MAX / 2, 0));
+
+        let error = open_sparse(disk).err().unwrap();
+        assert!(error.to_string().contains("out of bounds"), "{error:#}");
+    }
+}
@@ -0,0 +1,53 @@
+use bootsector::pio::ReadAt;
+use ext4::Ext4Reader;
+use unix_path::PathBuf as UnixPathBuf;
+
+use crate::profile::GameProfile;
+
+/// Directories that contain per-user storage roots.
+///
+/// When reading a userdata partition directly, `/data/media/<user>` shows up as `/media/<user>`.
+/// Full system images and some emulators keep the `/data` prefix.
+const STORAGE_BASES: &[&str] = &["/media", "/data/media"];
+
+/// External storage root of a single Android user, eg. `/media/0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageRoot {
+    pub path: UnixPathBuf,
+    /// Android user id, 0 for the primary user. Secondary users and work profiles usually start at 10.
+    pub user_id: u32,
+    /// Whether the game's patch directory exists under this root
+    pub has_game_data: bool,
+}
+
+/// Finds all user storage roots on the filesystem, sorted by user id
+pub fn probe_storage_roots<R: ReadAt>(
+    ext4: &Ext4Reader<R>,
+    profile: &GameProfile,
+) -> Vec<StorageRoot> {
+    let mut roots = vec![];
+    for base in STORAGE_BASES {
+        let Ok(entries) = ext4.read_dir(base) else {
+            continue;
+        };
+
+        for entry in entries {
+            let Ok(user_id) = entry.name.parse::<u32>() else {
+                continue;
+            };
+            if !entry.is_dir {
+                continue;
+            }
+
+            let has_game_data = ext4.exists(profile.patch_dir(&entry.path));
+            roots.push(StorageRoot {
+                path: entry.path,
+                user_id,
+                has_game_data,
+            });
+        }
+    }
+
+    roots.sort_by_key(|r| r.user_id);
+    roots
+}
@@ -0,0 +1,403 @@
+//! Synthetic ext4 images and Android binary XML documents for tests.
+//!
+//! Images have a single block group, 4 KiB blocks, 256 byte inodes and every file stored in a single extent.
+//! Directories have to fit in a single block.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use ext4::Ext4Reader;
+
+use crate::{
+    Filesystem,
+    axml::Value,
+    profile::GameProfile,
+    sources::{BlockDevice, MemoryDisk},
+};
+
+/// Patch directory of the global release under the `/media/0` storage root
+const PATCH_DIR: &str = "/media/0/Android/data/com.netease.g108na/files/LocalData/Patch";
+
+const BLOCK_SIZE: usize = 4096;
+const INODE_SIZE: usize = 256;
+const INODE_COUNT: usize = 64;
+const ROOT_INODE: u32 = 2;
+const LOST_AND_FOUND_INODE: u32 = 11;
+
+const BLOCK_BITMAP: usize = 2;
+const INODE_BITMAP: usize = 3;
+const INODE_TABLE: usize = 4;
+const FIRST_DATA_BLOCK: usize = INODE_TABLE + INODE_COUNT * INODE_SIZE / BLOCK_SIZE;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_EXTENTS: u32 = 0x40;
+const INODE_FLAG_EXTENTS: u32 = 0x80000;
+const MODE_DIRECTORY: u16 = 0o040755;
+const MODE_FILE: u16 = 0o100644;
+
+enum Node {
+    Directory(BTreeMap<String, Node>),
+    File(Vec<u8>),
+}
+
+/// Builds an ext4 image from a list of files, parent directories are created as needed
+#[derive(Default)]
+pub(crate) struct Ext4Builder {
+    root: BTreeMap<String, Node>,
+}
+
+impl Ext4Builder {
+    /// Adds a file at the given absolute path
+    pub fn file(mut self, path: &str, data: impl Into<Vec<u8>>) -> Self {
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let name = components.pop().expect("file path has no name");
+
+        let mut dir = &mut self.root;
+        for component in components {
+            let node = dir
+                .entry(component.to_string())
+                .or_insert_with(|| Node::Directory(BTreeMap::new()));
+            let Node::Directory(children) = node else {
+                panic!("{component} is a file");
+            };
+            dir = children;
+        }
+        dir.insert(name.to_string(), Node::File(data.into()));
+
+        self
+    }
+
+    /// Adds a patch to the patch directory of the global release, named `Patch`, `Patch1` and so on by `index`.
+    /// Files are stored in order without compression.
+    pub fn patch(self, index: usize, files: &[(&str, &[u8])]) -> Self {
+        let (mut mpkinfo, mut mpk) = (vec![], vec![]);
+        for (path, data) in files {
+            mpkinfo.extend(mpkinfo_entry(path, mpk.len() as u64, data.len() as u64));
+            mpk.extend_from_slice(data);
+        }
+
+        let naming = GameProfile::global().patch_naming;
+        self.file(
+            &format!("{PATCH_DIR}/{}", naming.file_name(index, "mpkinfo")),
+            mpkinfo,
+        )
+        .file(
+            &format!("{PATCH_DIR}/{}", naming.file_name(index, "mpk")),
+            mpk,
+        )
+    }
+
+    pub fn build(mut self) -> MemoryDisk {
+        self.root
+            .insert("lost+found".to_string(), Node::Directory(BTreeMap::new()));
+
+        let mut image = Image {
+            disk: MemoryDisk::default(),
+            next_inode: LOST_AND_FOUND_INODE + 1,
+            next_block: FIRST_DATA_BLOCK,
+            directories: 0,
+        };
+        image.write_directory(ROOT_INODE, ROOT_INODE, &self.root);
+
+        let block_count = image.next_block + 16;
+        image.disk.write(block_count * BLOCK_SIZE - 1, &[0]);
+        image.write_metadata(block_count);
+        image.disk
+    }
+}
+
+/// Opens an image with patches of the global release stored under `/media/0`
+pub(crate) fn open_filesystem(disk: MemoryDisk, cache_path: Option<PathBuf>) -> Filesystem {
+    let ext4 = Ext4Reader::new(BlockDevice::new(disk)).unwrap();
+    Filesystem::open_indexed(ext4, GameProfile::global(), "/media/0".into(), cache_path).unwrap()
+}
+
+fn mpkinfo_entry(path: &str, offset: u64, length: u64) -> Vec<u8> {
+    let mut entry = (path.len() as u32).to_le_bytes().to_vec();
+    entry.extend_from_slice(path.as_bytes());
+    entry.extend_from_slice(&0u64.to_le_bytes()); // asset_id
+    entry.extend_from_slice(&length.to_le_bytes());
+    entry.extend_from_slice(&0u16.to_le_bytes()); // index
+    entry.extend_from_slice(&[b'0'; 32]); // hash
+    entry.extend_from_slice(&0u16.to_le_bytes()); // flags
+    entry.extend_from_slice(&offset.to_le_bytes());
+    entry
+}
+
+struct Image {
+    disk: MemoryDisk,
+    next_inode: u32,
+    next_block: usize,
+    directories: u16,
+}
+
+impl Image {
+    fn allocate_inode(&mut self, name: &str) -> u32 {
+        if name == "lost+found" {
+            return LOST_AND_FOUND_INODE;
+        }
+
+        let inode = self.next_inode;
+        assert!((inode as usize) <= INODE_COUNT, "too many files");
+        self.next_inode += 1;
+        inode
+    }
+
+    /// Writes data to newly allocated blocks, returning the first block
+    fn write_blocks(&mut self, data: &[u8]) -> usize {
+        let block = self.next_block;
+        self.disk.write(block * BLOCK_SIZE, data);
+        self.next_block += data.len().div_ceil(BLOCK_SIZE);
+        block
+    }
+
+    fn write_directory(&mut self, inode: u32, parent: u32, children: &BTreeMap<String, Node>) {
+        let mut entries = vec![(".", inode, 2u8), ("..", parent, 2)];
+        let mut subdirectories = vec![];
+        for (name, node) in children {
+            let child = self.allocate_inode(name);
+            match node {
+                Node::Directory(grandchildren) => {
+                    entries.push((name.as_str(), child, 2));
+                    subdirectories.push((child, grandchildren));
+                }
+                Node::File(data) => {
+                    entries.push((name.as_str(), child, 1));
+                    let block = self.write_blocks(data);
+                    self.write_inode(child, MODE_FILE, 1, data.len(), block);
+                }
+            }
+        }
+
+        let mut block = vec![];
+        for (i, (name, entry_inode, file_type)) in entries.iter().enumerate() {
+            let mut rec_len = (8 + name.len()).next_multiple_of(4);
+            if i == entries.len() - 1 {
+                rec_len = BLOCK_SIZE - block.len();
+            }
+            block.extend_from_slice(&entry_inode.to_le_bytes());
+            block.extend_from_slice(&(rec_len as u16).to_le_bytes());
+            block.extend_from_slice(&[name.len() as u8, *file_type]);
+            block.extend_from_slice(name.as_bytes());
+            block.resize(block.len() + rec_len - 8 - name.len(), 0);
+        }
+        assert_eq!(block.len(), BLOCK_SIZE, "directory doesn't fit in a block");
+
+        let links = 2 + subdirectories.len() as u16;
+        let first_block = self.write_blocks(&block);
+        self.write_inode(inode, MODE_DIRECTORY, links, BLOCK_SIZE, first_block);
+        self.directories += 1;
+
+        for (child, grandchildren) in subdirectories {
+            self.write_directory(child, inode, grandchildren);
+        }
+    }
+
+    fn write_inode(&mut self, inode: u32, mode: u16, links: u16, size: usize, block: usize) {
+        let blocks = size.div_ceil(BLOCK_SIZE);
+        let mut data = vec![0u8; INODE_SIZE];
+        data[0..2].copy_from_slice(&mode.to_le_bytes());
+        data[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        data[26..28].copy_from_slice(&links.to_le_bytes());
+        data[28..32].copy_from_slice(&((blocks * BLOCK_SIZE / 512) as u32).to_le_bytes());
+        data[32..36].copy_from_slice(&INODE_FLAG_EXTENTS.to_le_bytes());
+        data[108..112].copy_from_slice(&((size as u64 >> 32) as u32).to_le_bytes());
+        data[128..130].copy_from_slice(&32u16.to_le_bytes()); // i_extra_isize
+
+        // Extent tree with a single leaf in the inode itself
+        let extents = (blocks > 0) as u16;
+        let i_block = &mut data[40..100];
+        i_block[0..2].copy_from_slice(&0xF30Au16.to_le_bytes());
+        i_block[2..4].copy_from_slice(&extents.to_le_bytes());
+        i_block[4..6].copy_from_slice(&4u16.to_le_bytes()); // eh_max
+        if blocks > 0 {
+            i_block[16..18].copy_from_slice(&(blocks as u16).to_le_bytes());
+            i_block[20..24].copy_from_slice(&(block as u32).to_le_bytes());
+        }
+
+        let offset = INODE_TABLE * BLOCK_SIZE + (inode as usize - 1) * INODE_SIZE;
+        self.disk.write(offset, &data);
+    }
+
+    fn write_metadata(&mut self, block_count: usize) {
+        let used_inodes = self.next_inode as usize - 1;
+        let free_blocks = (block_count - self.next_block) as u32;
+        let free_inodes = (INODE_COUNT - used_inodes) as u32;
+
+        let mut superblock = vec![0u8; 1024];
+        let mut put = |offset: usize, value: &[u8]| {
+            superblock[offset..offset + value.len()].copy_from_slice(value)
+        };
+        put(0, &(INODE_COUNT as u32).to_le_bytes());
+        put(4, &(block_count as u32).to_le_bytes());
+        put(12, &free_blocks.to_le_bytes());
+        put(16, &free_inodes.to_le_bytes());
+        put(24, &2u32.to_le_bytes()); // s_log_block_size
+        put(28, &2u32.to_le_bytes()); // s_log_cluster_size
+        put(32, &32768u32.to_le_bytes()); // s_blocks_per_group
+        put(36, &32768u32.to_le_bytes()); // s_clusters_per_group
+        put(40, &(INODE_COUNT as u32).to_le_bytes());
+        put(54, &u16::MAX.to_le_bytes()); // s_max_mnt_count
+        put(56, &0xEF53u16.to_le_bytes());
+        put(58, &1u16.to_le_bytes()); // s_state, cleanly unmounted
+        put(60, &1u16.to_le_bytes()); // s_errors, continue
+        put(76, &1u32.to_le_bytes()); // s_rev_level, dynamic
+        put(84, &(LOST_AND_FOUND_INODE).to_le_bytes()); // s_first_ino
+        put(88, &(INODE_SIZE as u16).to_le_bytes());
+        put(96, &(INCOMPAT_FILETYPE | INCOMPAT_EXTENTS).to_le_bytes());
+        put(104, b"gwynn-test-image");
+        put(0x15C, &32u16.to_le_bytes()); // s_min_extra_isize
+        put(0x15E, &32u16.to_le_bytes()); // s_want_extra_isize
+        self.disk.write(1024, &superblock);
+
+        let mut descriptor = vec![0u8; 32];
+        descriptor[0..4].copy_from_slice(&(BLOCK_BITMAP as u32).to_le_bytes());
+        descriptor[4..8].copy_from_slice(&(INODE_BITMAP as u32).to_le_bytes());
+        descriptor[8..12].copy_from_slice(&(INODE_TABLE as u32).to_le_bytes());
+        descriptor[12..14].copy_from_slice(&(free_blocks as u16).to_le_bytes());
+        descriptor[14..16].copy_from_slice(&(free_inodes as u16).to_le_bytes());
+        descriptor[16..18].copy_from_slice(&self.directories.to_le_bytes());
+        self.disk.write(BLOCK_SIZE, &descriptor);
+
+        // Bits past the end of the group are set as well
+        let bitmap = |used: usize, count: usize| {
+            let mut bitmap = vec![0u8; BLOCK_SIZE];
+            for bit in (0..used).chain(count..BLOCK_SIZE * 8) {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+            bitmap
+        };
+        self.disk.write(
+            BLOCK_BITMAP * BLOCK_SIZE,
+            &bitmap(self.next_block, block_count),
+        );
+        self.disk
+            .write(INODE_BITMAP * BLOCK_SIZE, &bitmap(used_inodes, INODE_COUNT));
+    }
+}
+
+/// Name, resource id and value of an attribute
+type TestAttribute = (String, Option<u32>, Value);
+
+/// Builds a binary XML document of start elements, the way `aapt2` writes manifests
+pub(crate) struct AxmlBuilder {
+    utf8: bool,
+    /// Name and attributes of every element
+    elements: Vec<(String, Vec<TestAttribute>)>,
+}
+
+impl AxmlBuilder {
+    pub fn new(utf8: bool) -> Self {
+        Self {
+            utf8,
+            elements: vec![],
+        }
+    }
+
+    pub fn element(mut self, name: &str, attributes: &[(&str, Option<u32>, Value)]) -> Self {
+        let attributes = attributes
+            .iter()
+            .map(|(name, id, value)| (name.to_string(), *id, value.clone()))
+            .collect();
+        self.elements.push((name.to_string(), attributes));
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        // Attribute names with resource ids come first, their index in the pool is their index in the resource map
+        let mut strings: Vec<String> = vec![];
+        let mut resource_ids = vec![];
+        for (_, attributes) in &self.elements {
+            for (name, id, _) in attributes {
+                if let Some(id) = id
+                    && !strings.contains(name)
+                {
+                    strings.push(name.clone());
+                    resource_ids.push(*id);
+                }
+            }
+        }
+        let mut intern = |s: &str| match strings.iter().position(|x| x == s) {
+            Some(i) => i as u32,
+            None => {
+                strings.push(s.to_string());
+                strings.len() as u32 - 1
+            }
+        };
+
+        let mut element_chunks = vec![];
+        for (name, attributes) in &self.elements {
+            let mut chunk = vec![];
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // line number
+            chunk.extend_from_slice(&u32::MAX.to_le_bytes()); // comment
+            chunk.extend_from_slice(&u32::MAX.to_le_bytes()); // namespace
+            chunk.extend_from_slice(&intern(name).to_le_bytes());
+            chunk.extend_from_slice(&20u16.to_le_bytes()); // attribute_start
+            chunk.extend_from_slice(&20u16.to_le_bytes()); // attribute_size
+            chunk.extend_from_slice(&(attributes.len() as u16).to_le_bytes());
+            chunk.extend_from_slice(&[0; 6]); // id, class and style indices
+
+            for (name, _, value) in attributes {
+                let (raw_value, data_type, data) = match value {
+                    Value::String(s) => {
+                        let index = intern(s);
+                        (index, 0x03, index)
+                    }
+                    Value::Int(v) => (u32::MAX, 0x10, *v),
+                    Value::Bool(v) => (u32::MAX, 0x12, if *v { u32::MAX } else { 0 }),
+                    Value::Other { data_type, data } => (u32::MAX, *data_type, *data),
+                };
+                chunk.extend_from_slice(&u32::MAX.to_le_bytes()); // namespace
+                chunk.extend_from_slice(&intern(name).to_le_bytes());
+                chunk.extend_from_slice(&raw_value.to_le_bytes());
+                chunk.extend_from_slice(&8u16.to_le_bytes());
+                chunk.extend_from_slice(&[0, data_type]);
+                chunk.extend_from_slice(&data.to_le_bytes());
+            }
+            element_chunks.push(xml_chunk(0x0102, 16, &chunk));
+        }
+
+        let mut string_data = vec![];
+        let mut offsets = vec![];
+        for s in &strings {
+            offsets.extend_from_slice(&(string_data.len() as u32).to_le_bytes());
+            if self.utf8 {
+                let utf16_len = s.encode_utf16().count();
+                string_data.extend_from_slice(&[utf16_len as u8, s.len() as u8]);
+                string_data.extend_from_slice(s.as_bytes());
+                string_data.push(0);
+            } else {
+                let units: Vec<u16> = s.encode_utf16().collect();
+                string_data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+                string_data.extend(units.iter().chain([&0]).flat_map(|u| u.to_le_bytes()));
+            }
+        }
+        string_data.resize(string_data.len().next_multiple_of(4), 0);
+
+        let mut pool = vec![];
+        pool.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        pool.extend_from_slice(&0u32.to_le_bytes()); // style count
+        pool.extend_from_slice(&(if self.utf8 { 1u32 << 8 } else { 0 }).to_le_bytes());
+        pool.extend_from_slice(&(28 + offsets.len() as u32).to_le_bytes()); // strings_start
+        pool.extend_from_slice(&0u32.to_le_bytes()); // styles_start
+        pool.extend_from_slice(&offsets);
+        pool.extend_from_slice(&string_data);
+
+        let resource_map: Vec<u8> = resource_ids
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        let mut body = xml_chunk(0x0001, 28, &pool);
+        body.extend(xml_chunk(0x0180, 8, &resource_map));
+        body.extend(element_chunks.concat());
+        xml_chunk(0x0003, 8, &body)
+    }
+}
+
+/// A chunk with a header of `header_size` bytes, the part of the header past the type and sizes is part of `data`
+fn xml_chunk(chunk_type: u16, header_size: u16, data: &[u8]) -> Vec<u8> {
+    let mut chunk = chunk_type.to_le_bytes().to_vec();
+    chunk.extend_from_slice(&header_size.to_le_bytes());
+    chunk.extend_from_slice(&(8 + data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
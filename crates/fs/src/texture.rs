@@ -0,0 +1,205 @@
+//! Converting textures while exporting or mounting, decoded on the CPU by `gwynn-texture`.
+
+use std::{collections::BTreeMap, io::Cursor};
+
+use gwynn_texture::{
+    container::{self, ContainerFormat},
+    export::{ExportOptions, ImageFormat},
+};
+use serde_json::{Value, json};
+use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
+
+use crate::{export::ExportConverter, filetype::FileType, manifest::MetadataProvider};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureOutput {
+    /// Decodes the full size mip, with options matching the pixel format of the texture
+    Image(ImageFormat),
+    /// Rewraps every mip, layer and face without decoding them
+    Container(ContainerFormat),
+}
+
+impl TextureOutput {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Image(format) => format.extension(),
+            Self::Container(ContainerFormat::Dds) => "dds",
+            Self::Container(ContainerFormat::Ktx2) => "ktx2",
+        }
+    }
+}
+
+/// Converts textures to images or DDS/KTX2 files, other files are left as they are.
+///
+/// The extension of the output is appended to the original file name, eg. `<uuid>.1.png`, as textures with the same
+/// UUID and different extensions are different files.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureConverter {
+    pub output: TextureOutput,
+}
+
+impl TextureConverter {
+    pub fn new(output: TextureOutput) -> Self {
+        Self { output }
+    }
+
+    /// Converts the decompressed contents of a texture file
+    pub fn convert_texture(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut output = Cursor::new(vec![]);
+        match self.output {
+            TextureOutput::Image(format) => {
+                let header = gwynn_texture::read_header(data)?;
+                let image = gwynn_texture::decode(data)?;
+                gwynn_texture::export::write_image(
+                    &image,
+                    format,
+                    &ExportOptions::from_header(&header),
+                    &mut output,
+                )?;
+            }
+            TextureOutput::Container(format) => {
+                container::write_container(data, format, &mut output)?
+            }
+        }
+
+        Ok(output.into_inner())
+    }
+
+    /// Path of the converted copy of a file
+    pub fn converted_path(&self, path: &UnixPath) -> UnixPathBuf {
+        format!("{}.{}", path.to_string_lossy(), self.output.extension()).into()
+    }
+}
+
+impl ExportConverter for TextureConverter {
+    fn convert(
+        &self,
+        path: &UnixPath,
+        file_type: FileType,
+        data: &[u8],
+    ) -> anyhow::Result<Option<(UnixPathBuf, Vec<u8>)>> {
+        if file_type != FileType::Texture {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            self.converted_path(path),
+            self.convert_texture(data)?,
+        )))
+    }
+}
+
+/// Dimensions and format of [`FileType::Texture`] files
+pub struct TextureMetadata;
+
+impl MetadataProvider for TextureMetadata {
+    fn applies_to(&self, file_type: FileType) -> bool {
+        file_type == FileType::Texture
+    }
+
+    fn metadata(&self, _path: &UnixPath, data: &[u8]) -> anyhow::Result<BTreeMap<String, Value>> {
+        let header = gwynn_texture::read_header(data)?;
+
+        Ok(BTreeMap::from([
+            ("width".to_string(), json!(header.width)),
+            ("height".to_string(), json!(header.height)),
+            ("format".to_string(), json!(format!("{:?}", header.format))),
+            ("mip_count".to_string(), json!(header.mip_count)),
+            (
+                "texture_type".to_string(),
+                json!(format!("{:?}", header.texture_type)),
+            ),
+        ]))
+    }
+}
+
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+impl crate::mount::SyntheticView for TextureConverter {
+    fn extension(&self) -> &str {
+        self.output.extension()
+    }
+
+    fn applies_to(&self, file_type: FileType) -> bool {
+        file_type == FileType::Texture
+    }
+
+    fn convert(&self, _path: &UnixPath, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.convert_texture(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 uncompressed RGBA8 texture with a single mip
+    fn texture() -> Vec<u8> {
+        let mut data = 0u32.to_le_bytes().to_vec();
+        // Filters and address modes, then the format, mip level, flags, compression preset, LOD group, mip
+        // generation preset and texture type
+        data.extend_from_slice(&[1, 1, 1, 1, 1, 3, 1, 0, 8, 0, 0, 1]);
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&[0; 16]); // default_color
+        data.extend_from_slice(&16u32.to_le_bytes()); // size
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes()); // mip_count
+
+        let pixels: Vec<u8> = (0..16).map(|i| i * 16).collect();
+        data.extend_from_slice(&(16 + pixels.len() as u32).to_le_bytes()); // total_size
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+        data.extend_from_slice(&pixels);
+        data
+    }
+
+    #[test]
+    fn converts_textures_only() {
+        let converter = TextureConverter::new(TextureOutput::Image(ImageFormat::Png));
+        let path = UnixPath::new("tx/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10.1");
+
+        let (converted_path, png) = converter
+            .convert(path, FileType::Texture, &texture())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            converted_path,
+            UnixPath::new("tx/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10.1.png")
+        );
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        assert!(
+            converter
+                .convert(path, FileType::Json, b"{}")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn texture_metadata() {
+        let path = UnixPath::new("tx/0b2e4b4c-8f4e-4b8e-9f53-2f0d1f6f2a10.1");
+        assert!(TextureMetadata.applies_to(FileType::Texture));
+        assert!(!TextureMetadata.applies_to(FileType::Model));
+        assert_eq!(
+            TextureMetadata.metadata(path, &texture()).unwrap(),
+            BTreeMap::from([
+                ("width".to_string(), json!(2)),
+                ("height".to_string(), json!(2)),
+                ("format".to_string(), json!("R8G8B8A8")),
+                ("mip_count".to_string(), json!(1)),
+                ("texture_type".to_string(), json!("Texture2D")),
+            ])
+        );
+    }
+
+    #[test]
+    fn rewraps_textures_in_containers() {
+        let converter = TextureConverter::new(TextureOutput::Container(ContainerFormat::Dds));
+        let dds = converter.convert_texture(&texture()).unwrap();
+        assert!(dds.starts_with(b"DDS "));
+        assert!(dds.ends_with(&(0..16).map(|i| i * 16).collect::<Vec<u8>>()));
+    }
+}
@@ -6,7 +6,7 @@ use std::{
 use anyhow::{ensure, Context};
 use log::warn;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CompressionType {
     None,
     Zlib,
@@ -38,12 +38,39 @@ impl CompressionType {
             _ => None,
         }
     }
+
+    /// Returns the decompressed size stored in the compression header.
+    ///
+    /// Only the first 8 bytes of the data are needed. Uncompressed (`NNNN`) and zlib data don't store their size.
+    pub fn decompressed_size_from_slice(buf: &[u8]) -> Option<usize> {
+        match Self::detect_from_slice(buf)? {
+            CompressionType::None | CompressionType::Zlib => None,
+            _ => Some(u32::from_le_bytes(buf.get(4..8)?.try_into().unwrap()) as usize),
+        }
+    }
+}
+
+/// XOR encryption applied to the start of G108 LZ4/ZSTD payloads
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encryption {
+    /// Keyed encryption used since the beta
+    #[default]
+    Beta,
+    /// Single byte XOR used in the alpha
+    Alpha,
 }
 
 /// Decompresses the given buffer.
 ///
 /// This function may modify the given buffer due to the use of in-place XOR encryption.
 pub fn decompress(buf: &mut [u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    decompress_with(buf, Encryption::default())
+}
+
+/// Decompresses the given buffer, using the given flavor of the G108 XOR encryption.
+///
+/// This function may modify the given buffer due to the use of in-place XOR encryption.
+pub fn decompress_with(buf: &mut [u8], encryption: Encryption) -> anyhow::Result<Cow<'_, [u8]>> {
     match CompressionType::detect_from_slice(buf) {
         Some(CompressionType::Zlib) => {
             let input = unxor_zlib(buf);
@@ -58,7 +85,11 @@ pub fn decompress(buf: &mut [u8]) -> anyhow::Result<Cow<'_, [u8]>> {
             v.copy_from_slice(&buf[4..8]);
             let mut uncompressed_size = u32::from_le_bytes(v) as usize;
             let real_uncompressed_size = uncompressed_size;
-            let input = if c.is_g108() { unxor(buf) } else { &buf[8..] };
+            let input = if c.is_g108() {
+                unxor(buf, encryption)
+            } else {
+                &buf[8..]
+            };
 
             let mut decompressed_bytes = loop {
                 match lz4_flex::decompress(input, uncompressed_size) {
@@ -99,7 +130,11 @@ pub fn decompress(buf: &mut [u8]) -> anyhow::Result<Cow<'_, [u8]>> {
             let mut v = [0u8; 4];
             v.copy_from_slice(&buf[4..8]);
             let uncompressed_size = u32::from_le_bytes(v);
-            let input = if c.is_g108() { unxor(buf) } else { &buf[8..] };
+            let input = if c.is_g108() {
+                unxor(buf, encryption)
+            } else {
+                &buf[8..]
+            };
 
             let mut out_buf = vec![];
             let mut decompressor = zstd::stream::Decoder::new(Cursor::new(input))?;
@@ -129,14 +164,13 @@ const XOR_KEY: &[u8] = &[
 /// Applies the ZSTD/LZ4 flavor of the XOR encryption to the given buffer.
 ///
 /// Pass in the data __with__ the identifier+size header. This function will return a slice that can be passed to the decompressor.
-fn unxor(buf: &mut [u8]) -> &[u8] {
+fn unxor(buf: &mut [u8], encryption: Encryption) -> &[u8] {
     let xor_size = (buf.len() - 8).clamp(0, 256);
     for (i, x) in buf[8..8 + xor_size].iter_mut().enumerate() {
-        // New encrypted used since the beta
-        *x = !(*x ^ XOR_KEY[i % XOR_KEY.len()]);
-
-        // Old encryption used in the alpha
-        // *x ^= 0x5E;
+        match encryption {
+            Encryption::Beta => *x = !(*x ^ XOR_KEY[i % XOR_KEY.len()]),
+            Encryption::Alpha => *x ^= 0x5E,
+        }
     }
 
     &buf[8..]
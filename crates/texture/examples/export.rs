@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+// Decode a Messiah texture file on the CPU and write it as .png, .tga, .exr or .hdr
+fn main() -> anyhow::Result<()> {
+    // Usage: export <texture file> [output] [--mips|--channels|--cross|--equirect]
+    let mut args = std::env::args().skip(1);
+    let input = PathBuf::from(args.next().context("Missing texture path")?);
+    let output = args.next();
+    let mode = args.next();
+
+    let data = std::fs::read(&input)?;
+    let header = gwynn_texture::read_header(&data)?;
+    let image = gwynn_texture::decode(&data)?;
+
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => {
+            input.with_extension(gwynn_texture::export::ImageFormat::preferred(&image).extension())
+        }
+    };
+
+    println!(
+        "{}x{} {:?} -> {}",
+        header.width,
+        header.height,
+        header.format,
+        output.display()
+    );
+    let unfold = match mode.as_deref() {
+        Some("--cross") => Some(gwynn_texture::cubemap::Unfold::HorizontalCross),
+        Some("--equirect") => Some(gwynn_texture::cubemap::Unfold::Equirectangular),
+        _ => None,
+    };
+    if let Some(unfold) = unfold {
+        return gwynn_texture::export::export_cubemap(&data, &output, unfold);
+    }
+
+    let paths = match mode.as_deref() {
+        Some("--mips") => gwynn_texture::export::export_mips(&data, &output)?,
+        Some("--channels") => gwynn_texture::export::export_channels(&data, &output)?,
+        _ => vec![],
+    };
+    if !paths.is_empty() {
+        for path in paths {
+            println!("Wrote {}", path.display());
+        }
+
+        return Ok(());
+    }
+
+    gwynn_texture::export::write_to_file(
+        &image,
+        &output,
+        &gwynn_texture::export::ExportOptions::from_header(&header),
+    )?;
+
+    Ok(())
+}
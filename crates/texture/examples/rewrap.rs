@@ -0,0 +1,26 @@
+use anyhow::Context;
+
+// Rewrap a Messiah texture file as .dds or .ktx2, keeping the original compressed data
+fn main() -> anyhow::Result<()> {
+    // Usage: rewrap <texture file> <output .dds/.ktx2>
+    let mut args = std::env::args().skip(1);
+    let input = std::path::PathBuf::from(args.next().context("Missing texture path")?);
+    let output = std::path::PathBuf::from(args.next().context("Missing output path")?);
+
+    let data = std::fs::read(&input)?;
+    let layout = gwynn_texture::layout::Layout::read(&data)?;
+    println!(
+        "{}x{}x{} {:?}, {} layers, {} faces, {} mips -> {}",
+        layout.width,
+        layout.height,
+        layout.depth,
+        layout.format,
+        layout.layers,
+        layout.faces,
+        layout.levels.len(),
+        output.display()
+    );
+    gwynn_texture::container::write_to_file(&data, &output)?;
+
+    Ok(())
+}
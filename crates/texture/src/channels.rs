@@ -0,0 +1,149 @@
+//! Remapping the channels of decoded images: swizzles, single channel extraction and normal map fixups.
+
+use crate::{
+    decode::{DecodedImage, Pixels},
+    format::PixelFormat,
+    structs::{TextureCompression, TextureHeader},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+    Zero,
+    One,
+}
+
+impl Channel {
+    pub const RGBA: [Channel; 4] = [Channel::R, Channel::G, Channel::B, Channel::A];
+
+    fn read(&self, pixel: [f32; 4]) -> f32 {
+        match self {
+            Channel::R => pixel[0],
+            Channel::G => pixel[1],
+            Channel::B => pixel[2],
+            Channel::A => pixel[3],
+            Channel::Zero => 0.0,
+            Channel::One => 1.0,
+        }
+    }
+
+    /// Lowercase name, used as a file name suffix
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::R => "r",
+            Channel::G => "g",
+            Channel::B => "b",
+            Channel::A => "a",
+            Channel::Zero => "zero",
+            Channel::One => "one",
+        }
+    }
+}
+
+/// Applies `f` to every pixel, 8 bit pixels are passed as 0-1 and rounded back afterwards
+fn map_pixels(image: &DecodedImage, f: impl Fn([f32; 4]) -> [f32; 4]) -> DecodedImage {
+    let pixels = match &image.pixels {
+        Pixels::Rgba8(pixels) => Pixels::Rgba8(
+            pixels
+                .chunks_exact(4)
+                .flat_map(|p| f(std::array::from_fn(|i| p[i] as f32 / 255.0)))
+                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+        ),
+        Pixels::Rgba32F(pixels) => Pixels::Rgba32F(
+            pixels
+                .chunks_exact(4)
+                .flat_map(|p| f(p.try_into().unwrap()))
+                .collect(),
+        ),
+    };
+
+    DecodedImage {
+        width: image.width,
+        height: image.height,
+        pixels,
+    }
+}
+
+/// Rearranges the channels of an image, `swizzle` holds the source of the red, green, blue and alpha channels
+pub fn swizzle(image: &DecodedImage, swizzle: [Channel; 4]) -> DecodedImage {
+    map_pixels(image, |p| swizzle.map(|c| c.read(p)))
+}
+
+/// A single channel as an opaque grayscale image
+pub fn extract(image: &DecodedImage, channel: Channel) -> DecodedImage {
+    swizzle(image, [channel, channel, channel, Channel::One])
+}
+
+/// Splits an image into grayscale images of its red, green, blue and alpha channels
+pub fn split(image: &DecodedImage) -> [DecodedImage; 4] {
+    Channel::RGBA.map(|c| extract(image, c))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalMapOptions {
+    /// Channels holding X and Y
+    pub xy: (Channel, Channel),
+    /// Compute Z from X and Y, for normal maps that only store those
+    pub reconstruct_z: bool,
+    /// Invert Y, converting between the DirectX (Y down) and OpenGL (Y up) conventions
+    pub flip_green: bool,
+}
+
+impl Default for NormalMapOptions {
+    fn default() -> Self {
+        Self {
+            xy: (Channel::R, Channel::G),
+            reconstruct_z: true,
+            flip_green: false,
+        }
+    }
+}
+
+impl NormalMapOptions {
+    /// Options for two channel normal maps, `None` for other textures.
+    ///
+    /// Compact normal maps in formats with alpha are expected in the DXT5nm layout, with X in alpha.
+    pub fn from_header(header: &TextureHeader) -> Option<Self> {
+        let xy = match header.compression_preset {
+            TextureCompression::NormalMapBC5 => (Channel::R, Channel::G),
+            TextureCompression::NormalMapCompact
+                if matches!(header.format, PixelFormat::Bc3 | PixelFormat::R8G8B8A8) =>
+            {
+                (Channel::A, Channel::G)
+            }
+            TextureCompression::NormalMapCompact => (Channel::R, Channel::G),
+            _ => return None,
+        };
+
+        Some(Self {
+            xy,
+            ..Default::default()
+        })
+    }
+}
+
+/// Moves X and Y of a normal map to red and green, optionally flipping Y and reconstructing Z in blue.
+///
+/// Values are expected in the usual 0-1 encoding of -1 to 1. Alpha is made opaque.
+pub fn process_normal_map(image: &DecodedImage, options: &NormalMapOptions) -> DecodedImage {
+    map_pixels(image, |p| {
+        let x = options.xy.0.read(p);
+        let mut y = options.xy.1.read(p);
+        if options.flip_green {
+            y = 1.0 - y;
+        }
+
+        let z = if options.reconstruct_z {
+            let (nx, ny) = (x * 2.0 - 1.0, y * 2.0 - 1.0);
+            (1.0 - nx * nx - ny * ny).max(0.0).sqrt() * 0.5 + 0.5
+        } else {
+            p[2]
+        };
+
+        [x, y, z, 1.0]
+    })
+}
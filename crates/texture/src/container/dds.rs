@@ -0,0 +1,227 @@
+//! DirectDraw Surface files with a DX10 header.
+//!
+//! Formats without a DXGI format (ETC1 and ATC) use the FourCC codes of AMD Compressonator instead, those can't be
+//! used for arrays.
+
+use std::io::Write;
+
+use anyhow::ensure;
+
+use crate::{format::PixelFormat, layout::Layout};
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDSD_DEPTH: u32 = 0x800000;
+
+const DDPF_FOURCC: u32 = 0x4;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xFE00;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Index of the ASTC block size, in the order used by the DXGI and Vulkan format lists
+pub(super) fn astc_index(format: PixelFormat) -> Option<u32> {
+    let (width, height, _) = format.block_size()?;
+    let sizes = [
+        (4, 4),
+        (5, 4),
+        (5, 5),
+        (6, 5),
+        (6, 6),
+        (8, 5),
+        (8, 6),
+        (8, 8),
+        (10, 5),
+        (10, 6),
+        (10, 8),
+        (10, 10),
+        (12, 10),
+        (12, 12),
+    ];
+
+    format
+        .is_astc()
+        .then(|| sizes.iter().position(|s| *s == (width, height)))
+        .flatten()
+        .map(|i| i as u32)
+}
+
+/// The DXGI_FORMAT matching the pixel format
+pub fn dxgi_format(format: PixelFormat, srgb: bool) -> Option<u32> {
+    let srgb = srgb as u32;
+    Some(match format {
+        PixelFormat::A32R32G32B32F | PixelFormat::FloatRGBA => 2,
+        PixelFormat::R32g32b32a32ui => 3,
+        PixelFormat::FloatRGB => 6,
+        PixelFormat::A16B16G16R16F => 10,
+        PixelFormat::G32R32F => 16,
+        PixelFormat::R10g10b10a2 => 24,
+        PixelFormat::R11g11b10 => 26,
+        PixelFormat::R8G8B8A8 => 28 + srgb,
+        PixelFormat::G16R16F => 34,
+        PixelFormat::G16R16 => 35,
+        PixelFormat::D32 | PixelFormat::Shadowdepth32 => 40,
+        PixelFormat::R32u => 42,
+        PixelFormat::D24 | PixelFormat::Shadowdepth => 45,
+        PixelFormat::A8L8 => 49,
+        PixelFormat::R16F => 54,
+        PixelFormat::L16 => 56,
+        PixelFormat::L8 => 61,
+        PixelFormat::A8 => 65,
+        PixelFormat::Bc1 => 71 + srgb,
+        PixelFormat::Bc2 => 74 + srgb,
+        PixelFormat::Bc3 => 77 + srgb,
+        PixelFormat::Bc4 => 80,
+        PixelFormat::Bc5 => 83,
+        PixelFormat::B5G6R5 => 85,
+        PixelFormat::Bc6U => 95,
+        PixelFormat::Bc6S => 96,
+        PixelFormat::Bc7 => 98 + srgb,
+        // Not part of the official list, but understood by most tools that support ASTC in DDS files
+        f if f.is_astc() && !f.is_hdr() => 134 + astc_index(f)? * 4 + srgb,
+        _ => return None,
+    })
+}
+
+fn fourcc(format: PixelFormat) -> Option<&'static [u8; 4]> {
+    Some(match format {
+        PixelFormat::Etc1 => b"ETC1",
+        PixelFormat::AtcRgbaE => b"ATCA",
+        PixelFormat::AtcRgbaI => b"ATCI",
+        _ => return None,
+    })
+}
+
+fn write_u32s<W: Write>(writer: &mut W, values: &[u32]) -> std::io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn write<W: Write>(layout: &Layout, mut writer: W) -> anyhow::Result<()> {
+    let dxgi = dxgi_format(layout.format, layout.srgb);
+    let fourcc = match dxgi {
+        Some(_) => b"DX10",
+        None => {
+            let fourcc = fourcc(layout.format).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Pixel format {:?} can't be stored in DDS files, use KTX2 instead",
+                    layout.format
+                )
+            })?;
+            ensure!(
+                !layout.is_array,
+                "Arrays of {:?} can't be stored in DDS files",
+                layout.format
+            );
+            fourcc
+        }
+    };
+
+    let level_count = layout.levels.len() as u32;
+    let is_cube = layout.faces == 6;
+    let is_3d = layout.depth > 1;
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+    let pitch_or_linear_size = if layout.format.is_compressed() {
+        flags |= DDSD_LINEARSIZE;
+        layout.surface_size(layout.width, layout.height) as u32
+    } else {
+        flags |= DDSD_PITCH;
+        layout.surface_size(layout.width, 1) as u32
+    };
+    if level_count > 1 {
+        flags |= DDSD_MIPMAPCOUNT;
+    }
+    if is_3d {
+        flags |= DDSD_DEPTH;
+    }
+
+    let mut caps = DDSCAPS_TEXTURE;
+    if level_count > 1 {
+        caps |= DDSCAPS_MIPMAP | DDSCAPS_COMPLEX;
+    }
+    if is_cube || is_3d || layout.is_array {
+        caps |= DDSCAPS_COMPLEX;
+    }
+    let caps2 = if is_cube {
+        DDSCAPS2_CUBEMAP_ALL_FACES
+    } else if is_3d {
+        DDSCAPS2_VOLUME
+    } else {
+        0
+    };
+
+    writer.write_all(b"DDS ")?;
+    write_u32s(
+        &mut writer,
+        &[
+            124,
+            flags,
+            layout.height,
+            layout.width,
+            pitch_or_linear_size,
+            if is_3d { layout.depth } else { 0 },
+            level_count,
+        ],
+    )?;
+    write_u32s(&mut writer, &[0; 11])?;
+
+    // Pixel format
+    write_u32s(&mut writer, &[32, DDPF_FOURCC])?;
+    writer.write_all(fourcc)?;
+    write_u32s(&mut writer, &[0; 5])?;
+
+    write_u32s(&mut writer, &[caps, caps2, 0, 0, 0])?;
+
+    if let Some(dxgi) = dxgi {
+        write_u32s(
+            &mut writer,
+            &[
+                dxgi,
+                if is_3d {
+                    D3D10_RESOURCE_DIMENSION_TEXTURE3D
+                } else {
+                    D3D10_RESOURCE_DIMENSION_TEXTURE2D
+                },
+                if is_cube {
+                    D3D10_RESOURCE_MISC_TEXTURECUBE
+                } else {
+                    0
+                },
+                layout.layers,
+                0,
+            ],
+        )?;
+    }
+
+    if is_3d {
+        // Volume textures store all slices of a level together, like Messiah does
+        for level in 0..layout.levels.len() {
+            writer.write_all(layout.level_data(level))?;
+        }
+    } else {
+        // Every layer and face has its own mip chain
+        for surface in 0..(layout.layers * layout.faces) as usize {
+            for level in 0..layout.levels.len() {
+                writer.write_all(layout.surface(level, surface))?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
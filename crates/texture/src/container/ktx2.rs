@@ -0,0 +1,419 @@
+//! KTX 2.0 files, with a basic data format descriptor and the levels stored smallest first.
+
+use std::io::Write;
+
+use super::dds::astc_index;
+use crate::{format::PixelFormat, layout::Layout};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+// Data format descriptor color models
+const MODEL_RGBSDA: u8 = 1;
+const MODEL_BC1A: u8 = 128;
+const MODEL_BC2: u8 = 129;
+const MODEL_BC3: u8 = 130;
+const MODEL_BC4: u8 = 131;
+const MODEL_BC5: u8 = 132;
+const MODEL_BC6H: u8 = 133;
+const MODEL_BC7: u8 = 134;
+const MODEL_ETC2: u8 = 161;
+const MODEL_ASTC: u8 = 162;
+const MODEL_PVRTC: u8 = 164;
+
+// Channels, the compressed models mostly reuse the ids of the first channels
+const CHANNEL_RED: u8 = 0;
+const CHANNEL_GREEN: u8 = 1;
+const CHANNEL_BLUE: u8 = 2;
+const CHANNEL_BC1A_ALPHA_PRESENT: u8 = 1;
+const CHANNEL_ETC2_COLOR: u8 = 2;
+const CHANNEL_DEPTH: u8 = 14;
+const CHANNEL_ALPHA: u8 = 15;
+
+// Sample qualifiers, stored in the upper bits of the channel type
+const QUALIFIER_LINEAR: u8 = 0x10;
+const QUALIFIER_SIGNED: u8 = 0x40;
+const QUALIFIER_FLOAT: u8 = 0x80;
+
+const FLOAT_MINUS_ONE: u32 = 0xBF80_0000;
+const FLOAT_ONE: u32 = 0x3F80_0000;
+
+/// A sample of the data format descriptor, describing one channel of a texel block
+struct Sample {
+    channel: u8,
+    qualifiers: u8,
+    bit_offset: u16,
+    bit_length: u8,
+    lower: u32,
+    upper: u32,
+}
+
+impl Sample {
+    fn unorm(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            channel,
+            qualifiers: 0,
+            bit_offset,
+            bit_length,
+            lower: 0,
+            upper: u32::MAX >> (32 - bit_length as u32),
+        }
+    }
+
+    fn uint(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            upper: 1,
+            ..Self::unorm(channel, bit_offset, bit_length)
+        }
+    }
+
+    fn sfloat(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            channel,
+            qualifiers: QUALIFIER_FLOAT | QUALIFIER_SIGNED,
+            bit_offset,
+            bit_length,
+            lower: FLOAT_MINUS_ONE,
+            upper: FLOAT_ONE,
+        }
+    }
+
+    fn ufloat(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            qualifiers: QUALIFIER_FLOAT,
+            lower: 0,
+            ..Self::sfloat(channel, bit_offset, bit_length)
+        }
+    }
+
+    /// A compressed block, or part of it
+    fn block(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            upper: u32::MAX,
+            ..Self::unorm(channel, bit_offset, bit_length)
+        }
+    }
+}
+
+/// Per channel samples of uncompressed formats, from the lowest bits up
+fn channels(channels: &[u8], bit_length: u8, sample: fn(u8, u16, u8) -> Sample) -> Vec<Sample> {
+    channels
+        .iter()
+        .enumerate()
+        .map(|(i, c)| sample(*c, i as u16 * bit_length as u16, bit_length))
+        .collect()
+}
+
+/// The VkFormat matching the pixel format
+pub fn vk_format(format: PixelFormat, srgb: bool, punchthrough: bool) -> Option<u32> {
+    let srgb = srgb as u32;
+    Some(match format {
+        PixelFormat::B5G6R5 => 4,
+        PixelFormat::L8 => 9,
+        PixelFormat::A8L8 => 16,
+        PixelFormat::R8G8B8A8 => 37 + srgb * 6,
+        PixelFormat::R10g10b10a2 => 64,
+        PixelFormat::L16 => 70,
+        PixelFormat::R16F => 76,
+        PixelFormat::G16R16 => 77,
+        PixelFormat::G16R16F => 83,
+        PixelFormat::A16B16G16R16F => 97,
+        PixelFormat::R32u => 98,
+        PixelFormat::G32R32F => 103,
+        PixelFormat::FloatRGB => 106,
+        PixelFormat::R32g32b32a32ui => 107,
+        PixelFormat::A32R32G32B32F | PixelFormat::FloatRGBA => 109,
+        PixelFormat::R11g11b10 => 122,
+        PixelFormat::D24 | PixelFormat::Shadowdepth => 125,
+        PixelFormat::D32 | PixelFormat::Shadowdepth32 => 126,
+        PixelFormat::Bc1 => 133 + srgb,
+        PixelFormat::Bc2 => 135 + srgb,
+        PixelFormat::Bc3 => 137 + srgb,
+        PixelFormat::Bc4 => 139,
+        PixelFormat::Bc5 => 141,
+        PixelFormat::Bc6U => 143,
+        PixelFormat::Bc6S => 144,
+        PixelFormat::Bc7 => 145 + srgb,
+        // ETC1 is a subset of ETC2
+        PixelFormat::Etc1 | PixelFormat::Etc2Rgb => 147 + srgb,
+        PixelFormat::Etc2Rgba if punchthrough => 149 + srgb,
+        PixelFormat::Etc2Rgba => 151 + srgb,
+        PixelFormat::Pvrtc2Rgb | PixelFormat::Pvrtc2Rgba => 1000054000 + srgb * 4,
+        PixelFormat::Pvrtc4Rgb | PixelFormat::Pvrtc4Rgba => 1000054001 + srgb * 4,
+        PixelFormat::A8 => 1000470001,
+        f if f.is_astc() && f.is_hdr() => 1000066000 + astc_index(f)?,
+        f if f.is_astc() => 157 + astc_index(f)? * 2 + srgb,
+        _ => return None,
+    })
+}
+
+/// Size of the components of the format, 1 for block compressed formats
+fn type_size(format: PixelFormat) -> u32 {
+    match format {
+        PixelFormat::B5G6R5
+        | PixelFormat::L16
+        | PixelFormat::R16F
+        | PixelFormat::G16R16
+        | PixelFormat::G16R16F
+        | PixelFormat::A16B16G16R16F => 2,
+        PixelFormat::R10g10b10a2
+        | PixelFormat::R32u
+        | PixelFormat::G32R32F
+        | PixelFormat::FloatRGB
+        | PixelFormat::R32g32b32a32ui
+        | PixelFormat::A32R32G32B32F
+        | PixelFormat::FloatRGBA
+        | PixelFormat::R11g11b10
+        | PixelFormat::D24
+        | PixelFormat::Shadowdepth
+        | PixelFormat::D32
+        | PixelFormat::Shadowdepth32 => 4,
+        _ => 1,
+    }
+}
+
+/// Color model and samples of the data format descriptor
+fn descriptor(layout: &Layout) -> (u8, Vec<Sample>) {
+    const RGBA: &[u8] = &[CHANNEL_RED, CHANNEL_GREEN, CHANNEL_BLUE, CHANNEL_ALPHA];
+    const RGB: &[u8] = &[CHANNEL_RED, CHANNEL_GREEN, CHANNEL_BLUE];
+    const RG: &[u8] = &[CHANNEL_RED, CHANNEL_GREEN];
+
+    let hdr_block = |channel, bit_length, signed| Sample {
+        qualifiers: QUALIFIER_FLOAT | if signed { QUALIFIER_SIGNED } else { 0 },
+        lower: if signed { FLOAT_MINUS_ONE } else { 0 },
+        upper: FLOAT_ONE,
+        ..Sample::block(channel, 0, bit_length)
+    };
+
+    let (model, samples) = match layout.format {
+        PixelFormat::R8G8B8A8 => (MODEL_RGBSDA, channels(RGBA, 8, Sample::unorm)),
+        PixelFormat::B5G6R5 => (
+            MODEL_RGBSDA,
+            vec![
+                Sample::unorm(CHANNEL_BLUE, 0, 5),
+                Sample::unorm(CHANNEL_GREEN, 5, 6),
+                Sample::unorm(CHANNEL_RED, 11, 5),
+            ],
+        ),
+        PixelFormat::A8L8 => (MODEL_RGBSDA, channels(RG, 8, Sample::unorm)),
+        PixelFormat::L8 => (MODEL_RGBSDA, channels(&[CHANNEL_RED], 8, Sample::unorm)),
+        PixelFormat::A8 => (MODEL_RGBSDA, channels(&[CHANNEL_ALPHA], 8, Sample::unorm)),
+        PixelFormat::L16 => (MODEL_RGBSDA, channels(&[CHANNEL_RED], 16, Sample::unorm)),
+        PixelFormat::G16R16 => (MODEL_RGBSDA, channels(RG, 16, Sample::unorm)),
+        PixelFormat::R16F => (MODEL_RGBSDA, channels(&[CHANNEL_RED], 16, Sample::sfloat)),
+        PixelFormat::G16R16F => (MODEL_RGBSDA, channels(RG, 16, Sample::sfloat)),
+        PixelFormat::A16B16G16R16F => (MODEL_RGBSDA, channels(RGBA, 16, Sample::sfloat)),
+        PixelFormat::G32R32F => (MODEL_RGBSDA, channels(RG, 32, Sample::sfloat)),
+        PixelFormat::FloatRGB => (MODEL_RGBSDA, channels(RGB, 32, Sample::sfloat)),
+        PixelFormat::A32R32G32B32F | PixelFormat::FloatRGBA => {
+            (MODEL_RGBSDA, channels(RGBA, 32, Sample::sfloat))
+        }
+        PixelFormat::R32u => (MODEL_RGBSDA, channels(&[CHANNEL_RED], 32, Sample::uint)),
+        PixelFormat::R32g32b32a32ui => (MODEL_RGBSDA, channels(RGBA, 32, Sample::uint)),
+        PixelFormat::R10g10b10a2 => (
+            MODEL_RGBSDA,
+            vec![
+                Sample::unorm(CHANNEL_RED, 0, 10),
+                Sample::unorm(CHANNEL_GREEN, 10, 10),
+                Sample::unorm(CHANNEL_BLUE, 20, 10),
+                Sample::unorm(CHANNEL_ALPHA, 30, 2),
+            ],
+        ),
+        PixelFormat::R11g11b10 => (
+            MODEL_RGBSDA,
+            vec![
+                Sample::ufloat(CHANNEL_RED, 0, 11),
+                Sample::ufloat(CHANNEL_GREEN, 11, 11),
+                Sample::ufloat(CHANNEL_BLUE, 22, 10),
+            ],
+        ),
+        PixelFormat::D24 | PixelFormat::Shadowdepth => {
+            (MODEL_RGBSDA, vec![Sample::unorm(CHANNEL_DEPTH, 0, 24)])
+        }
+        PixelFormat::D32 | PixelFormat::Shadowdepth32 => {
+            (MODEL_RGBSDA, vec![Sample::ufloat(CHANNEL_DEPTH, 0, 32)])
+        }
+        PixelFormat::Bc1 => (
+            MODEL_BC1A,
+            vec![Sample::block(CHANNEL_BC1A_ALPHA_PRESENT, 0, 64)],
+        ),
+        PixelFormat::Bc2 => (
+            MODEL_BC2,
+            vec![
+                Sample::block(CHANNEL_ALPHA, 0, 64),
+                Sample::block(0, 64, 64),
+            ],
+        ),
+        PixelFormat::Bc3 => (
+            MODEL_BC3,
+            vec![
+                Sample::block(CHANNEL_ALPHA, 0, 64),
+                Sample::block(0, 64, 64),
+            ],
+        ),
+        PixelFormat::Bc4 => (MODEL_BC4, vec![Sample::block(0, 0, 64)]),
+        PixelFormat::Bc5 => (
+            MODEL_BC5,
+            vec![Sample::block(0, 0, 64), Sample::block(1, 64, 64)],
+        ),
+        PixelFormat::Bc6U => (MODEL_BC6H, vec![hdr_block(0, 128, false)]),
+        PixelFormat::Bc6S => (MODEL_BC6H, vec![hdr_block(0, 128, true)]),
+        PixelFormat::Bc7 => (MODEL_BC7, vec![Sample::block(0, 0, 128)]),
+        // Stored as ETC2, like the VkFormat
+        PixelFormat::Etc1 | PixelFormat::Etc2Rgb => {
+            (MODEL_ETC2, vec![Sample::block(CHANNEL_ETC2_COLOR, 0, 64)])
+        }
+        PixelFormat::Etc2Rgba if layout.punchthrough => {
+            (MODEL_ETC2, vec![Sample::block(CHANNEL_ETC2_COLOR, 0, 64)])
+        }
+        PixelFormat::Etc2Rgba => (
+            MODEL_ETC2,
+            vec![
+                Sample::block(CHANNEL_ALPHA, 0, 64),
+                Sample::block(CHANNEL_ETC2_COLOR, 64, 64),
+            ],
+        ),
+        f if f.is_pvrtc() => (MODEL_PVRTC, vec![Sample::block(0, 0, 64)]),
+        f if f.is_astc() && f.is_hdr() => (MODEL_ASTC, vec![hdr_block(0, 128, true)]),
+        _ => (MODEL_ASTC, vec![Sample::block(0, 0, 128)]),
+    };
+
+    (model, samples)
+}
+
+fn data_format_descriptor(layout: &Layout) -> Vec<u8> {
+    let (model, mut samples) = descriptor(layout);
+    let (block_width, block_height, block_bytes) = layout.block_size();
+
+    // Alpha is never sRGB encoded
+    if layout.srgb {
+        for sample in samples.iter_mut().filter(|s| s.channel == CHANNEL_ALPHA) {
+            sample.qualifiers |= QUALIFIER_LINEAR;
+        }
+    }
+
+    let block_size = 24 + samples.len() * 16;
+    let mut dfd = Vec::with_capacity(4 + block_size);
+    dfd.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    // Khronos vendor, basic descriptor type
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    // Version 1.3
+    dfd.extend_from_slice(&(2 | (block_size as u32) << 16).to_le_bytes());
+    dfd.extend_from_slice(&[
+        model,
+        // BT.709 primaries
+        1,
+        // Transfer function
+        if layout.srgb { 2 } else { 1 },
+        // Straight alpha
+        0,
+    ]);
+    dfd.extend_from_slice(&[block_width as u8 - 1, block_height as u8 - 1, 0, 0]);
+    dfd.extend_from_slice(&[block_bytes as u8, 0, 0, 0, 0, 0, 0, 0]);
+
+    for sample in samples {
+        dfd.extend_from_slice(&sample.bit_offset.to_le_bytes());
+        dfd.extend_from_slice(&[sample.bit_length - 1, sample.channel | sample.qualifiers]);
+        dfd.extend_from_slice(&[0; 4]);
+        dfd.extend_from_slice(&sample.lower.to_le_bytes());
+        dfd.extend_from_slice(&sample.upper.to_le_bytes());
+    }
+
+    dfd
+}
+
+fn key_value_data() -> Vec<u8> {
+    let entry = b"KTXwriter\0gwynn-texture\0";
+    let mut kvd = Vec::new();
+    kvd.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    kvd.extend_from_slice(entry);
+    kvd.resize(kvd.len().next_multiple_of(4), 0);
+    kvd
+}
+
+pub fn write<W: Write>(layout: &Layout, mut writer: W) -> anyhow::Result<()> {
+    let vk_format =
+        vk_format(layout.format, layout.srgb, layout.punchthrough).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Pixel format {:?} can't be stored in KTX2 files",
+                layout.format
+            )
+        })?;
+
+    let level_count = layout.levels.len();
+    let dfd = data_format_descriptor(layout);
+    let kvd = key_value_data();
+
+    let dfd_offset = HEADER_SIZE + level_count * LEVEL_INDEX_ENTRY_SIZE;
+    let kvd_offset = dfd_offset + dfd.len();
+
+    // Levels are stored smallest first, each aligned to the block size and 4 bytes
+    let (_, _, block_bytes) = layout.block_size();
+    let alignment = lcm(block_bytes as usize, 4);
+    let mut level_offsets = vec![0; level_count];
+    let mut offset = kvd_offset + kvd.len();
+    for level in (0..level_count).rev() {
+        offset = offset.next_multiple_of(alignment);
+        level_offsets[level] = offset;
+        offset += layout.level_data(level).len();
+    }
+
+    writer.write_all(&IDENTIFIER)?;
+    for value in [
+        vk_format,
+        type_size(layout.format),
+        layout.width,
+        layout.height,
+        if layout.depth > 1 { layout.depth } else { 0 },
+        if layout.is_array { layout.layers } else { 0 },
+        layout.faces,
+        level_count as u32,
+        // No supercompression
+        0,
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    // No supercompression global data
+    writer.write_all(&[0; 16])?;
+
+    for (level, offset) in level_offsets.iter().enumerate() {
+        let size = layout.level_data(level).len() as u64;
+        writer.write_all(&(*offset as u64).to_le_bytes())?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(&size.to_le_bytes())?;
+    }
+
+    writer.write_all(&dfd)?;
+    writer.write_all(&kvd)?;
+
+    let mut position = kvd_offset + kvd.len();
+    for level in (0..level_count).rev() {
+        writer.write_all(&vec![0; level_offsets[level] - position])?;
+        let data = layout.level_data(level);
+        writer.write_all(data)?;
+        position = level_offsets[level] + data.len();
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+
+    a / gcd(a, b) * b
+}
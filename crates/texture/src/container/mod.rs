@@ -0,0 +1,54 @@
+//! Lossless DDS and KTX2 containers, carrying the original (block compressed) data of every mip level, array layer
+//! and cube face without recompressing it.
+
+use std::{io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::layout::Layout;
+
+pub mod dds;
+pub mod ktx2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerFormat {
+    Dds,
+    Ktx2,
+}
+
+impl ContainerFormat {
+    /// Guesses the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "dds" => Some(Self::Dds),
+            "ktx2" => Some(Self::Ktx2),
+            _ => None,
+        }
+    }
+}
+
+/// Rewraps a Messiah texture file in the given container
+pub fn write_container<W: Write>(
+    data: &[u8],
+    format: ContainerFormat,
+    writer: W,
+) -> anyhow::Result<()> {
+    let layout = Layout::read(data)?;
+    match format {
+        ContainerFormat::Dds => dds::write(&layout, writer),
+        ContainerFormat::Ktx2 => ktx2::write(&layout, writer),
+    }
+}
+
+/// Rewraps a Messiah texture file into a file, the container is picked from its extension
+pub fn write_to_file(data: &[u8], path: &Path) -> anyhow::Result<()> {
+    let format = ContainerFormat::from_path(path).with_context(|| {
+        format!(
+            "Unknown container format for {}, expected .dds or .ktx2",
+            path.display()
+        )
+    })?;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_container(data, format, file)
+}
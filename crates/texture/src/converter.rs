@@ -6,13 +6,23 @@ use wgpu::{
     PipelineCompilationOptions, RequestAdapterOptions, ShaderModuleDescriptor,
 };
 
-use crate::Texture;
+use crate::{
+    decode::{DecodedImage, Pixels},
+    format::ColorSpace,
+    tonemap::Tonemap,
+    Texture,
+};
 
 pub struct TextureConverter {
     device: wgpu::Device,
     queue: wgpu::Queue,
 
     pipeline: wgpu::ComputePipeline,
+    /// Writes linear RGBA32F instead of RGBA8888
+    float_pipeline: wgpu::ComputePipeline,
+
+    /// Overrides the color space picked from the texture header
+    color_space: Option<ColorSpace>,
 }
 
 impl TextureConverter {
@@ -39,6 +49,11 @@ impl TextureConverter {
             warn!("GPU does not support ASTC textures. Decoding will be done on the CPU");
         };
 
+        // Optional, ASTC HDR textures can't be converted without it and 32 bit float textures are otherwise uploaded
+        // as half floats
+        features |= adapter.features()
+            & (Features::TEXTURE_COMPRESSION_ASTC_HDR | Features::FLOAT32_FILTERABLE);
+
         let (device, queue) = block_on(adapter.request_device(&DeviceDescriptor {
             label: Some("Texture Converter Device"),
             required_features: features,
@@ -50,41 +65,138 @@ impl TextureConverter {
             ..Default::default()
         }))?;
 
-        let shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Texture converter shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("./convert.wgsl").into()),
-        });
-
-        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("Texture converter pipeline"),
-            layout: None,
-            module: &shader,
-            entry_point: Some("convert_main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            cache: None,
-        });
+        let pipeline = create_pipeline(
+            &device,
+            include_str!("./convert.wgsl"),
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+        let float_pipeline = create_pipeline(
+            &device,
+            include_str!("./convert_float.wgsl"),
+            wgpu::TextureFormat::Rgba32Float,
+        );
 
         Ok(Self {
             device,
             queue,
             pipeline,
+            float_pipeline,
+            color_space: None,
         })
     }
 
+    /// Treat every texture as sRGB encoded or linear, instead of picking the color space from its header.
+    ///
+    /// Only affects float conversion, RGBA8888 output keeps the colors as they are stored.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = Some(color_space);
+        self
+    }
+
+    fn load(&self, data: &[u8]) -> anyhow::Result<Texture> {
+        Texture::load_with_color_space(&self.device, &self.queue, data, self.color_space)
+    }
+
     /// Converts the given texture data to RGBA8888
     pub fn convert(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
-        debug!("Converting texture");
+        self.convert_mip(data, 0)
+    }
+
+    /// Converts every mip level of the given texture data to RGBA8888, largest first
+    pub fn convert_mips(&self, data: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let texture = self.load(data)?;
+        (0..texture.mip_level_count())
+            .map(|level| self.convert_texture(&texture, level, 0, false))
+            .collect()
+    }
+
+    /// Converts every layer, cube face or depth slice of a mip level to RGBA8888
+    pub fn convert_layers(&self, data: &[u8], level: u32) -> anyhow::Result<Vec<Vec<u8>>> {
+        let texture = self.load(data)?;
+        anyhow::ensure!(
+            level < texture.mip_level_count(),
+            "Texture has no mip level {level}"
+        );
+
+        (0..texture.layer_count())
+            .map(|layer| self.convert_texture(&texture, level, layer, false))
+            .collect()
+    }
+
+    /// Converts a single mip level of the given texture data to RGBA8888, 0 being the full size one
+    pub fn convert_mip(&self, data: &[u8], level: u32) -> anyhow::Result<Vec<u8>> {
+        let texture = self.load(data)?;
+        anyhow::ensure!(
+            level < texture.mip_level_count(),
+            "Texture has no mip level {level}"
+        );
+
+        self.convert_texture(&texture, level, 0, false)
+    }
+
+    /// Converts the given texture data to linear RGBA32F, keeping values outside of the 0-1 range.
+    ///
+    /// sRGB textures are decoded to linear colors.
+    pub fn convert_float(&self, data: &[u8]) -> anyhow::Result<Vec<f32>> {
+        self.convert_float_mip(data, 0)
+    }
 
-        let texture = Texture::load(&self.device, &self.queue, data)?;
-        if texture.header.format.is_hdr() {
-            anyhow::bail!("HDR textures are not supported");
+    /// Converts a single mip level of the given texture data to linear RGBA32F, 0 being the full size one
+    pub fn convert_float_mip(&self, data: &[u8], level: u32) -> anyhow::Result<Vec<f32>> {
+        let texture = self.load(data)?;
+        anyhow::ensure!(
+            level < texture.mip_level_count(),
+            "Texture has no mip level {level}"
+        );
+
+        Ok(to_f32s(&self.convert_texture(&texture, level, 0, true)?))
+    }
+
+    /// Converts the given texture data to RGBA8888 for previewing, float textures are tonemapped and sRGB encoded
+    pub fn convert_preview(&self, data: &[u8], tonemap: &Tonemap) -> anyhow::Result<Vec<u8>> {
+        let texture = self.load(data)?;
+        if !texture.header.format.is_float() {
+            return self.convert_texture(&texture, 0, 0, false);
         }
 
+        let (width, height) = texture.mip_size(0);
+        let image = DecodedImage {
+            width,
+            height,
+            pixels: Pixels::Rgba32F(to_f32s(&self.convert_texture(&texture, 0, 0, true)?)),
+        };
+
+        Ok(crate::tonemap::tonemap(&image, tonemap).to_rgba8())
+    }
+
+    /// Converts a layer of a mip level to RGBA8888 or, if `float` is set, to RGBA32F
+    fn convert_texture(
+        &self,
+        texture: &Texture,
+        level: u32,
+        layer: u32,
+        float: bool,
+    ) -> anyhow::Result<Vec<u8>> {
+        debug!("Converting texture mip {level}, layer {layer}");
+
+        let (pipeline, output_format) = if float {
+            (&self.float_pipeline, wgpu::TextureFormat::Rgba32Float)
+        } else {
+            (&self.pipeline, wgpu::TextureFormat::Rgba8Unorm)
+        };
+        let pixel_size = output_format.block_copy_size(None).unwrap() as usize;
+
+        let (width, height) = texture.mip_size(level);
         let texture_size = Extent3d {
-            width: texture.header.width as _,
-            height: texture.header.height as _,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
+        let input_view = if float {
+            texture.layer_view(level, layer)
+        } else {
+            texture.encoded_layer_view(level, layer)
+        };
 
         // Create an output texture
         let output_texture = self.device.create_texture(&wgpu::TextureDescriptor {
@@ -93,18 +205,18 @@ impl TextureConverter {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format: output_format,
             usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[wgpu::TextureFormat::Rgba8Unorm],
+            view_formats: &[output_format],
         });
 
         let texture_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Texture bind group"),
-            layout: &self.pipeline.get_bind_group_layout(0),
+            layout: &pipeline.get_bind_group_layout(0),
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                    resource: wgpu::BindingResource::TextureView(&input_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -120,27 +232,24 @@ impl TextureConverter {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         {
-            let (dispatch_with, dispatch_height) = compute_work_group_count(
-                (texture.header.width as u32, texture.header.height as u32),
-                (16, 16),
-            );
+            let (dispatch_with, dispatch_height) =
+                compute_work_group_count((width, height), (16, 16));
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Copy pass"),
                 timestamp_writes: None,
             });
-            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_pipeline(pipeline);
             compute_pass.set_bind_group(0, &texture_bind_group, &[]);
             compute_pass.dispatch_workgroups(dispatch_with, dispatch_height, 1);
         }
 
         debug!("Downloading converted texture data");
 
-        let padded_bytes_per_row = padded_bytes_per_row(texture.header.width as u32);
-        let unpadded_bytes_per_row = texture.header.width as usize * 4;
+        let unpadded_bytes_per_row = width as usize * pixel_size;
+        let padded_bytes_per_row = padded_bytes_per_row(unpadded_bytes_per_row);
 
-        let output_buffer_size = padded_bytes_per_row as u64
-            * texture.header.height as u64
-            * std::mem::size_of::<u8>() as u64;
+        let output_buffer_size =
+            padded_bytes_per_row as u64 * height as u64 * std::mem::size_of::<u8>() as u64;
         let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
             size: output_buffer_size,
@@ -160,7 +269,7 @@ impl TextureConverter {
                 layout: wgpu::TexelCopyBufferLayout {
                     offset: 0,
                     bytes_per_row: Some(padded_bytes_per_row as u32),
-                    rows_per_image: Some(texture.header.height as u32),
+                    rows_per_image: Some(height),
                 },
             },
             texture_size,
@@ -197,8 +306,68 @@ fn compute_work_group_count(
 }
 
 /// Compute the next multiple of 256 for texture retrieval padding.
-fn padded_bytes_per_row(width: u32) -> usize {
-    let bytes_per_row = width as usize * 4;
+fn padded_bytes_per_row(bytes_per_row: usize) -> usize {
     let padding = (256 - bytes_per_row % 256) % 256;
     bytes_per_row + padding
 }
+
+/// The downloaded bytes of a float texture aren't necessarily aligned to 4 bytes
+fn to_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+/// Compute pipeline reading any float texture and writing it to a storage texture of `output_format`
+fn create_pipeline(
+    device: &wgpu::Device,
+    source: &str,
+    output_format: wgpu::TextureFormat,
+) -> wgpu::ComputePipeline {
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("Texture converter shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    // Unfilterable, so 32 bit float textures can be read as well
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Texture converter bind group layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: output_format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+        ],
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Texture converter pipeline layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("Texture converter pipeline"),
+        layout: Some(&layout),
+        module: &shader,
+        entry_point: Some("convert_main"),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
@@ -0,0 +1,189 @@
+//! Unfolding the six faces of a cubemap into a single image.
+//!
+//! Faces are expected in the usual D3D/Vulkan order: +X, -X, +Y, -Y, +Z, -Z.
+
+use std::f32::consts::PI;
+
+use anyhow::ensure;
+
+use crate::decode::{DecodedImage, Pixels};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unfold {
+    /// 4x3 faces, with -X, +Z, +X and -Z in the middle row
+    HorizontalCross,
+    /// 3x4 faces, with -Z upside down in the bottom row
+    VerticalCross,
+    /// Latitude/longitude panorama, twice as wide as it is high
+    Equirectangular,
+}
+
+/// A face as linear RGBA32F, or 8 bit values divided by 255
+struct Face {
+    size: u32,
+    pixels: Vec<f32>,
+}
+
+impl Face {
+    fn pixel(&self, x: u32, y: u32) -> &[f32] {
+        let i = (y.min(self.size - 1) * self.size + x.min(self.size - 1)) as usize * 4;
+        &self.pixels[i..i + 4]
+    }
+
+    /// Bilinear sample at face coordinates in the 0..1 range, clamped to the face edges
+    fn sample(&self, s: f32, t: f32) -> [f32; 4] {
+        let x = (s * self.size as f32 - 0.5).max(0.0);
+        let y = (t * self.size as f32 - 0.5).max(0.0);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let mut out = [0.0; 4];
+        for (c, out) in out.iter_mut().enumerate() {
+            let top = self.pixel(x0, y0)[c] * (1.0 - fx) + self.pixel(x0 + 1, y0)[c] * fx;
+            let bottom =
+                self.pixel(x0, y0 + 1)[c] * (1.0 - fx) + self.pixel(x0 + 1, y0 + 1)[c] * fx;
+            *out = top * (1.0 - fy) + bottom * fy;
+        }
+        out
+    }
+}
+
+/// Unfolds the first six faces of a cubemap into a single image.
+///
+/// The result holds float pixels if the faces do, otherwise RGBA8. Parts of crosses without a face are transparent.
+pub fn unfold(faces: &[DecodedImage], unfold: Unfold) -> anyhow::Result<DecodedImage> {
+    ensure!(
+        faces.len() >= 6,
+        "A cubemap needs 6 faces, got {}",
+        faces.len()
+    );
+    let faces = &faces[..6];
+    let size = faces[0].width;
+    ensure!(
+        size > 0 && faces.iter().all(|f| f.width == size && f.height == size),
+        "Cubemap faces must be square and of the same size"
+    );
+
+    let is_hdr = faces.iter().any(|f| f.is_hdr());
+    let faces: Vec<Face> = faces
+        .iter()
+        .map(|f| Face {
+            size,
+            pixels: f.to_rgba32f(),
+        })
+        .collect();
+
+    let (width, height, pixels) = match unfold {
+        Unfold::HorizontalCross => {
+            // (face, column, row, rotated by 180 degrees)
+            let cells = [
+                (2, 1, 0, false),
+                (1, 0, 1, false),
+                (4, 1, 1, false),
+                (0, 2, 1, false),
+                (5, 3, 1, false),
+                (3, 1, 2, false),
+            ];
+            cross(&faces, 4, 3, &cells)
+        }
+        Unfold::VerticalCross => {
+            let cells = [
+                (2, 1, 0, false),
+                (1, 0, 1, false),
+                (4, 1, 1, false),
+                (0, 2, 1, false),
+                (3, 1, 2, false),
+                (5, 1, 3, true),
+            ];
+            cross(&faces, 3, 4, &cells)
+        }
+        Unfold::Equirectangular => equirectangular(&faces),
+    };
+
+    let pixels = if is_hdr {
+        Pixels::Rgba32F(pixels)
+    } else {
+        Pixels::Rgba8(
+            pixels
+                .iter()
+                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+        )
+    };
+
+    Ok(DecodedImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn cross(
+    faces: &[Face],
+    columns: u32,
+    rows: u32,
+    cells: &[(usize, u32, u32, bool)],
+) -> (u32, u32, Vec<f32>) {
+    let size = faces[0].size;
+    let (width, height) = (columns * size, rows * size);
+    let mut pixels = vec![0.0; (width * height) as usize * 4];
+
+    for &(face, column, row, rotated) in cells {
+        for y in 0..size {
+            for x in 0..size {
+                let (sx, sy) = if rotated {
+                    (size - 1 - x, size - 1 - y)
+                } else {
+                    (x, y)
+                };
+                let i = ((row * size + y) * width + column * size + x) as usize * 4;
+                pixels[i..i + 4].copy_from_slice(faces[face].pixel(sx, sy));
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+fn equirectangular(faces: &[Face]) -> (u32, u32, Vec<f32>) {
+    let width = faces[0].size * 4;
+    let height = width / 2;
+    let mut pixels = Vec::with_capacity((width * height) as usize * 4);
+
+    for y in 0..height {
+        let lat = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+        for x in 0..width {
+            let lon = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+            let dir = [lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos()];
+
+            let (face, s, t) = face_coords(dir);
+            pixels.extend_from_slice(&faces[face].sample(s, t));
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/// The face a direction points at and the coordinates on it, following the OpenGL cube map table
+fn face_coords([x, y, z]: [f32; 3]) -> (usize, f32, f32) {
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if x > 0.0 {
+            (0, -z, -y, ax)
+        } else {
+            (1, z, -y, ax)
+        }
+    } else if ay >= az {
+        if y > 0.0 {
+            (2, x, z, ay)
+        } else {
+            (3, x, -z, ay)
+        }
+    } else if z > 0.0 {
+        (4, x, -y, az)
+    } else {
+        (5, -x, -y, az)
+    };
+
+    (face, (sc / ma + 1.0) / 2.0, (tc / ma + 1.0) / 2.0)
+}
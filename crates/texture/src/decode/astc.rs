@@ -0,0 +1,1173 @@
+//! ASTC decoder for the LDR and HDR profiles.
+//!
+//! This follows the decoding process of the Khronos data format specification: HDR endpoints are stored in a
+//! logarithmic (LNS) encoding that is interpolated before converting it to half floats, LDR endpoints are interpolated
+//! as UNORM16. The LDR profile decodes to RGBA8 and treats HDR blocks as errors, the HDR profile decodes to linear
+//! floats.
+
+use half::f16;
+
+/// Alpha of HDR endpoint modes without alpha, 1.0 in the LNS encoding
+const LNS_ONE: i32 = 0x7800;
+
+/// Texel type of a decoding profile
+trait Texel: Copy + Default {
+    /// Shown for reserved or otherwise invalid blocks
+    const ERROR: Self;
+    /// Whether HDR endpoints and void extent blocks are allowed, they decode to the error color otherwise
+    const HDR: bool;
+
+    /// Converts interpolated channels, which are half floats for HDR channels and UNORM16 otherwise
+    fn from_channels(channels: [(u16, bool); 4]) -> Self;
+}
+
+impl Texel for [u8; 4] {
+    const ERROR: Self = [255, 0, 255, 255];
+    const HDR: bool = false;
+
+    fn from_channels(channels: [(u16, bool); 4]) -> Self {
+        channels.map(|(value, _)| (value >> 8) as u8)
+    }
+}
+
+impl Texel for [f32; 4] {
+    const ERROR: Self = [1.0, 0.0, 1.0, 1.0];
+    const HDR: bool = true;
+
+    fn from_channels(channels: [(u16, bool); 4]) -> Self {
+        channels.map(|(value, hdr)| {
+            if hdr {
+                f16::from_bits(value).to_f32()
+            } else {
+                value as f32 / 65535.0
+            }
+        })
+    }
+}
+
+/// Number of trits or quints and bits of every integer sequence encoding range, in order of increasing levels
+#[derive(Clone, Copy)]
+enum Encoding {
+    Bits(u32),
+    Trit(u32),
+    Quint(u32),
+}
+
+const RANGES: [Encoding; 21] = [
+    Encoding::Bits(1),
+    Encoding::Trit(0),
+    Encoding::Bits(2),
+    Encoding::Quint(0),
+    Encoding::Trit(1),
+    Encoding::Bits(3),
+    Encoding::Quint(1),
+    Encoding::Trit(2),
+    Encoding::Bits(4),
+    Encoding::Quint(2),
+    Encoding::Trit(3),
+    Encoding::Bits(5),
+    Encoding::Quint(3),
+    Encoding::Trit(4),
+    Encoding::Bits(6),
+    Encoding::Quint(4),
+    Encoding::Trit(5),
+    Encoding::Bits(7),
+    Encoding::Quint(5),
+    Encoding::Trit(6),
+    Encoding::Bits(8),
+];
+/// Index of the smallest range color endpoints can use
+const MIN_COLOR_RANGE: usize = 4;
+
+impl Encoding {
+    /// Size of a sequence of `count` values
+    fn sequence_bits(&self, count: u32) -> u32 {
+        match self {
+            Self::Bits(n) => n * count,
+            Self::Trit(n) => n * count + (8 * count).div_ceil(5),
+            Self::Quint(n) => n * count + (7 * count).div_ceil(3),
+        }
+    }
+}
+
+/// Reads a range of bits of a block least significant bit first, bits past the end of the range read as zero
+struct BitStream {
+    bits: u128,
+    pos: u32,
+    end: u32,
+}
+
+impl BitStream {
+    fn new(bits: u128, start: u32, len: u32) -> Self {
+        Self {
+            bits,
+            pos: start,
+            end: (start + len).min(128),
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..count {
+            if self.pos < self.end {
+                value |= ((self.bits >> self.pos) & 1) as u32 * (1 << i);
+            }
+            self.pos += 1;
+        }
+
+        value
+    }
+}
+
+fn bits(block: u128, start: u32, count: u32) -> u32 {
+    ((block >> start) & ((1 << count) - 1)) as u32
+}
+
+/// Decodes an integer sequence into its trit or quint and bits, as `(tq, bits)` pairs
+fn decode_ise(stream: &mut BitStream, encoding: Encoding, count: usize) -> Vec<(u32, u32)> {
+    let mut values = Vec::with_capacity(count);
+    while values.len() < count {
+        match encoding {
+            Encoding::Bits(n) => values.push((0, stream.read(n))),
+            Encoding::Trit(n) => {
+                let mut m = [0; 5];
+                let mut t = 0;
+                for (i, shift, len) in [(0, 0, 2), (1, 2, 2), (2, 4, 1), (3, 5, 2), (4, 7, 1)] {
+                    m[i] = stream.read(n);
+                    t |= stream.read(len) << shift;
+                }
+                let trits = decode_trits(t);
+                values.extend((0..5).map(|i| (trits[i], m[i])));
+            }
+            Encoding::Quint(n) => {
+                let mut m = [0; 3];
+                let mut q = 0;
+                for (i, shift, len) in [(0, 0, 3), (1, 3, 2), (2, 5, 2)] {
+                    m[i] = stream.read(n);
+                    q |= stream.read(len) << shift;
+                }
+                let quints = decode_quints(q);
+                values.extend((0..3).map(|i| (quints[i], m[i])));
+            }
+        }
+    }
+
+    values.truncate(count);
+    values
+}
+
+fn bit(value: u32, i: u32) -> u32 {
+    (value >> i) & 1
+}
+
+/// Unpacks 5 trits from 8 bits
+fn decode_trits(t: u32) -> [u32; 5] {
+    let (c, t4, t3) = if (t >> 2) & 7 == 7 {
+        (((t >> 5) & 7) << 2 | (t & 3), 2, 2)
+    } else if (t >> 5) & 3 == 3 {
+        (t & 0x1F, 2, bit(t, 7))
+    } else {
+        (t & 0x1F, bit(t, 7), (t >> 5) & 3)
+    };
+
+    let (t2, t1, t0) = if c & 3 == 3 {
+        (
+            2,
+            bit(c, 4),
+            (bit(c, 3) << 1) | (bit(c, 2) & !bit(c, 3) & 1),
+        )
+    } else if (c >> 2) & 3 == 3 {
+        (2, 2, c & 3)
+    } else {
+        (
+            bit(c, 4),
+            (c >> 2) & 3,
+            (bit(c, 1) << 1) | (bit(c, 0) & !bit(c, 1) & 1),
+        )
+    };
+
+    [t0, t1, t2, t3, t4]
+}
+
+/// Unpacks 3 quints from 7 bits
+fn decode_quints(q: u32) -> [u32; 3] {
+    if (q >> 1) & 3 == 3 && (q >> 5) & 3 == 0 {
+        let q2 =
+            (bit(q, 0) << 2) | ((bit(q, 4) & !bit(q, 0) & 1) << 1) | (bit(q, 3) & !bit(q, 0) & 1);
+        return [4, 4, q2];
+    }
+
+    let (q2, c) = if (q >> 1) & 3 == 3 {
+        (4, ((q >> 3) & 3) << 3 | ((!(q >> 5) & 3) << 1) | (q & 1))
+    } else {
+        ((q >> 5) & 3, q & 0x1F)
+    };
+    let (q1, q0) = if c & 7 == 5 {
+        (4, (c >> 3) & 3)
+    } else {
+        ((c >> 3) & 3, c & 7)
+    };
+
+    [q0, q1, q2]
+}
+
+/// Replicates the low `from` bits of a value to fill `to` bits
+fn replicate(value: u32, from: u32, to: u32) -> u32 {
+    let mut result = 0;
+    let mut filled = 0;
+    while filled < to {
+        result = (result << from) | value;
+        filled += from;
+    }
+
+    result >> (filled - to)
+}
+
+/// Unquantizes a color endpoint value to 0-255
+fn unquantize_color((tq, m): (u32, u32), encoding: Encoding) -> u32 {
+    let a = if m & 1 != 0 { 0x1FF } else { 0 };
+    let b = |i: u32| bit(m, i);
+    let (c, bb) = match encoding {
+        Encoding::Bits(n) => return replicate(m, n, 8),
+        Encoding::Trit(1) => (204, 0),
+        Encoding::Trit(2) => (93, b(1) * 0x116),
+        Encoding::Trit(3) => (44, b(2) * 0x10A + b(1) * 0x85),
+        Encoding::Trit(4) => (22, b(3) * 0x104 + b(2) * 0x82 + b(1) * 0x41),
+        Encoding::Trit(5) => (11, b(4) * 0x102 + b(3) * 0x81 + b(2) * 0x40 + b(1) * 0x20),
+        Encoding::Trit(6) => (
+            5,
+            b(5) * 0x101 + b(4) * 0x80 + b(3) * 0x40 + b(2) * 0x20 + b(1) * 0x10,
+        ),
+        Encoding::Quint(1) => (113, 0),
+        Encoding::Quint(2) => (54, b(1) * 0x10C),
+        Encoding::Quint(3) => (26, b(2) * 0x105 + b(1) * 0x82),
+        Encoding::Quint(4) => (13, b(3) * 0x102 + b(2) * 0x81 + b(1) * 0x40),
+        Encoding::Quint(5) => (6, b(4) * 0x101 + b(3) * 0x80 + b(2) * 0x40 + b(1) * 0x20),
+        // Ranges below 6 levels aren't allowed for colors
+        _ => return 0,
+    };
+
+    let t = (tq * c + bb) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+/// Unquantizes a weight to 0-64
+fn unquantize_weight((tq, m): (u32, u32), encoding: Encoding) -> u32 {
+    let a = if m & 1 != 0 { 0x7F } else { 0 };
+    let b = |i: u32| bit(m, i);
+    let value = match encoding {
+        Encoding::Bits(n) => replicate(m, n, 6),
+        Encoding::Trit(0) => [0, 32, 63][tq as usize],
+        Encoding::Quint(0) => [0, 16, 32, 47, 63][tq as usize],
+        encoding => {
+            let (c, bb) = match encoding {
+                Encoding::Trit(1) => (50, 0),
+                Encoding::Trit(2) => (23, b(1) * 0x45),
+                Encoding::Trit(3) => (11, b(2) * 0x42 + b(1) * 0x21),
+                Encoding::Quint(1) => (28, 0),
+                Encoding::Quint(2) => (13, b(1) * 0x42),
+                _ => unreachable!("weights use at most 32 levels"),
+            };
+            let t = (tq * c + bb) ^ a;
+            (a & 0x20) | (t >> 2)
+        }
+    };
+
+    if value > 32 {
+        value + 1
+    } else {
+        value
+    }
+}
+
+struct BlockMode {
+    weights_x: u32,
+    weights_y: u32,
+    dual_plane: bool,
+    /// Index into [`RANGES`]
+    weight_range: usize,
+}
+
+impl BlockMode {
+    fn decode(mode: u32) -> Option<Self> {
+        let a = (mode >> 5) & 3;
+        let mut dual_plane = bit(mode, 10) != 0;
+        let mut high_precision = bit(mode, 9);
+
+        let (weights_x, weights_y, range) = if mode & 3 != 0 {
+            let range = bit(mode, 4) | (mode & 3) << 1;
+            let b = (mode >> 7) & 3;
+            let (x, y) = match (mode >> 2) & 3 {
+                0 => (b + 4, a + 2),
+                1 => (b + 8, a + 2),
+                2 => (a + 2, b + 8),
+                _ if mode & 0x100 != 0 => ((b & 1) + 2, a + 2),
+                _ => (a + 2, (b & 1) + 6),
+            };
+            (x, y, range)
+        } else {
+            let range = bit(mode, 4) | ((mode >> 2) & 3) << 1;
+            if (mode >> 2) & 3 == 0 {
+                return None;
+            }
+
+            let b = (mode >> 9) & 3;
+            let (x, y) = match (mode >> 7) & 3 {
+                0 => (12, a + 2),
+                1 => (a + 2, 12),
+                2 => {
+                    dual_plane = false;
+                    high_precision = 0;
+                    (a + 6, b + 6)
+                }
+                _ => match a {
+                    0 => (6, 10),
+                    1 => (10, 6),
+                    _ => return None,
+                },
+            };
+            (x, y, range)
+        };
+
+        Some(Self {
+            weights_x,
+            weights_y,
+            dual_plane,
+            // The 12 weight ranges are the levels from 2 to 32, the first entries of `RANGES`
+            weight_range: (range - 2 + 6 * high_precision) as usize,
+        })
+    }
+
+    fn weight_count(&self) -> u32 {
+        self.weights_x * self.weights_y * (self.dual_plane as u32 + 1)
+    }
+}
+
+/// Converts a value in the 16 bit logarithmic encoding of HDR endpoints to the bits of a half float
+fn lns_to_f16(value: i32) -> u16 {
+    let mantissa = value & 0x7FF;
+    let exponent = value >> 11;
+    let mantissa = if mantissa < 512 {
+        mantissa * 3
+    } else if mantissa < 1536 {
+        mantissa * 4 - 512
+    } else {
+        mantissa * 5 - 2048
+    };
+
+    ((exponent << 10) | (mantissa >> 3)).min(0x7BFF) as u16
+}
+
+/// Endpoints of a partition, as 16 bit values that are either UNORM16 or LNS encoded
+struct Endpoints {
+    colors: [[i32; 4]; 2],
+    hdr_rgb: bool,
+    hdr_alpha: bool,
+}
+
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let mut a = (a >> 1) & 0x3F;
+    if a & 0x20 != 0 {
+        a -= 0x40;
+    }
+    (a, b)
+}
+
+fn blue_contract([r, g, b, a]: [i32; 4]) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+fn clamp_ldr(color: [i32; 4]) -> [i32; 4] {
+    color.map(|c| c.clamp(0, 255))
+}
+
+impl Endpoints {
+    fn ldr(e0: [i32; 4], e1: [i32; 4]) -> Self {
+        Self {
+            colors: [e0, e1].map(|e| e.map(|c| c * 257)),
+            hdr_rgb: false,
+            hdr_alpha: false,
+        }
+    }
+
+    /// Decodes the endpoints of a color endpoint mode from its unquantized values
+    fn decode(mode: u32, v: &[i32]) -> Self {
+        match mode {
+            0 => Self::ldr([v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]),
+            1 => {
+                let l0 = (v[0] >> 2) | (v[1] & 0xC0);
+                let l1 = (l0 + (v[1] & 0x3F)).min(255);
+                Self::ldr([l0, l0, l0, 255], [l1, l1, l1, 255])
+            }
+            4 => Self::ldr([v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]),
+            5 => {
+                let (o0, l) = bit_transfer_signed(v[1], v[0]);
+                let (o1, a) = bit_transfer_signed(v[3], v[2]);
+                Self::ldr([l, l, l, a], clamp_ldr([l + o0, l + o0, l + o0, a + o1]))
+            }
+            6 => Self::ldr(
+                [
+                    (v[0] * v[3]) >> 8,
+                    (v[1] * v[3]) >> 8,
+                    (v[2] * v[3]) >> 8,
+                    255,
+                ],
+                [v[0], v[1], v[2], 255],
+            ),
+            8 | 12 => {
+                let (a0, a1) = if mode == 12 { (v[6], v[7]) } else { (255, 255) };
+                let e0 = [v[0], v[2], v[4], a0];
+                let e1 = [v[1], v[3], v[5], a1];
+                if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                    Self::ldr(e0, e1)
+                } else {
+                    Self::ldr(blue_contract(e1), blue_contract(e0))
+                }
+            }
+            9 | 13 => {
+                let (r1, r0) = bit_transfer_signed(v[1], v[0]);
+                let (g1, g0) = bit_transfer_signed(v[3], v[2]);
+                let (b1, b0) = bit_transfer_signed(v[5], v[4]);
+                let (a1, a0) = if mode == 13 {
+                    bit_transfer_signed(v[7], v[6])
+                } else {
+                    (0, 255)
+                };
+                let base = [r0, g0, b0, a0];
+                let offset = [r0 + r1, g0 + g1, b0 + b1, a0 + a1];
+                if r1 + g1 + b1 >= 0 {
+                    Self::ldr(base, clamp_ldr(offset))
+                } else {
+                    Self::ldr(
+                        clamp_ldr(blue_contract(offset)),
+                        clamp_ldr(blue_contract(base)),
+                    )
+                }
+            }
+            10 => Self::ldr(
+                [
+                    (v[0] * v[3]) >> 8,
+                    (v[1] * v[3]) >> 8,
+                    (v[2] * v[3]) >> 8,
+                    v[4],
+                ],
+                [v[0], v[1], v[2], v[5]],
+            ),
+            2 => {
+                let (y0, y1) = if v[1] >= v[0] {
+                    (v[0] << 4, v[1] << 4)
+                } else {
+                    ((v[1] << 4) + 8, (v[0] << 4) - 8)
+                };
+                Self::hdr_luminance(y0, y1)
+            }
+            3 => {
+                let (y0, d) = if v[0] & 0x80 != 0 {
+                    (
+                        ((v[1] & 0xE0) << 4) | ((v[0] & 0x7F) << 2),
+                        (v[1] & 0x1F) << 2,
+                    )
+                } else {
+                    (
+                        ((v[1] & 0xF0) << 4) | ((v[0] & 0x7F) << 1),
+                        (v[1] & 0xF) << 1,
+                    )
+                };
+                Self::hdr_luminance(y0, (y0 + d).min(0xFFF))
+            }
+            7 => Self::hdr(hdr_rgb_scale(v), [LNS_ONE; 2]),
+            11 => Self::hdr(hdr_rgb(v), [LNS_ONE; 2]),
+            14 => Self {
+                hdr_alpha: false,
+                ..Self::hdr(hdr_rgb(v), [v[6] * 257, v[7] * 257])
+            },
+            15 => Self::hdr(hdr_rgb(v), hdr_alpha(v[6], v[7])),
+            _ => unreachable!("color endpoint modes are 4 bits"),
+        }
+    }
+
+    /// From 12 bit luminance values
+    fn hdr_luminance(y0: i32, y1: i32) -> Self {
+        Self::hdr([[y0 << 4; 3], [y1 << 4; 3]], [LNS_ONE; 2])
+    }
+
+    fn hdr(rgb: [[i32; 3]; 2], alpha: [i32; 2]) -> Self {
+        Self {
+            colors: [0, 1].map(|i| [rgb[i][0], rgb[i][1], rgb[i][2], alpha[i]]),
+            hdr_rgb: true,
+            hdr_alpha: true,
+        }
+    }
+}
+
+/// HDR RGB base + scale (mode 7), returns 16 bit LNS colors
+fn hdr_rgb_scale(v: &[i32]) -> [[i32; 3]; 2] {
+    let mode_value = ((v[0] & 0xC0) >> 6) | ((v[1] & 0x80) >> 5) | ((v[2] & 0x80) >> 4);
+    let (major, mode) = if mode_value & 0xC != 0xC {
+        (mode_value >> 2, mode_value & 3)
+    } else if mode_value != 0xF {
+        (mode_value & 3, 4)
+    } else {
+        (0, 5)
+    };
+
+    // Red, green, blue and scale
+    let mut values = [v[0] & 0x3F, v[1] & 0x1F, v[2] & 0x1F, v[3] & 0x1F];
+    let bits = [
+        (v[1] >> 6) & 1,
+        (v[1] >> 5) & 1,
+        (v[2] >> 6) & 1,
+        (v[2] >> 5) & 1,
+        (v[3] >> 7) & 1,
+        (v[3] >> 6) & 1,
+        (v[3] >> 5) & 1,
+    ];
+
+    // Which of the variable bits are present depends on the mode
+    let one_hot = 1 << mode;
+    for (mask, value, bit, shift) in [
+        (0x30, 1, 0, 6),
+        (0x3A, 1, 1, 5),
+        (0x30, 2, 2, 6),
+        (0x3A, 2, 3, 5),
+        (0x3D, 3, 6, 5),
+        (0x2D, 3, 5, 6),
+        (0x04, 3, 4, 7),
+        (0x3B, 0, 4, 6),
+        (0x04, 0, 3, 6),
+        (0x10, 0, 5, 7),
+        (0x0F, 0, 2, 7),
+        (0x05, 0, 1, 8),
+        (0x0A, 0, 0, 8),
+        (0x05, 0, 0, 9),
+        (0x02, 0, 6, 9),
+        (0x01, 0, 3, 10),
+        (0x02, 0, 5, 10),
+    ] {
+        if one_hot & mask != 0 {
+            values[value] |= bits[bit] << shift;
+        }
+    }
+
+    let shift = [1, 1, 2, 3, 4, 5][mode as usize];
+    let [mut red, mut green, mut blue, scale] = values.map(|x| x << shift);
+    // Green and blue are stored as differences from red, except in the last mode
+    if mode != 5 {
+        green = red - green;
+        blue = red - blue;
+    }
+    match major {
+        1 => std::mem::swap(&mut red, &mut green),
+        2 => std::mem::swap(&mut red, &mut blue),
+        _ => {}
+    }
+
+    let e1 = [red, green, blue].map(|c| c.max(0));
+    let e0 = [red - scale, green - scale, blue - scale].map(|c| c.max(0));
+    [e0.map(|c| c << 4), e1.map(|c| c << 4)]
+}
+
+/// HDR RGB direct (modes 11, 14 and 15), returns 16 bit LNS colors
+fn hdr_rgb(v: &[i32]) -> [[i32; 3]; 2] {
+    let mode = ((v[1] & 0x80) >> 7) | ((v[2] & 0x80) >> 6) | ((v[3] & 0x80) >> 5);
+    let major = ((v[4] & 0x80) >> 7) | ((v[5] & 0x80) >> 6);
+
+    if major == 3 {
+        return [
+            [v[0] << 8, v[2] << 8, (v[4] & 0x7F) << 9],
+            [v[1] << 8, v[3] << 8, (v[5] & 0x7F) << 9],
+        ];
+    }
+
+    // a, b0, b1, c, d0 and d1
+    let mut values = [
+        v[0] | ((v[1] & 0x40) << 2),
+        v[2] & 0x3F,
+        v[3] & 0x3F,
+        v[1] & 0x3F,
+        v[4] & 0x7F,
+        v[5] & 0x7F,
+    ];
+    let d_bits = [7, 6, 7, 6, 5, 6, 5, 6][mode as usize];
+
+    let bits = [
+        (v[2] >> 6) & 1,
+        (v[3] >> 6) & 1,
+        (v[4] >> 6) & 1,
+        (v[5] >> 6) & 1,
+        (v[4] >> 5) & 1,
+        (v[5] >> 5) & 1,
+    ];
+    let one_hot = 1 << mode;
+    for (mask, value, bit, shift) in [
+        (0xA4, 0, 0, 9),
+        (0x08, 0, 2, 9),
+        (0x50, 0, 4, 9),
+        (0x50, 0, 5, 10),
+        (0xA0, 0, 1, 10),
+        (0xC0, 0, 2, 11),
+        (0x04, 3, 1, 6),
+        (0xE8, 3, 3, 6),
+        (0x20, 3, 2, 7),
+        (0x5B, 1, 0, 6),
+        (0x5B, 2, 1, 6),
+        (0x12, 1, 2, 7),
+        (0x12, 2, 3, 7),
+        (0xAF, 4, 4, 5),
+        (0xAF, 5, 5, 5),
+        (0x05, 4, 2, 6),
+        (0x05, 5, 3, 6),
+    ] {
+        if one_hot & mask != 0 {
+            values[value] |= bits[bit] << shift;
+        }
+    }
+
+    // Sign extend the differences and expand everything to 12 bits
+    let sign_extend = |d: i32| (d << (32 - d_bits)) >> (32 - d_bits);
+    let shift = (mode >> 1) ^ 3;
+    let [a, b0, b1, c, d0, d1] = values;
+    let [a, b0, b1, c, d0, d1] =
+        [a, b0, b1, c, sign_extend(d0), sign_extend(d1)].map(|x| x << shift);
+
+    let mut e0 = [a - c, a - b0 - c - d0, a - b1 - c - d1].map(|x| x.clamp(0, 0xFFF));
+    let mut e1 = [a, a - b0, a - b1].map(|x| x.clamp(0, 0xFFF));
+    match major {
+        1 => {
+            e0.swap(0, 1);
+            e1.swap(0, 1);
+        }
+        2 => {
+            e0.swap(0, 2);
+            e1.swap(0, 2);
+        }
+        _ => {}
+    }
+
+    [e0.map(|x| x << 4), e1.map(|x| x << 4)]
+}
+
+/// HDR alpha of mode 15, returns 16 bit LNS values
+fn hdr_alpha(v6: i32, v7: i32) -> [i32; 2] {
+    let selector = ((v6 >> 7) & 1) | ((v7 >> 6) & 2);
+    let (mut v6, mut v7) = (v6 & 0x7F, v7 & 0x7F);
+    if selector == 3 {
+        return [v6 << 9, v7 << 9];
+    }
+
+    v6 |= (v7 << (selector + 1)) & 0x780;
+    v7 &= 0x3F >> selector;
+    v7 ^= 32 >> selector;
+    v7 -= 32 >> selector;
+    v6 <<= 4 - selector;
+    v7 <<= 4 - selector;
+    v7 = (v7 + v6).clamp(0, 0xFFF);
+
+    [v6 << 4, v7 << 4]
+}
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+/// Partition of a texel, from the partition pattern hash of the specification
+fn select_partition(seed: u32, x: u32, y: u32, count: u32, small_block: bool) -> usize {
+    let (x, y) = if small_block {
+        (x << 1, y << 1)
+    } else {
+        (x, y)
+    };
+    let seed = seed + (count - 1) * 1024;
+    let rnum = hash52(seed);
+
+    let mut seeds = [
+        rnum,
+        rnum >> 4,
+        rnum >> 8,
+        rnum >> 12,
+        rnum >> 16,
+        rnum >> 20,
+        rnum >> 24,
+        rnum >> 28,
+        rnum >> 18,
+        rnum >> 22,
+        rnum >> 26,
+        rnum.rotate_left(2),
+    ]
+    .map(|s| (s & 0xF) * (s & 0xF));
+
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if count == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if count == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= match i {
+            0..8 if i % 2 == 0 => sh1,
+            0..8 => sh2,
+            _ => sh3,
+        };
+    }
+
+    // The z terms are zero for 2D blocks
+    let a = (seeds[0] * x + seeds[1] * y + (rnum >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + (rnum >> 10)) & 0x3F;
+    let c = if count >= 3 {
+        (seeds[4] * x + seeds[5] * y + (rnum >> 6)) & 0x3F
+    } else {
+        0
+    };
+    let d = if count >= 4 {
+        (seeds[6] * x + seeds[7] * y + (rnum >> 2)) & 0x3F
+    } else {
+        0
+    };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+/// Decodes a surface of `block_width`x`block_height` blocks with the LDR profile
+pub fn decode_astc_ldr(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+) -> anyhow::Result<Vec<[u8; 4]>> {
+    decode_astc(data, width, height, block_width, block_height)
+}
+
+/// Decodes a surface of `block_width`x`block_height` blocks with the HDR profile, into linear floats
+pub fn decode_astc_hdr(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+) -> anyhow::Result<Vec<[f32; 4]>> {
+    decode_astc(data, width, height, block_width, block_height)
+}
+
+/// Decodes a 16 byte block of `width`x`height` texels with the LDR profile
+pub fn decode_astc_ldr_block(block: &[u8], width: u32, height: u32, out: &mut [[u8; 4]]) {
+    decode_astc_block(block, width, height, out);
+}
+
+/// Decodes a 16 byte block of `width`x`height` texels with the HDR profile, into linear floats
+pub fn decode_astc_hdr_block(block: &[u8], width: u32, height: u32, out: &mut [[f32; 4]]) {
+    decode_astc_block(block, width, height, out);
+}
+
+/// Decodes a surface, cropping the blocks at the right and bottom edges
+fn decode_astc<T: Texel>(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+) -> anyhow::Result<Vec<T>> {
+    let blocks_x = width.div_ceil(block_width) as usize;
+    let block_count = blocks_x * height.div_ceil(block_height) as usize;
+    anyhow::ensure!(
+        data.len() >= block_count * 16,
+        "Insufficient data for {width}x{height} texture, expected {} bytes but got {}",
+        block_count * 16,
+        data.len()
+    );
+
+    let (width, height) = (width as usize, height as usize);
+    let (block_width, block_height) = (block_width as usize, block_height as usize);
+    let mut pixels = vec![T::default(); width * height];
+    let mut block_pixels = vec![T::default(); block_width * block_height];
+    for (i, block) in data.chunks_exact(16).take(block_count).enumerate() {
+        decode_astc_block(
+            block,
+            block_width as u32,
+            block_height as u32,
+            &mut block_pixels,
+        );
+
+        let (block_x, block_y) = (i % blocks_x * block_width, i / blocks_x * block_height);
+        let columns = block_width.min(width - block_x);
+        for y in 0..block_height.min(height - block_y) {
+            let row = (block_y + y) * width + block_x;
+            pixels[row..row + columns]
+                .copy_from_slice(&block_pixels[y * block_width..y * block_width + columns]);
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn decode_astc_block<T: Texel>(block: &[u8], width: u32, height: u32, out: &mut [T]) {
+    let block = u128::from_le_bytes(block[..16].try_into().unwrap());
+    match decode_block(block, width, height, out) {
+        Some(()) => {}
+        None => out.fill(T::ERROR),
+    }
+}
+
+fn decode_block<T: Texel>(block: u128, width: u32, height: u32, out: &mut [T]) -> Option<()> {
+    let texel_count = (width * height) as usize;
+
+    // Void extent blocks have a single color, stored as half floats for HDR and UNORM16 otherwise
+    if bits(block, 0, 9) == 0x1FC {
+        let hdr = bits(block, 9, 1) != 0;
+        if hdr && !T::HDR {
+            return None;
+        }
+        let color = std::array::from_fn(|i| (bits(block, 64 + i as u32 * 16, 16) as u16, hdr));
+        out[..texel_count].fill(T::from_channels(color));
+        return Some(());
+    }
+
+    let mode = BlockMode::decode(bits(block, 0, 11))?;
+    let weight_encoding = RANGES[mode.weight_range];
+    let weight_count = mode.weight_count();
+    let weight_bits = weight_encoding.sequence_bits(weight_count);
+    if mode.weights_x > width
+        || mode.weights_y > height
+        || weight_count > 64
+        || !(24..=96).contains(&weight_bits)
+    {
+        return None;
+    }
+
+    let partitions = bits(block, 11, 2) + 1;
+    if partitions == 4 && mode.dual_plane {
+        return None;
+    }
+
+    // Extra bits of the color endpoint modes and the plane 2 component are stored right below the weights
+    let mut below_weights = 128 - weight_bits;
+    let (modes, partition_seed, color_start) = if partitions == 1 {
+        (vec![bits(block, 13, 4)], 0, 17)
+    } else {
+        let selector = bits(block, 23, 6);
+        let modes = if selector & 3 == 0 {
+            vec![selector >> 2; partitions as usize]
+        } else {
+            let extra_bits = 3 * partitions - 4;
+            below_weights -= extra_bits;
+            let encoded = selector | bits(block, below_weights, extra_bits) << 6;
+            let base_class = (encoded & 3) - 1;
+            (0..partitions)
+                .map(|i| {
+                    let class = bit(encoded, 2 + i) + base_class;
+                    let low = (encoded >> (2 + partitions + 2 * i)) & 3;
+                    (class << 2) | low
+                })
+                .collect()
+        };
+        (modes, bits(block, 13, 10), 29)
+    };
+
+    let plane2_component = if mode.dual_plane {
+        below_weights -= 2;
+        Some(bits(block, below_weights, 2) as usize)
+    } else {
+        None
+    };
+
+    // The color endpoints use the largest range that fits in the remaining bits
+    let value_count: u32 = modes.iter().map(|m| ((m >> 2) + 1) * 2).sum();
+    if value_count > 18 {
+        return None;
+    }
+    let color_bits = below_weights.checked_sub(color_start)?;
+    let color_range = (0..RANGES.len())
+        .rev()
+        .find(|&r| RANGES[r].sequence_bits(value_count) <= color_bits)?;
+    if color_range < MIN_COLOR_RANGE {
+        return None;
+    }
+
+    let color_encoding = RANGES[color_range];
+    let mut stream = BitStream::new(block, color_start, color_bits);
+    let values: Vec<i32> = decode_ise(&mut stream, color_encoding, value_count as usize)
+        .into_iter()
+        .map(|v| unquantize_color(v, color_encoding) as i32)
+        .collect();
+    let mut offset = 0;
+    let endpoints: Vec<Endpoints> = modes
+        .iter()
+        .map(|&m| {
+            let count = (((m >> 2) + 1) * 2) as usize;
+            let endpoints = Endpoints::decode(m, &values[offset..offset + count]);
+            offset += count;
+            endpoints
+        })
+        .collect();
+    if !T::HDR && endpoints.iter().any(|e| e.hdr_rgb || e.hdr_alpha) {
+        return None;
+    }
+
+    // Weights are stored from the top of the block down
+    let mut stream = BitStream::new(block.reverse_bits(), 0, weight_bits);
+    let weights: Vec<u32> = decode_ise(&mut stream, weight_encoding, weight_count as usize)
+        .into_iter()
+        .map(|w| unquantize_weight(w, weight_encoding))
+        .collect();
+    let planes = mode.dual_plane as usize + 1;
+
+    let ds = (1024 + width / 2) / (width - 1);
+    let dt = (1024 + height / 2) / (height - 1);
+    for y in 0..height {
+        for x in 0..width {
+            // Bilinear infill of the weight grid
+            let gs = (ds * x * (mode.weights_x - 1) + 32) >> 6;
+            let gt = (dt * y * (mode.weights_y - 1) + 32) >> 6;
+            let (js, fs, jt, ft) = (gs >> 4, gs & 0xF, gt >> 4, gt & 0xF);
+            let w11 = (fs * ft + 8) >> 4;
+            let factors = [16 - fs - ft + w11, fs - w11, ft - w11, w11];
+            let v0 = (js + jt * mode.weights_x) as usize;
+            let grid = [
+                v0,
+                v0 + 1,
+                v0 + mode.weights_x as usize,
+                v0 + mode.weights_x as usize + 1,
+            ];
+            let weight = |plane: usize| {
+                let sum: u32 = grid
+                    .iter()
+                    .zip(factors)
+                    .map(|(&i, f)| weights.get(i * planes + plane).copied().unwrap_or(0) * f)
+                    .sum();
+                (sum + 8) >> 4
+            };
+            let (w1, w2) = (weight(0), plane2_component.map(|_| weight(1)));
+
+            let partition = if partitions > 1 {
+                select_partition(partition_seed, x, y, partitions, texel_count < 31)
+            } else {
+                0
+            };
+            let endpoints = &endpoints[partition];
+
+            out[(y * width + x) as usize] = T::from_channels(std::array::from_fn(|c| {
+                let w = match (plane2_component, w2) {
+                    (Some(component), Some(w2)) if component == c => w2,
+                    _ => w1,
+                } as i32;
+                let [e0, e1] = [endpoints.colors[0][c], endpoints.colors[1][c]];
+                let value = (e0 * (64 - w) + e1 * w + 32) >> 6;
+                let hdr = if c == 3 {
+                    endpoints.hdr_alpha
+                } else {
+                    endpoints.hdr_rgb
+                };
+                if hdr {
+                    (lns_to_f16(value), true)
+                } else {
+                    (value as u16, false)
+                }
+            }));
+        }
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LDR_ERROR: [u8; 4] = <[u8; 4]>::ERROR;
+    const HDR_ERROR: [f32; 4] = <[f32; 4]>::ERROR;
+
+    /// Block mode of a 4x4 grid of 2 bit weights (A = 2, B = 0)
+    const MODE_4X4_2BIT: u128 = 0x42;
+    /// The same with a second plane of weights
+    const MODE_4X4_2BIT_DUAL_PLANE: u128 = 0x442;
+
+    /// Stores 2 bit weights bit reversed from the top of the block
+    fn with_weights(mut block: u128, weights: &[u32]) -> [u8; 16] {
+        for (i, w) in weights.iter().enumerate() {
+            block |= ((w & 1) as u128) << (127 - 2 * i);
+            block |= ((w >> 1) as u128) << (126 - 2 * i);
+        }
+        block.to_le_bytes()
+    }
+
+    /// 8 bit color values starting at `start`
+    fn with_values(mut block: u128, start: usize, values: &[u8]) -> u128 {
+        for (i, v) in values.iter().enumerate() {
+            block |= (*v as u128) << (start + i * 8);
+        }
+        block
+    }
+
+    /// A single partition 4x4 block with a 4x4 grid of 2 bit weights and 8 bit color values
+    fn block(color_mode: u32, values: &[u8], weights: [u32; 16]) -> [u8; 16] {
+        let block = MODE_4X4_2BIT | (color_mode as u128) << 13;
+        with_weights(with_values(block, 17, values), &weights)
+    }
+
+    fn void_extent(hdr: bool, color: [u16; 4]) -> [u8; 16] {
+        // Block mode, HDR bit and reserved bits, followed by all ones for the unused extent coordinates
+        let mut block = 0xFFC & !((!hdr as u128) << 9) | ((1u128 << 52) - 1) << 12;
+        for (i, c) in color.iter().enumerate() {
+            block |= (*c as u128) << (64 + i * 16);
+        }
+        block.to_le_bytes()
+    }
+
+    fn decode(block: &[u8]) -> Vec<[f32; 4]> {
+        let mut out = vec![[0.0; 4]; 16];
+        decode_astc_hdr_block(block, 4, 4, &mut out);
+        out
+    }
+
+    fn decode_ldr(block: &[u8]) -> Vec<[u8; 4]> {
+        let mut out = vec![[0; 4]; 16];
+        decode_astc_ldr_block(block, 4, 4, &mut out);
+        out
+    }
+
+    #[test]
+    fn void_extent_blocks() {
+        let hdr = void_extent(true, [0x4000, 0x3800, 0x3C00, 0x3C00]);
+        assert_eq!(decode(&hdr), vec![[2.0, 0.5, 1.0, 1.0]; 16]);
+
+        let ldr = void_extent(false, [0xFFFF, 0, 0xFFFF, 0xFFFF]);
+        assert_eq!(decode(&ldr), vec![[1.0, 0.0, 1.0, 1.0]; 16]);
+    }
+
+    #[test]
+    fn ldr_profile() {
+        // UNORM16 endpoints 0 and 65535, the top 8 bits of the interpolated values are the output
+        let weights = std::array::from_fn(|i| i as u32 % 4);
+        let pixels = decode_ldr(&block(0, &[0, 255], weights));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let l = [0, 84, 171, 255][i % 4];
+            assert_eq!(*pixel, [l, l, l, 255], "texel {i}");
+        }
+
+        let ldr = void_extent(false, [0xFFFF, 0x8000, 0, 0xFFFF]);
+        assert_eq!(decode_ldr(&ldr), vec![[255, 128, 0, 255]; 16]);
+    }
+
+    #[test]
+    fn ldr_profile_rejects_hdr_blocks() {
+        let hdr = void_extent(true, [0x4000, 0x3800, 0x3C00, 0x3C00]);
+        assert_eq!(decode_ldr(&hdr), vec![LDR_ERROR; 16]);
+        assert_eq!(
+            decode_ldr(&block(2, &[0xB0, 0xC0], [0; 16])),
+            vec![LDR_ERROR; 16]
+        );
+    }
+
+    #[test]
+    fn hdr_luminance_interpolates_in_lns() {
+        // LNS endpoints 0xB000 and 0xC000 are 128.0 and 512.0, 2 bit weights unquantize to 0, 21, 43 and 64
+        let weights = std::array::from_fn(|i| i as u32 % 4);
+        let pixels = decode(&block(2, &[0xB0, 0xC0], weights));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let y = [128.0, 204.0, 328.0, 512.0][i % 4];
+            assert_eq!(*pixel, [y, y, y, 1.0], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn hdr_rgb_direct() {
+        // Major component 3 stores 8 bit red and green and 7 bit blue values directly
+        let weights = std::array::from_fn(|i| if i < 8 { 0 } else { 3 });
+        let pixels = decode(&block(11, &[0x78, 0x70, 0x80, 0x88, 0xBC, 0xC0], weights));
+        assert_eq!(pixels[..8], [[1.0, 2.0, 1.0, 1.0]; 8]);
+        assert_eq!(pixels[8..], [[0.5, 4.0, 2.0, 1.0]; 8]);
+    }
+
+    #[test]
+    fn hdr_dual_plane() {
+        // Red uses the second plane, which is the second weight of every texel
+        let block = with_values(MODE_4X4_2BIT_DUAL_PLANE | 2 << 13, 17, &[0xB0, 0xC0]);
+        let weights: Vec<u32> = (0..16)
+            .flat_map(|i| [i % 4, if i < 8 { 3 } else { 0 }])
+            .collect();
+        let pixels = decode(&with_weights(block, &weights));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let r = if i < 8 { 512.0 } else { 128.0 };
+            let y = [128.0, 204.0, 328.0, 512.0][i % 4];
+            assert_eq!(*pixel, [r, y, y, 1.0], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn ldr_dual_plane_alpha() {
+        // Luminance and alpha (mode 4), with alpha in the second plane
+        let block = with_values(MODE_4X4_2BIT_DUAL_PLANE | 4 << 13, 17, &[0, 255, 255, 0]);
+        let block = block | 3 << 62;
+        let weights: Vec<u32> = (0..16)
+            .flat_map(|i| [i % 4, if i < 8 { 3 } else { 0 }])
+            .collect();
+        let pixels = decode_ldr(&with_weights(block, &weights));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let l = [0, 84, 171, 255][i % 4];
+            let a = if i < 8 { 0 } else { 255 };
+            assert_eq!(*pixel, [l, l, l, a], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn hdr_partitions() {
+        // Two partitions with seed 3, both using HDR luminance (mode 2) with the same mode selector
+        let block = MODE_4X4_2BIT | 1 << 11 | 3 << 13 | 2 << 25;
+        let block = with_values(block, 29, &[0xB0, 0xB0, 0xC0, 0xC0]);
+        let pixels = decode(&with_weights(block, &[0; 16]));
+
+        // Partition of every texel for seed 3, following the hash of the specification
+        let partitions = [1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0];
+        for (i, pixel) in pixels.iter().enumerate() {
+            let y = [128.0, 512.0][partitions[i]];
+            assert_eq!(*pixel, [y, y, y, 1.0], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn reserved_blocks_decode_to_the_error_color() {
+        assert_eq!(decode(&[0; 16]), vec![HDR_ERROR; 16]);
+        assert_eq!(decode_ldr(&[0; 16]), vec![LDR_ERROR; 16]);
+    }
+
+    #[test]
+    fn crops_blocks_at_the_edges() {
+        let blocks: Vec<u8> = [0x3C00, 0x4000, 0x4200, 0x4400]
+            .into_iter()
+            .flat_map(|r| void_extent(true, [r, 0, 0, 0x3C00]))
+            .collect();
+        let pixels = decode_astc_hdr(&blocks, 6, 5, 4, 4).unwrap();
+        assert_eq!(pixels.len(), 30);
+        assert_eq!(pixels[3][0], 1.0);
+        assert_eq!(pixels[4][0], 2.0);
+        assert_eq!(pixels[4 * 6][0], 3.0);
+        assert_eq!(pixels[4 * 6 + 5][0], 4.0);
+
+        assert!(decode_astc_hdr(&blocks[..48], 6, 5, 4, 4).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! AMD/Qualcomm ATC block decoders
+
+use super::bc::decode_bc4_channel;
+
+fn expand_5(v: u16) -> i32 {
+    let v = (v & 0x1F) as i32;
+    (v << 3) | (v >> 2)
+}
+
+fn expand_6(v: u16) -> i32 {
+    let v = (v & 0x3F) as i32;
+    (v << 2) | (v >> 4)
+}
+
+/// Decodes the 8 byte color block, like BC1 but with an RGB555 first color.
+///
+/// The top bit of the first color switches from interpolating between the two colors to black, `c0 - c1 / 4`, `c0`
+/// and `c1`.
+fn decode_color(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let e0 = [expand_5(c0 >> 10), expand_5(c0 >> 5), expand_5(c0)];
+    let e1 = [expand_5(c1 >> 11), expand_6(c1 >> 5), expand_5(c1)];
+
+    let palette: [[i32; 3]; 4] = if c0 & 0x8000 == 0 {
+        [
+            e0,
+            std::array::from_fn(|i| (5 * e0[i] + 3 * e1[i]) / 8),
+            std::array::from_fn(|i| (3 * e0[i] + 5 * e1[i]) / 8),
+            e1,
+        ]
+    } else {
+        [
+            [0; 3],
+            std::array::from_fn(|i| (e0[i] - e1[i] / 4).max(0)),
+            e0,
+            e1,
+        ]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, pixel) in out.iter_mut().enumerate() {
+        let [r, g, b] = palette[(indices >> (i * 2)) as usize & 3];
+        *pixel = [r as u8, g as u8, b as u8, 255];
+    }
+}
+
+/// ATC RGBA with explicit 4 bit alpha, stored like BC2
+pub fn decode_atc_explicit_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], out);
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    for (i, pixel) in out.iter_mut().enumerate() {
+        pixel[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+    }
+}
+
+/// ATC RGBA with interpolated alpha, stored like BC3
+pub fn decode_atc_interpolated_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], out);
+    for (pixel, alpha) in out.iter_mut().zip(decode_bc4_channel(&block[..8])) {
+        pixel[3] = alpha;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Explicit alpha of 0 to 15 for the pixels in order, pixel `i` uses color `i % 4`
+    fn block(c0: u16, c1: u16) -> [u8; 16] {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&0xFEDC_BA98_7654_3210u64.to_le_bytes());
+        block[8..10].copy_from_slice(&c0.to_le_bytes());
+        block[10..12].copy_from_slice(&c1.to_le_bytes());
+        block[12..].copy_from_slice(&0xE4E4_E4E4u32.to_le_bytes());
+        block
+    }
+
+    fn decode(block: &[u8]) -> [[u8; 4]; 16] {
+        let mut out = [[0; 4]; 16];
+        decode_atc_explicit_block(block, &mut out);
+        out
+    }
+
+    #[test]
+    fn interpolates_between_colors() {
+        // RGB555 red and RGB565 blue, interpolated at 3/8 and 5/8
+        let pixels = decode(&block(0x7C00, 0x001F));
+        let palette = [[255, 0, 0], [159, 0, 95], [95, 0, 159], [0, 0, 255]];
+        for (i, pixel) in pixels.iter().enumerate() {
+            let [r, g, b] = palette[i % 4];
+            assert_eq!(*pixel, [r, g, b, i as u8 * 17], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn top_bit_of_first_color_selects_alternate_palette() {
+        // Red with a little blue and magenta, giving black, `c0 - c1 / 4` with blue clamped to 0, `c0` and `c1`
+        let pixels = decode(&block(0xFC04, 0xF81F));
+        let palette = [[0, 0, 0], [192, 0, 0], [255, 0, 33], [255, 0, 255]];
+        for (i, pixel) in pixels.iter().enumerate() {
+            let [r, g, b] = palette[i % 4];
+            assert_eq!(*pixel, [r, g, b, i as u8 * 17], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn interpolated_alpha() {
+        let mut block = block(0x7C00, 0x001F);
+        // Alpha endpoints 200 and 100, the second pixel uses index 1
+        block[..8].copy_from_slice(&[200, 100, 0x08, 0, 0, 0, 0, 0]);
+        let mut out = [[0; 4]; 16];
+        decode_atc_interpolated_block(&block, &mut out);
+        assert_eq!(out[0], [255, 0, 0, 200]);
+        assert_eq!(out[1], [159, 0, 95, 100]);
+        assert_eq!(out[2][3], 200);
+    }
+}
@@ -0,0 +1,217 @@
+//! BC1-BC5 (DXT1-5, RGTC) block decoders
+
+pub(super) fn expand_565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1F) as u8;
+    let g = ((color >> 5) & 0x3F) as u8;
+    let b = (color & 0x1F) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        255,
+    ]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], weight_a: u16, weight_b: u16) -> [u8; 4] {
+    let total = weight_a + weight_b;
+    let mut out = [255; 4];
+    for i in 0..3 {
+        out[i] = ((a[i] as u16 * weight_a + b[i] as u16 * weight_b) / total) as u8;
+    }
+    out
+}
+
+/// Decodes the 8 byte color part shared by BC1-3.
+///
+/// BC2 and BC3 always use four colors, BC1 switches to three colors and transparent black when `c0 <= c1`.
+fn decode_color(block: &[u8], out: &mut [[u8; 4]; 16], punchthrough: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (expand_565(c0), expand_565(c1));
+
+    let palette = if c0 > c1 || !punchthrough {
+        [e0, e1, mix(e0, e1, 2, 1), mix(e0, e1, 1, 2)]
+    } else {
+        [e0, e1, mix(e0, e1, 1, 1), [0; 4]]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (i * 2)) as usize & 3];
+    }
+}
+
+/// Decodes an 8 byte BC4 block into a single channel
+pub fn decode_bc4_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [a0 as u8, a1 as u8, 0, 0, 0, 0, 0, 0];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1 + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1 + 2) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let bits = u64::from_le_bytes(bits);
+
+    std::array::from_fn(|i| palette[(bits >> (i * 3)) as usize & 7])
+}
+
+pub fn decode_bc1_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(block, out, true);
+}
+
+/// BC2 (DXT3), explicit 4 bit alpha
+pub fn decode_bc2_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], out, false);
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    for (i, pixel) in out.iter_mut().enumerate() {
+        pixel[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+    }
+}
+
+/// BC3 (DXT5), interpolated alpha
+pub fn decode_bc3_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], out, false);
+    for (pixel, alpha) in out.iter_mut().zip(decode_bc4_channel(&block[..8])) {
+        pixel[3] = alpha;
+    }
+}
+
+/// BC4, decoded into the red channel
+pub fn decode_bc4_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    for (pixel, r) in out.iter_mut().zip(decode_bc4_channel(block)) {
+        *pixel = [r, 0, 0, 255];
+    }
+}
+
+/// BC5, decoded into the red and green channels
+pub fn decode_bc5_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let r = decode_bc4_channel(&block[..8]);
+    let g = decode_bc4_channel(&block[8..]);
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = [r[i], g[i], 0, 255];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0xF800;
+    const BLUE: u16 = 0x001F;
+
+    fn color_block(c0: u16, c1: u16, indices: u32) -> Vec<u8> {
+        [c0.to_le_bytes(), c1.to_le_bytes()]
+            .concat()
+            .into_iter()
+            .chain(indices.to_le_bytes())
+            .collect()
+    }
+
+    fn bc4_block(a0: u8, a1: u8, indices: [u64; 16]) -> Vec<u8> {
+        let bits = indices
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, index)| bits | index << (i * 3));
+        [a0, a1]
+            .into_iter()
+            .chain(bits.to_le_bytes().into_iter().take(6))
+            .collect()
+    }
+
+    /// Every row uses the indices 0, 1, 2 and 3 from left to right
+    const COLUMN_INDICES: u32 = 0xE4E4_E4E4;
+
+    fn decode(decoder: fn(&[u8], &mut [[u8; 4]; 16]), block: &[u8]) -> [[u8; 4]; 16] {
+        let mut out = [[0; 4]; 16];
+        decoder(block, &mut out);
+        out
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        let out = decode(decode_bc1_block, &color_block(RED, BLUE, COLUMN_INDICES));
+        let row = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        assert_eq!(out, [row; 4].concat()[..]);
+    }
+
+    #[test]
+    fn bc1_three_colors_and_transparent_black() {
+        // c0 <= c1 switches to the midpoint and transparent black
+        let out = decode(decode_bc1_block, &color_block(BLUE, RED, COLUMN_INDICES));
+        let row = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0; 4],
+        ];
+        assert_eq!(out, [row; 4].concat()[..]);
+
+        // Equal endpoints are also three color blocks
+        let out = decode(decode_bc1_block, &color_block(RED, RED, 0xFFFF_FFFF));
+        assert_eq!(out, [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn bc2_explicit_alpha_and_four_colors() {
+        let alpha = (0..16u64).fold(0, |bits, i| bits | i << (i * 4));
+        let block = [
+            alpha.to_le_bytes().to_vec(),
+            color_block(BLUE, RED, COLUMN_INDICES),
+        ]
+        .concat();
+        let out = decode(decode_bc2_block, &block);
+
+        // BC2 and BC3 never use the three color palette
+        let row = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        for (i, pixel) in out.iter().enumerate() {
+            let [r, g, b] = row[i % 4];
+            assert_eq!(*pixel, [r, g, b, i as u8 * 17], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn bc3_eight_alpha_values() {
+        let indices = std::array::from_fn(|i| i as u64 % 8);
+        let block = [bc4_block(255, 0, indices), color_block(RED, BLUE, 0)].concat();
+        let out = decode(decode_bc3_block, &block);
+
+        let palette = [255, 0, 219, 182, 146, 109, 73, 36];
+        for (i, pixel) in out.iter().enumerate() {
+            assert_eq!(*pixel, [255, 0, 0, palette[i % 8]], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn bc4_six_values_with_black_and_white() {
+        let indices = std::array::from_fn(|i| i as u64 % 8);
+        let palette = [0, 255, 51, 102, 153, 204, 0, 255];
+        assert_eq!(
+            decode_bc4_channel(&bc4_block(0, 255, indices)),
+            std::array::from_fn(|i| palette[i % 8])
+        );
+
+        // Equal endpoints also use the six value palette
+        let block = bc4_block(128, 128, [7; 16]);
+        assert_eq!(decode(decode_bc4_block, &block), [[255, 0, 0, 255]; 16]);
+    }
+
+    #[test]
+    fn bc5_two_channels() {
+        let block = [bc4_block(200, 100, [0; 16]), bc4_block(10, 20, [1; 16])].concat();
+        assert_eq!(decode(decode_bc5_block, &block), [[200, 20, 0, 255]; 16]);
+    }
+}
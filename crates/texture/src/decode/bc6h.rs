@@ -0,0 +1,288 @@
+//! BC6H block decoder, producing linear float colors
+
+use half::f16;
+
+use super::{
+    bc7::{ANCHORS_2, PARTITIONS_2, WEIGHTS_3, WEIGHTS_4},
+    BitReader,
+};
+
+/// Mode bits of the 14 modes. Modes 0-9 have two subsets, 10-13 have one.
+const MODE_VALUES: [u32; 14] = [
+    0b00, 0b01, 0b00010, 0b00110, 0b01010, 0b01110, 0b10010, 0b10110, 0b11010, 0b11110, 0b00011,
+    0b00111, 0b01011, 0b01111,
+];
+
+/// Endpoint precision and the delta precision of the red, green and blue channel of every mode
+const MODE_BITS: [(u32, [u32; 3]); 14] = [
+    (10, [5, 5, 5]),
+    (7, [6, 6, 6]),
+    (11, [5, 4, 4]),
+    (11, [4, 5, 4]),
+    (11, [4, 4, 5]),
+    (9, [5, 5, 5]),
+    (8, [6, 5, 5]),
+    (8, [5, 6, 5]),
+    (8, [5, 5, 6]),
+    (6, [6, 6, 6]),
+    (10, [10, 10, 10]),
+    (11, [9, 9, 9]),
+    (12, [8, 8, 8]),
+    (16, [4, 4, 4]),
+];
+
+/// Header fields of every mode in bitstream order, as `(field, first bit, bit count)`.
+///
+/// Fields 0-11 are the red, green and blue values of endpoints 0-3 (`rw gw bw rx gx bx ry gy by rz gz bz`
+/// in the D3D specification), field 12 is the partition.
+#[rustfmt::skip]
+const MODE_LAYOUTS: [&[(u8, u8, u8)]; 14] = [
+    // 00
+    &[(7, 4, 1), (8, 4, 1), (11, 4, 1), (0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 5), (10, 4, 1), (7, 0, 4), (4, 0, 5), (11, 0, 1), (10, 0, 4), (5, 0, 5), (11, 1, 1), (8, 0, 4), (6, 0, 5), (11, 2, 1), (9, 0, 5), (11, 3, 1), (12, 0, 5)],
+    // 01
+    &[(7, 5, 1), (10, 4, 1), (10, 5, 1), (0, 0, 7), (11, 0, 1), (11, 1, 1), (8, 4, 1), (1, 0, 7), (8, 5, 1), (11, 2, 1), (7, 4, 1), (2, 0, 7), (11, 3, 1), (11, 5, 1), (11, 4, 1), (3, 0, 6), (7, 0, 4), (4, 0, 6), (10, 0, 4), (5, 0, 6), (8, 0, 4), (6, 0, 6), (9, 0, 6), (12, 0, 5)],
+    // 00010
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 5), (0, 10, 1), (7, 0, 4), (4, 0, 4), (1, 10, 1), (11, 0, 1), (10, 0, 4), (5, 0, 4), (2, 10, 1), (11, 1, 1), (8, 0, 4), (6, 0, 5), (11, 2, 1), (9, 0, 5), (11, 3, 1), (12, 0, 5)],
+    // 00110
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 4), (0, 10, 1), (10, 4, 1), (7, 0, 4), (4, 0, 5), (1, 10, 1), (10, 0, 4), (5, 0, 4), (2, 10, 1), (11, 1, 1), (8, 0, 4), (6, 0, 4), (11, 0, 1), (11, 2, 1), (9, 0, 4), (7, 4, 1), (11, 3, 1), (12, 0, 5)],
+    // 01010
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 4), (0, 10, 1), (8, 4, 1), (7, 0, 4), (4, 0, 4), (1, 10, 1), (11, 0, 1), (10, 0, 4), (5, 0, 5), (2, 10, 1), (8, 0, 4), (6, 0, 4), (11, 1, 1), (11, 2, 1), (9, 0, 4), (11, 4, 1), (11, 3, 1), (12, 0, 5)],
+    // 01110
+    &[(0, 0, 9), (8, 4, 1), (1, 0, 9), (7, 4, 1), (2, 0, 9), (11, 4, 1), (3, 0, 5), (10, 4, 1), (7, 0, 4), (4, 0, 5), (11, 0, 1), (10, 0, 4), (5, 0, 5), (11, 1, 1), (8, 0, 4), (6, 0, 5), (11, 2, 1), (9, 0, 5), (11, 3, 1), (12, 0, 5)],
+    // 10010
+    &[(0, 0, 8), (10, 4, 1), (8, 4, 1), (1, 0, 8), (11, 2, 1), (7, 4, 1), (2, 0, 8), (11, 3, 1), (11, 4, 1), (3, 0, 6), (7, 0, 4), (4, 0, 5), (11, 0, 1), (10, 0, 4), (5, 0, 5), (11, 1, 1), (8, 0, 4), (6, 0, 6), (9, 0, 6), (12, 0, 5)],
+    // 10110
+    &[(0, 0, 8), (11, 0, 1), (8, 4, 1), (1, 0, 8), (7, 5, 1), (7, 4, 1), (2, 0, 8), (10, 5, 1), (11, 4, 1), (3, 0, 5), (10, 4, 1), (7, 0, 4), (4, 0, 6), (10, 0, 4), (5, 0, 5), (11, 1, 1), (8, 0, 4), (6, 0, 5), (11, 2, 1), (9, 0, 5), (11, 3, 1), (12, 0, 5)],
+    // 11010
+    &[(0, 0, 8), (11, 1, 1), (8, 4, 1), (1, 0, 8), (8, 5, 1), (7, 4, 1), (2, 0, 8), (11, 5, 1), (11, 4, 1), (3, 0, 5), (10, 4, 1), (7, 0, 4), (4, 0, 5), (11, 0, 1), (10, 0, 4), (5, 0, 6), (8, 0, 4), (6, 0, 5), (11, 2, 1), (9, 0, 5), (11, 3, 1), (12, 0, 5)],
+    // 11110
+    &[(0, 0, 6), (10, 4, 1), (11, 0, 1), (11, 1, 1), (8, 4, 1), (1, 0, 6), (7, 5, 1), (8, 5, 1), (11, 2, 1), (7, 4, 1), (2, 0, 6), (10, 5, 1), (11, 3, 1), (11, 5, 1), (11, 4, 1), (3, 0, 6), (7, 0, 4), (4, 0, 6), (10, 0, 4), (5, 0, 6), (8, 0, 4), (6, 0, 6), (9, 0, 6), (12, 0, 5)],
+    // 00011
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 10), (4, 0, 10), (5, 0, 10)],
+    // 00111
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 9), (0, 10, 1), (4, 0, 9), (1, 10, 1), (5, 0, 9), (2, 10, 1)],
+    // 01011
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 8), (0, 11, 1), (0, 10, 1), (4, 0, 8), (1, 11, 1), (1, 10, 1), (5, 0, 8), (2, 11, 1), (2, 10, 1)],
+    // 01111
+    &[(0, 0, 10), (1, 0, 10), (2, 0, 10), (3, 0, 4), (0, 15, 1), (0, 14, 1), (0, 13, 1), (0, 12, 1), (0, 11, 1), (0, 10, 1), (4, 0, 4), (1, 15, 1), (1, 14, 1), (1, 13, 1), (1, 12, 1), (1, 11, 1), (1, 10, 1), (5, 0, 4), (2, 15, 1), (2, 14, 1), (2, 13, 1), (2, 12, 1), (2, 11, 1), (2, 10, 1)],
+];
+
+fn extend_sign(value: i32, bits: u32) -> i32 {
+    (value << (32 - bits)) >> (32 - bits)
+}
+
+/// Endpoints 1-3 of transformed modes are stored as deltas to endpoint 0
+fn transform_inverse(value: i32, base: i32, bits: u32, signed: bool) -> i32 {
+    let value = (value + base) & ((1 << bits) - 1);
+    if signed {
+        extend_sign(value, bits)
+    } else {
+        value
+    }
+}
+
+fn unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xFFFF
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else if bits >= 16 || value == 0 {
+        value
+    } else {
+        let magnitude = value.abs();
+        let unquantized = if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        unquantized * value.signum()
+    }
+}
+
+fn interpolate(e0: i32, e1: i32, weight: u32) -> i32 {
+    (e0 * (64 - weight as i32) + e1 * weight as i32 + 32) >> 6
+}
+
+/// Scales an interpolated value to the bits of a half float
+fn finish_unquantize(value: i32, signed: bool) -> f16 {
+    if !signed {
+        f16::from_bits(((value * 31) >> 6) as u16)
+    } else {
+        let magnitude = (value.abs() * 31) >> 5;
+        let sign = if value < 0 { 0x8000 } else { 0 };
+        f16::from_bits(sign | magnitude as u16)
+    }
+}
+
+pub fn decode_bc6h_block(block: &[u8], signed: bool, out: &mut [[f32; 4]; 16]) {
+    let mut bits = BitReader::new(block);
+    let mut mode_value = bits.read(2);
+    if mode_value > 1 {
+        mode_value |= bits.read(3) << 2;
+    }
+    let Some(mode) = MODE_VALUES.iter().position(|&m| m == mode_value) else {
+        // Reserved modes decode to black
+        *out = [[0.0, 0.0, 0.0, 1.0]; 16];
+        return;
+    };
+
+    let mut fields = [0i32; 13];
+    for &(field, first_bit, count) in MODE_LAYOUTS[mode] {
+        fields[field as usize] |= (bits.read(count as u32) << first_bit) as i32;
+    }
+    let partition = fields[12] as usize;
+
+    let two_subsets = mode < 10;
+    let endpoint_count = if two_subsets { 4 } else { 2 };
+    let (precision, delta_bits) = MODE_BITS[mode];
+    let mut endpoints: [[i32; 3]; 4] =
+        std::array::from_fn(|e| std::array::from_fn(|c| fields[e * 3 + c]));
+
+    // Modes 9 and 10 store all endpoints at full precision, the others store deltas
+    let transformed = mode != 9 && mode != 10;
+    if signed {
+        for value in endpoints[0].iter_mut() {
+            *value = extend_sign(*value, precision);
+        }
+    }
+    if transformed || signed {
+        for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+            for (value, bits) in endpoint.iter_mut().zip(delta_bits) {
+                *value = extend_sign(*value, bits);
+            }
+        }
+    }
+    if transformed {
+        let base = endpoints[0];
+        for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+            for (value, base) in endpoint.iter_mut().zip(base) {
+                *value = transform_inverse(*value, base, precision, signed);
+            }
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for value in endpoint.iter_mut() {
+            *value = unquantize(*value, precision, signed);
+        }
+    }
+
+    for (pixel, out) in out.iter_mut().enumerate() {
+        let (subset, is_anchor) = if two_subsets {
+            (
+                (PARTITIONS_2[partition] >> pixel) as usize & 1,
+                pixel == 0 || pixel == ANCHORS_2[partition] as usize,
+            )
+        } else {
+            (0, pixel == 0)
+        };
+
+        // Anchor indices have an implicit 0 as their highest bit
+        let index_bits = if two_subsets { 3 } else { 4 } - is_anchor as u32;
+        let index = bits.read(index_bits) as usize;
+        let weight = if two_subsets {
+            WEIGHTS_3[index]
+        } else {
+            WEIGHTS_4[index]
+        };
+
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        *out = [
+            finish_unquantize(interpolate(e0[0], e1[0], weight), signed).to_f32(),
+            finish_unquantize(interpolate(e0[1], e1[1], weight), signed).to_f32(),
+            finish_unquantize(interpolate(e0[2], e1[2], weight), signed).to_f32(),
+            1.0,
+        ];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::pack_bits;
+
+    fn decode(fields: &[(u32, u32)], signed: bool) -> [[f32; 4]; 16] {
+        let mut out = [[0.0; 4]; 16];
+        decode_bc6h_block(&pack_bits(fields), signed, &mut out);
+        out
+    }
+
+    /// Mode 10 (`00011`), one subset with two 10 bit endpoints and the index of every pixel being the pixel
+    fn mode_10(e0: [u32; 3], e1: [u32; 3]) -> Vec<(u32, u32)> {
+        let mut fields = vec![(5, 0b00011)];
+        fields.extend(e0.into_iter().chain(e1).map(|v| (10, v)));
+        fields.extend((0..16).map(|pixel| (if pixel == 0 { 3 } else { 4 }, pixel)));
+        fields
+    }
+
+    #[test]
+    fn unsigned_mode_10() {
+        let out = decode(&mode_10([0, 0, 0], [1023, 512, 0]), false);
+        assert_eq!(out[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(out[8], [2.935_546_9, 0.009_704_59, 0.0, 1.0]);
+        // The largest value unquantizes to the largest finite half float
+        assert_eq!(out[15], [65504.0, 1.514_648_4, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn signed_mode_10() {
+        // -512 and -100 as 10 bit two's complement
+        let out = decode(&mode_10([512, 100, 0], [511, 924, 0]), true);
+        assert_eq!(out[0], [-65504.0, 0.002_119_064_3, 0.0, 1.0]);
+        assert_eq!(out[8], [0.000_118_255_615, -2.318_620_7e-5, 0.0, 1.0]);
+        assert_eq!(out[15], [65504.0, -0.002_119_064_3, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn transformed_two_subset_mode_0() {
+        // Base endpoint (100, 200, 300) with the deltas (5, -1, 0), (-3, 0, 2) and (0, 0, -2), in the bit order of
+        // the D3D specification
+        #[rustfmt::skip]
+        let mut fields = vec![
+            (2, 0b00),
+            (1, 0), (1, 0), (1, 1), // gy[4], by[4], bz[4]
+            (10, 100), (10, 200), (10, 300), // rw, gw, bw
+            (5, 5), (1, 0), (4, 0), // rx, gz[4], gy[3:0]
+            (5, 31), (1, 0), (4, 0), // gx, bz[0], gz[3:0]
+            (5, 0), (1, 1), (4, 2), // bx, bz[1], by[3:0]
+            (5, 0b11101), (1, 1), // ry, bz[2]
+            (5, 0), (1, 1), // rz, bz[3]
+            (5, 13), // partition 13: the bottom two rows are subset 1
+        ];
+        fields.extend(indices_3(|pixel| match pixel {
+            7 => 7,
+            15 => 3,
+            _ => 0,
+        }));
+
+        let out = decode(&fields, false);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                7 => [0.000_291_347_5, 0.002_029_419, 0.017_135_62, 1.0],
+                0..8 => [0.000_254_392_62, 0.002_088_546_8, 0.017_135_62, 1.0],
+                15 => [0.000_242_829_32, 0.002_088_546_8, 0.017_288_208, 1.0],
+                _ => [0.000_238_180_16, 0.002_088_546_8, 0.018_081_665, 1.0],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    /// 3 bit indices of a two subset block with its anchors at pixels 0 and 15
+    fn indices_3(index: impl Fn(u32) -> u32) -> Vec<(u32, u32)> {
+        (0..16)
+            .map(|pixel| (if pixel == 0 || pixel == 15 { 2 } else { 3 }, index(pixel)))
+            .collect()
+    }
+
+    #[test]
+    fn reserved_modes_decode_to_black() {
+        let mut block = [0xFF; 16];
+        block[0] = 0b10011;
+        let mut out = [[1.0; 4]; 16];
+        decode_bc6h_block(&block, false, &mut out);
+        assert_eq!(out, [[0.0, 0.0, 0.0, 1.0]; 16]);
+    }
+}
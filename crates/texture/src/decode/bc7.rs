@@ -0,0 +1,473 @@
+//! BC7 block decoder
+//!
+//! The partition tables are shared with BC6H.
+
+use super::BitReader;
+
+struct Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint
+    endpoint_pbits: bool,
+    /// One p-bit per subset, shared by both of its endpoints
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Separate alpha (or color, see `index_selection_bits`) indices
+    index_bits_2: u32,
+}
+
+#[rustfmt::skip]
+const MODES: [Mode; 8] = [
+    Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits_2: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits_2: 0 },
+    Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits_2: 0 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 3 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits_2: 2 },
+    Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits_2: 0 },
+    Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits_2: 0 },
+];
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+pub(super) const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+pub(super) const WEIGHTS_4: [u32; 16] =
+    [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Subset of every pixel for the 64 two-subset partitions, one bit per pixel
+pub(super) const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Subset of every pixel for the 64 three-subset partitions, two bits per pixel
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// Pixel whose index is stored with one bit less (the "fix-up" index) for the second subset
+pub(super) const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const ANCHORS_3_2: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+
+const ANCHORS_3_3: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+fn weight(bits: u32, index: u32) -> u32 {
+    match bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+
+fn interpolate(e0: u8, e1: u8, weight: u32) -> u8 {
+    ((e0 as u32 * (64 - weight) + e1 as u32 * weight + 32) >> 6) as u8
+}
+
+/// Expands an n bit endpoint to 8 bits by replicating its high bits
+fn expand(value: u32, bits: u32) -> u8 {
+    let value = value << (8 - bits);
+    (value | (value >> bits)) as u8
+}
+
+pub fn decode_bc7_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let mode_index = block[0].trailing_zeros() as usize;
+    let Some(mode) = MODES.get(mode_index) else {
+        // Reserved mode
+        *out = [[0; 4]; 16];
+        return;
+    };
+
+    let mut bits = BitReader::new(block);
+    bits.read(mode_index as u32 + 1);
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut raw = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in raw.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+    if mode.alpha_bits > 0 {
+        for endpoint in raw.iter_mut().take(endpoint_count) {
+            endpoint[3] = bits.read(mode.alpha_bits);
+        }
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let mut pbits = [0u32; 6];
+        if mode.endpoint_pbits {
+            for pbit in pbits.iter_mut().take(endpoint_count) {
+                *pbit = bits.read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let pbit = bits.read(1);
+                pbits[subset * 2] = pbit;
+                pbits[subset * 2 + 1] = pbit;
+            }
+        }
+
+        for (endpoint, pbit) in raw.iter_mut().zip(pbits) {
+            for value in endpoint.iter_mut() {
+                *value = (*value << 1) | pbit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    let endpoints: [[u8; 4]; 6] = std::array::from_fn(|i| {
+        let [r, g, b, a] = raw[i];
+        [
+            expand(r, color_bits),
+            expand(g, color_bits),
+            expand(b, color_bits),
+            if alpha_bits > 0 {
+                expand(a, alpha_bits)
+            } else {
+                255
+            },
+        ]
+    });
+
+    let subset = |pixel: usize| -> usize {
+        match mode.subsets {
+            2 => (PARTITIONS_2[partition] >> pixel) as usize & 1,
+            3 => (PARTITIONS_3[partition] >> (pixel * 2)) as usize & 3,
+            _ => 0,
+        }
+    };
+    let is_anchor = |pixel: usize| -> bool {
+        pixel == 0
+            || match mode.subsets {
+                2 => pixel == ANCHORS_2[partition] as usize,
+                3 => {
+                    pixel == ANCHORS_3_2[partition] as usize
+                        || pixel == ANCHORS_3_3[partition] as usize
+                }
+                _ => false,
+            }
+    };
+
+    // Anchor indices have an implicit 0 as their highest bit
+    let indices: [u32; 16] =
+        std::array::from_fn(|pixel| bits.read(mode.index_bits - is_anchor(pixel) as u32));
+    let indices_2: [u32; 16] = std::array::from_fn(|pixel| {
+        if mode.index_bits_2 > 0 {
+            bits.read(mode.index_bits_2 - (pixel == 0) as u32)
+        } else {
+            0
+        }
+    });
+
+    for (pixel, out) in out.iter_mut().enumerate() {
+        let s = subset(pixel);
+        let (e0, e1) = (endpoints[s * 2], endpoints[s * 2 + 1]);
+
+        let (color_weight, alpha_weight) = if mode.index_bits_2 == 0 {
+            let w = weight(mode.index_bits, indices[pixel]);
+            (w, w)
+        } else if index_selection == 0 {
+            (
+                weight(mode.index_bits, indices[pixel]),
+                weight(mode.index_bits_2, indices_2[pixel]),
+            )
+        } else {
+            (
+                weight(mode.index_bits_2, indices_2[pixel]),
+                weight(mode.index_bits, indices[pixel]),
+            )
+        };
+
+        let mut color = [
+            interpolate(e0[0], e1[0], color_weight),
+            interpolate(e0[1], e1[1], color_weight),
+            interpolate(e0[2], e1[2], color_weight),
+            interpolate(e0[3], e1[3], alpha_weight),
+        ];
+        match rotation {
+            1 => color.swap(0, 3),
+            2 => color.swap(1, 3),
+            3 => color.swap(2, 3),
+            _ => {}
+        }
+        *out = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::pack_bits;
+
+    fn decode(fields: &[(u32, u32)]) -> [[u8; 4]; 16] {
+        let mut out = [[0; 4]; 16];
+        decode_bc7_block(&pack_bits(fields), &mut out);
+        out
+    }
+
+    /// Index fields of all pixels, anchors are one bit shorter
+    fn indices(bits: u32, anchors: &[usize], index: impl Fn(usize) -> u32) -> Vec<(u32, u32)> {
+        (0..16)
+            .map(|pixel| (bits - anchors.contains(&pixel) as u32, index(pixel)))
+            .collect()
+    }
+
+    /// Endpoint fields of a mode, channel by channel
+    fn endpoints(bits: u32, values: &[[u32; 3]]) -> Vec<(u32, u32)> {
+        (0..3)
+            .flat_map(|c| values.iter().map(move |v| (bits, v[c])))
+            .collect()
+    }
+
+    #[test]
+    fn mode_0() {
+        // Partition 0: pixels 0, 1, 4, 5 and 8 in subset 0, 2, 3, 6, 7 and 11 in subset 1, the rest in subset 2
+        let mut fields = vec![(1, 1), (4, 0)];
+        fields.extend(endpoints(
+            4,
+            &[
+                [15, 0, 0],
+                [0, 0, 0],
+                [0, 15, 0],
+                [0, 0, 0],
+                [0, 0, 15],
+                [15, 15, 15],
+            ],
+        ));
+        fields.extend([1, 0, 0, 1, 1, 1].map(|pbit| (1, pbit)));
+        fields.extend(indices(3, &[0, 3, 15], |pixel| match pixel {
+            13 => 3,
+            14 => 7,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                0 | 1 | 4 | 5 | 8 => [255, 8, 8, 255],
+                2 | 3 | 6 | 7 | 11 => [0, 247, 0, 255],
+                13 => [112, 112, 255, 255],
+                14 => [255; 4],
+                _ => [8, 8, 255, 255],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_1() {
+        // Partition 13: the bottom two rows are subset 1
+        let mut fields = vec![(2, 0b10), (6, 13)];
+        fields.extend(endpoints(
+            6,
+            &[[63, 0, 32], [0, 63, 0], [0, 0, 0], [63, 63, 63]],
+        ));
+        // Shared p-bits, one per subset
+        fields.extend([(1, 1), (1, 0)]);
+        fields.extend(indices(3, &[0, 15], |pixel| match pixel {
+            7 => 7,
+            8 => 4,
+            15 => 3,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                7 => [2, 255, 2, 255],
+                0..8 => [255, 2, 131, 255],
+                8 => [146, 146, 146, 255],
+                15 => [107, 107, 107, 255],
+                _ => [0, 0, 0, 255],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_2() {
+        let mut fields = vec![(3, 0b100), (6, 0)];
+        fields.extend(endpoints(
+            5,
+            &[
+                [31, 0, 0],
+                [0, 0, 0],
+                [0, 31, 0],
+                [0, 0, 0],
+                [0, 0, 31],
+                [16, 16, 16],
+            ],
+        ));
+        fields.extend(indices(2, &[0, 3, 15], |pixel| match pixel {
+            13 => 1,
+            14 => 3,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                0 | 1 | 4 | 5 | 8 => [255, 0, 0, 255],
+                2 | 3 | 6 | 7 | 11 => [0, 255, 0, 255],
+                13 => [43, 43, 215, 255],
+                14 => [132, 132, 132, 255],
+                _ => [0, 0, 255, 255],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_3() {
+        // Partition 0: the right two columns are subset 1
+        let mut fields = vec![(4, 0b1000), (6, 0)];
+        fields.extend(endpoints(
+            7,
+            &[[127, 0, 0], [0, 0, 0], [0, 0, 127], [64, 64, 64]],
+        ));
+        fields.extend([1, 0, 0, 1].map(|pbit| (1, pbit)));
+        fields.extend(indices(2, &[0, 15], |pixel| match pixel {
+            1 => 3,
+            2 => 2,
+            15 => 1,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                1 => [0, 0, 0, 255],
+                2 => [87, 87, 170, 255],
+                15 => [42, 42, 213, 255],
+                _ if pixel % 4 < 2 => [255, 1, 1, 255],
+                _ => [0, 0, 254, 255],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_4_rotation_and_index_selection() {
+        // Rotation 1 swaps red and alpha, index selection 1 uses the 3 bit indices for color
+        let mut fields = vec![(5, 0b10000), (2, 1), (1, 1)];
+        fields.extend(endpoints(5, &[[31, 0, 0], [0, 16, 31]]));
+        fields.extend([(6, 0), (6, 63)]);
+        fields.extend(indices(2, &[0], |pixel| (pixel == 1) as u32));
+        fields.extend(indices(3, &[0], |pixel| match pixel {
+            1 => 7,
+            2 => 4,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                1 => [84, 132, 255, 0],
+                2 => [0, 76, 147, 108],
+                _ => [0, 0, 0, 255],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_5_rotation() {
+        // Rotation 3 swaps blue and alpha
+        let mut fields = vec![(6, 0b100000), (2, 3)];
+        fields.extend(endpoints(7, &[[127, 0, 0], [0, 127, 64]]));
+        fields.extend([(8, 0), (8, 200)]);
+        fields.extend(indices(2, &[0], |pixel| if pixel == 3 { 3 } else { 0 }));
+        fields.extend(indices(2, &[0], |pixel| (pixel == 3) as u32));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                3 => [0, 255, 66, 129],
+                _ => [255, 0, 0, 0],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn mode_6() {
+        let mut fields = vec![(7, 1 << 6)];
+        fields.extend(endpoints(7, &[[0, 127, 64], [127, 0, 64]]));
+        fields.extend([(7, 127), (7, 0), (1, 0), (1, 1)]);
+        fields.extend(indices(4, &[0], |pixel| pixel as u32));
+
+        let out = decode(&fields);
+        assert_eq!(out[0], [0, 254, 128, 254]);
+        assert_eq!(out[5], [84, 171, 128, 171]);
+        assert_eq!(out[15], [255, 1, 129, 1]);
+    }
+
+    #[test]
+    fn mode_7() {
+        // Partition 13: the bottom two rows are subset 1
+        let mut fields = vec![(8, 1 << 7), (6, 13)];
+        fields.extend(endpoints(
+            5,
+            &[[31, 0, 0], [0, 0, 0], [0, 0, 31], [31, 31, 31]],
+        ));
+        fields.extend([31, 0, 16, 31].map(|alpha| (5, alpha)));
+        fields.extend([1, 0, 0, 1].map(|pbit| (1, pbit)));
+        fields.extend(indices(2, &[0, 15], |pixel| match pixel {
+            1 => 2,
+            15 => 1,
+            _ => 0,
+        }));
+
+        let out = decode(&fields);
+        for (pixel, color) in out.iter().enumerate() {
+            let expected = match pixel {
+                1 => [84, 1, 1, 84],
+                0..8 => [255, 4, 4, 255],
+                15 => [84, 84, 252, 171],
+                _ => [0, 0, 251, 130],
+            };
+            assert_eq!(*color, expected, "pixel {pixel}");
+        }
+    }
+
+    #[test]
+    fn reserved_mode_decodes_to_transparent_black() {
+        let mut out = [[255; 4]; 16];
+        decode_bc7_block(&[0; 16], &mut out);
+        assert_eq!(out, [[0; 4]; 16]);
+    }
+}
@@ -0,0 +1,380 @@
+//! ETC1, ETC2 and EAC block decoders
+//!
+//! Blocks are stored big endian, and pixel indices are stored column by column.
+
+const MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+/// Distances of the ETC2 T and H modes
+const DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    Etc1,
+    Etc2,
+    /// ETC2 with 1 bit alpha, the differential bit signals whether the block is opaque
+    Etc2Punchthrough,
+}
+
+/// Reads `count` bits of the block, with the highest one at bit `msb`
+fn bits(block: u64, count: u32, msb: u32) -> i32 {
+    ((block >> (msb + 1 - count)) & ((1 << count) - 1)) as i32
+}
+
+fn extend_4(v: i32) -> i32 {
+    v * 17
+}
+
+fn extend_5(v: i32) -> i32 {
+    (v << 3) | (v >> 2)
+}
+
+fn extend_6(v: i32) -> i32 {
+    (v << 2) | (v >> 4)
+}
+
+fn extend_7(v: i32) -> i32 {
+    (v << 1) | (v >> 6)
+}
+
+fn clamp(v: i32) -> u8 {
+    v.clamp(0, 255) as u8
+}
+
+fn offset(color: [i32; 3], offset: i32) -> [u8; 4] {
+    [
+        clamp(color[0] + offset),
+        clamp(color[1] + offset),
+        clamp(color[2] + offset),
+        255,
+    ]
+}
+
+/// Index of the pixel at `(x, y)`, the high bits of all pixels are stored before the low bits
+fn pixel_index(block: u64, x: usize, y: usize) -> usize {
+    let k = x * 4 + y;
+    ((((block >> (16 + k)) & 1) << 1) | ((block >> k) & 1)) as usize
+}
+
+fn decode_color(block: &[u8], mode: ColorMode, out: &mut [[u8; 4]; 16]) {
+    let block = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let flip = bits(block, 1, 32) != 0;
+    let diff_bit = bits(block, 1, 33) != 0;
+    // Punchthrough blocks always use the differential mode
+    let (differential, opaque) = match mode {
+        ColorMode::Etc2Punchthrough => (true, diff_bit),
+        _ => (diff_bit, true),
+    };
+
+    let (base_1, base_2) = if differential {
+        let base = [bits(block, 5, 63), bits(block, 5, 55), bits(block, 5, 47)];
+        let delta = [
+            bits(block, 3, 58) << 29 >> 29,
+            bits(block, 3, 50) << 29 >> 29,
+            bits(block, 3, 42) << 29 >> 29,
+        ];
+        let second = [base[0] + delta[0], base[1] + delta[1], base[2] + delta[2]];
+
+        // ETC2 encodes its additional modes as overflowing differential colors
+        if mode != ColorMode::Etc1 {
+            if !(0..32).contains(&second[0]) {
+                return decode_t(block, opaque, out);
+            }
+            if !(0..32).contains(&second[1]) {
+                return decode_h(block, opaque, out);
+            }
+            if !(0..32).contains(&second[2]) {
+                return decode_planar(block, out);
+            }
+        }
+
+        (base.map(extend_5), second.map(|v| extend_5(v & 0x1F)))
+    } else {
+        (
+            [bits(block, 4, 63), bits(block, 4, 55), bits(block, 4, 47)].map(extend_4),
+            [bits(block, 4, 59), bits(block, 4, 51), bits(block, 4, 43)].map(extend_4),
+        )
+    };
+
+    let tables = [bits(block, 3, 39) as usize, bits(block, 3, 36) as usize];
+    for y in 0..4 {
+        for x in 0..4 {
+            let second = if flip { y >= 2 } else { x >= 2 };
+            let (base, table) = if second {
+                (base_2, tables[1])
+            } else {
+                (base_1, tables[0])
+            };
+
+            let index = pixel_index(block, x, y);
+            out[y * 4 + x] = match (opaque, index) {
+                (false, 2) => [0; 4],
+                (false, 0) => offset(base, 0),
+                _ => offset(base, MODIFIERS[table][index]),
+            };
+        }
+    }
+}
+
+fn paint(block: u64, colors: [[u8; 4]; 4], opaque: bool, out: &mut [[u8; 4]; 16]) {
+    for y in 0..4 {
+        for x in 0..4 {
+            let index = pixel_index(block, x, y);
+            out[y * 4 + x] = if !opaque && index == 2 {
+                [0; 4]
+            } else {
+                colors[index]
+            };
+        }
+    }
+}
+
+fn decode_t(block: u64, opaque: bool, out: &mut [[u8; 4]; 16]) {
+    let c0 = [
+        (bits(block, 2, 60) << 2) | bits(block, 2, 57),
+        bits(block, 4, 55),
+        bits(block, 4, 51),
+    ]
+    .map(extend_4);
+    let c1 = [bits(block, 4, 47), bits(block, 4, 43), bits(block, 4, 39)].map(extend_4);
+    let distance = DISTANCES[((bits(block, 2, 35) << 1) | bits(block, 1, 32)) as usize];
+
+    let colors = [
+        offset(c0, 0),
+        offset(c1, distance),
+        offset(c1, 0),
+        offset(c1, -distance),
+    ];
+    paint(block, colors, opaque, out);
+}
+
+fn decode_h(block: u64, opaque: bool, out: &mut [[u8; 4]; 16]) {
+    let c0 = [
+        bits(block, 4, 62),
+        (bits(block, 3, 58) << 1) | bits(block, 1, 52),
+        (bits(block, 1, 51) << 3) | (bits(block, 2, 49) << 1) | bits(block, 1, 47),
+    ];
+    let c1 = [bits(block, 4, 46), bits(block, 4, 42), bits(block, 4, 38)];
+
+    // The lowest distance bit is implied by the order of the colors
+    let value = |c: [i32; 3]| (c[0] << 8) | (c[1] << 4) | c[2];
+    let distance_index =
+        (bits(block, 1, 34) << 2) | (bits(block, 1, 32) << 1) | (value(c0) >= value(c1)) as i32;
+    let distance = DISTANCES[distance_index as usize];
+
+    let (c0, c1) = (c0.map(extend_4), c1.map(extend_4));
+    let colors = [
+        offset(c0, distance),
+        offset(c0, -distance),
+        offset(c1, distance),
+        offset(c1, -distance),
+    ];
+    paint(block, colors, opaque, out);
+}
+
+fn decode_planar(block: u64, out: &mut [[u8; 4]; 16]) {
+    let origin = [
+        extend_6(bits(block, 6, 62)),
+        extend_7((bits(block, 1, 56) << 6) | bits(block, 6, 54)),
+        extend_6((bits(block, 1, 48) << 5) | (bits(block, 2, 44) << 3) | bits(block, 3, 41)),
+    ];
+    let horizontal = [
+        extend_6((bits(block, 5, 38) << 1) | bits(block, 1, 32)),
+        extend_7(bits(block, 7, 31)),
+        extend_6(bits(block, 6, 24)),
+    ];
+    let vertical = [
+        extend_6(bits(block, 6, 18)),
+        extend_7(bits(block, 7, 12)),
+        extend_6(bits(block, 6, 5)),
+    ];
+
+    for y in 0..4 {
+        for x in 0..4 {
+            let channel = |c: usize| {
+                clamp(
+                    (x as i32 * (horizontal[c] - origin[c])
+                        + y as i32 * (vertical[c] - origin[c])
+                        + 4 * origin[c]
+                        + 2)
+                        >> 2,
+                )
+            };
+            out[y * 4 + x] = [channel(0), channel(1), channel(2), 255];
+        }
+    }
+}
+
+/// Decodes an 8 byte EAC block into a single channel, as used for the alpha of ETC2 RGBA8
+pub fn decode_eac_channel(block: &[u8]) -> [u8; 16] {
+    let block = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let base = bits(block, 8, 63);
+    let multiplier = bits(block, 4, 55);
+    let table = &EAC_MODIFIERS[bits(block, 4, 51) as usize];
+
+    let mut out = [0; 16];
+    for x in 0..4 {
+        for y in 0..4 {
+            let index = bits(block, 3, 47 - (x * 4 + y) as u32 * 3) as usize;
+            out[y * 4 + x] = clamp(base + table[index] * multiplier);
+        }
+    }
+    out
+}
+
+pub fn decode_etc1_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(block, ColorMode::Etc1, out);
+}
+
+pub fn decode_etc2_rgb_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(block, ColorMode::Etc2, out);
+}
+
+/// ETC2 with punchthrough (1 bit) alpha
+pub fn decode_etc2_rgba1_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(block, ColorMode::Etc2Punchthrough, out);
+}
+
+/// ETC2 with an EAC alpha block in front of the color block
+pub fn decode_etc2_rgba8_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], ColorMode::Etc2, out);
+    for (pixel, alpha) in out.iter_mut().zip(decode_eac_channel(&block[..8])) {
+        pixel[3] = alpha;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index bits where every pixel uses its column as index, the high bits are in the upper half
+    const COLUMN_INDICES: u64 = 0xFF00_F0F0;
+
+    fn decode(decoder: fn(&[u8], &mut [[u8; 4]; 16]), block: u64) -> [[u8; 4]; 16] {
+        let mut out = [[0; 4]; 16];
+        decoder(&block.to_be_bytes(), &mut out);
+        out
+    }
+
+    fn columns(colors: [[u8; 4]; 4]) -> [[u8; 4]; 16] {
+        std::array::from_fn(|i| colors[i % 4])
+    }
+
+    #[test]
+    fn etc2_t_mode() {
+        // Red overflows: colors (14, 5, 10) and (2, 8, 15) with distance 3 (16)
+        let block = 0xFA5A_28F7_0000_0000 | COLUMN_INDICES;
+        assert_eq!(
+            decode(decode_etc2_rgb_block, block),
+            columns([
+                [238, 85, 170, 255],
+                [50, 152, 255, 255],
+                [34, 136, 255, 255],
+                [18, 120, 239, 255],
+            ])
+        );
+    }
+
+    #[test]
+    fn etc2_h_mode() {
+        // Green overflows: colors (12, 3, 9) and (2, 6, 14), the first being larger adds 1 to distance 4
+        let block = 0x611C_9376_0000_0000 | COLUMN_INDICES;
+        assert_eq!(
+            decode(decode_etc2_rgb_block, block),
+            columns([
+                [236, 83, 185, 255],
+                [172, 19, 121, 255],
+                [66, 134, 255, 255],
+                [2, 70, 206, 255],
+            ])
+        );
+    }
+
+    #[test]
+    fn etc2_planar_mode() {
+        // Blue overflows: origin (40, 100, 10), horizontal (63, 0, 63) and vertical (0, 127, 32)
+        let out = decode(decode_etc2_rgb_block, 0x5148_0D7F_01F8_1FE0);
+        assert_eq!(out[0], [162, 201, 40, 255]);
+        assert_eq!(out[3], [232, 50, 201, 255]);
+        assert_eq!(out[12], [41, 242, 108, 255]);
+        assert_eq!(out[15], [110, 91, 255, 255]);
+        assert_eq!(out[2 * 4 + 1], [104, 178, 139, 255]);
+    }
+
+    #[test]
+    fn etc2_punchthrough_t_mode() {
+        // Clearing the differential bit makes index 2 transparent
+        let block = (0xFA5A_28F7_0000_0000 & !(1 << 33)) | COLUMN_INDICES;
+        assert_eq!(
+            decode(decode_etc2_rgba1_block, block),
+            columns([
+                [238, 85, 170, 255],
+                [50, 152, 255, 255],
+                [0; 4],
+                [18, 120, 239, 255],
+            ])
+        );
+    }
+
+    fn eac_block(base: u8, multiplier: u8, table: u8, index: impl Fn(usize) -> u64) -> [u8; 8] {
+        // Indices are stored column by column
+        let indices = (0..16).fold(0, |bits, k| {
+            bits | index((k % 4) * 4 + k / 4) << (45 - k * 3)
+        });
+        ((base as u64) << 56 | (multiplier as u64) << 52 | (table as u64) << 48 | indices)
+            .to_be_bytes()
+    }
+
+    #[test]
+    fn eac_alpha() {
+        let block = eac_block(128, 3, 13, |pixel| pixel as u64 % 8);
+        let values = [125, 122, 119, 98, 128, 131, 134, 155];
+        assert_eq!(
+            decode_eac_channel(&block),
+            std::array::from_fn(|pixel| values[pixel % 8])
+        );
+
+        // Values are clamped, and a multiplier of 0 only uses the base value
+        let block = eac_block(250, 15, 0, |_| 7);
+        assert_eq!(decode_eac_channel(&block), [255; 16]);
+        let block = eac_block(60, 0, 0, |_| 3);
+        assert_eq!(decode_eac_channel(&block), [60; 16]);
+    }
+
+    #[test]
+    fn etc2_rgba8_uses_eac_alpha() {
+        let mut block = eac_block(128, 3, 13, |pixel| pixel as u64 % 8).to_vec();
+        block.extend((0xFA5A_28F7_0000_0000u64 | COLUMN_INDICES).to_be_bytes());
+        let mut out = [[0; 4]; 16];
+        decode_etc2_rgba8_block(&block, &mut out);
+        assert_eq!(out[0], [238, 85, 170, 125]);
+        assert_eq!(out[7], [18, 120, 239, 155]);
+    }
+}
@@ -0,0 +1,353 @@
+//! Texture decoding on the CPU, for machines without a (suitable) GPU.
+//!
+//! Formats decode to RGBA8, except for HDR formats which decode to linear RGBA32F.
+
+use anyhow::ensure;
+use half::f16;
+
+use crate::format::PixelFormat;
+
+pub mod astc;
+pub mod atc;
+pub mod bc;
+pub mod bc6h;
+pub mod bc7;
+pub mod etc;
+pub mod pvrtc;
+
+#[derive(Debug, Clone)]
+pub enum Pixels {
+    Rgba8(Vec<u8>),
+    /// Linear float colors
+    Rgba32F(Vec<f32>),
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Pixels,
+}
+
+impl DecodedImage {
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.pixels, Pixels::Rgba32F(_))
+    }
+
+    /// Returns the pixels as RGBA8, float pixels are clamped to 0-1
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        match &self.pixels {
+            Pixels::Rgba8(pixels) => pixels.clone(),
+            Pixels::Rgba32F(pixels) => pixels
+                .iter()
+                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+        }
+    }
+
+    pub fn to_rgba32f(&self) -> Vec<f32> {
+        match &self.pixels {
+            Pixels::Rgba8(pixels) => pixels.iter().map(|v| *v as f32 / 255.0).collect(),
+            Pixels::Rgba32F(pixels) => pixels.clone(),
+        }
+    }
+}
+
+/// Reads a block compressed stream least significant bit first
+pub(crate) struct BitReader(u128);
+
+impl BitReader {
+    pub fn new(block: &[u8]) -> Self {
+        Self(u128::from_le_bytes(block[..16].try_into().unwrap()))
+    }
+
+    pub fn read(&mut self, count: u32) -> u32 {
+        let value = (self.0 & ((1 << count) - 1)) as u32;
+        self.0 >>= count;
+        value
+    }
+}
+
+/// Decodes a single (decompressed) mip level of the given format
+pub fn decode_surface(
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> anyhow::Result<DecodedImage> {
+    let rgba8 = |pixels: Vec<[u8; 4]>| Pixels::Rgba8(pixels.into_flattened());
+    let rgba32f = |pixels: Vec<[f32; 4]>| Pixels::Rgba32F(pixels.into_flattened());
+
+    let pixels = match format {
+        PixelFormat::Bc1 => rgba8(decode_blocks(data, width, height, 8, bc::decode_bc1_block)?),
+        PixelFormat::Bc2 => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            bc::decode_bc2_block,
+        )?),
+        PixelFormat::Bc3 => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            bc::decode_bc3_block,
+        )?),
+        PixelFormat::Bc4 => rgba8(decode_blocks(data, width, height, 8, bc::decode_bc4_block)?),
+        PixelFormat::Bc5 => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            bc::decode_bc5_block,
+        )?),
+        PixelFormat::Bc6U | PixelFormat::Bc6S => {
+            let signed = format == PixelFormat::Bc6S;
+            rgba32f(decode_blocks(data, width, height, 16, |block, out| {
+                bc6h::decode_bc6h_block(block, signed, out)
+            })?)
+        }
+        PixelFormat::Bc7 => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            bc7::decode_bc7_block,
+        )?),
+        PixelFormat::Etc1 => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            8,
+            etc::decode_etc1_block,
+        )?),
+        PixelFormat::Etc2Rgb => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            8,
+            etc::decode_etc2_rgb_block,
+        )?),
+        PixelFormat::Etc2Rgba => {
+            // Mapped to punchthrough alpha for the GPU, but textures with a full EAC alpha block are twice as large
+            if data.len() >= block_count(width, height) * 16 {
+                rgba8(decode_blocks(
+                    data,
+                    width,
+                    height,
+                    16,
+                    etc::decode_etc2_rgba8_block,
+                )?)
+            } else {
+                rgba8(decode_blocks(
+                    data,
+                    width,
+                    height,
+                    8,
+                    etc::decode_etc2_rgba1_block,
+                )?)
+            }
+        }
+        PixelFormat::AtcRgbaE => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            atc::decode_atc_explicit_block,
+        )?),
+        PixelFormat::AtcRgbaI => rgba8(decode_blocks(
+            data,
+            width,
+            height,
+            16,
+            atc::decode_atc_interpolated_block,
+        )?),
+        PixelFormat::Pvrtc2Rgb | PixelFormat::Pvrtc2Rgba => {
+            rgba8(pvrtc::decode_pvrtc(data, width, height, true)?)
+        }
+        PixelFormat::Pvrtc4Rgb | PixelFormat::Pvrtc4Rgba => {
+            rgba8(pvrtc::decode_pvrtc(data, width, height, false)?)
+        }
+        f if f.is_astc() && !f.is_hdr() => {
+            let (block_width, block_height, _) = f.block_size().unwrap();
+            rgba8(astc::decode_astc_ldr(
+                data,
+                width,
+                height,
+                block_width,
+                block_height,
+            )?)
+        }
+
+        PixelFormat::R8G8B8A8 => rgba8(decode_pixels(data, width, height, |p: [u8; 4]| p)?),
+        PixelFormat::B5G6R5 => rgba8(decode_pixels(data, width, height, |p: [u8; 2]| {
+            bc::expand_565(u16::from_le_bytes(p))
+        })?),
+        // Luminance is spread over the color channels, alpha only formats are white
+        PixelFormat::L8 => rgba8(decode_pixels(data, width, height, |[l]: [u8; 1]| {
+            [l, l, l, 255]
+        })?),
+        PixelFormat::A8 => rgba8(decode_pixels(data, width, height, |[a]: [u8; 1]| {
+            [255, 255, 255, a]
+        })?),
+        PixelFormat::A8L8 => rgba8(decode_pixels(data, width, height, |[l, a]: [u8; 2]| {
+            [l, l, l, a]
+        })?),
+        PixelFormat::L16 => rgba8(decode_pixels(data, width, height, |p: [u8; 2]| {
+            let l = (u16::from_le_bytes(p) >> 8) as u8;
+            [l, l, l, 255]
+        })?),
+        PixelFormat::G16R16 => rgba8(decode_pixels(data, width, height, |p: [u8; 4]| {
+            [p[1], p[3], 0, 255]
+        })?),
+        // Depth is shown as grayscale, the stencil bits of 24 bit depth are dropped
+        PixelFormat::D24 | PixelFormat::Shadowdepth => {
+            rgba8(decode_pixels(data, width, height, |p: [u8; 4]| {
+                let d = p[2];
+                [d, d, d, 255]
+            })?)
+        }
+        PixelFormat::D32 | PixelFormat::Shadowdepth32 => {
+            rgba32f(decode_pixels(data, width, height, |p: [u8; 4]| {
+                let d = f32::from_le_bytes(p);
+                [d, d, d, 1.0]
+            })?)
+        }
+        // Integer formats keep their raw values, exact up to 2^24
+        PixelFormat::R32u => rgba32f(decode_pixels(data, width, height, |p: [u8; 4]| {
+            [u32::from_le_bytes(p) as f32, 0.0, 0.0, 1.0]
+        })?),
+        PixelFormat::R32g32b32a32ui => {
+            rgba32f(decode_pixels(data, width, height, |p: [u8; 16]| {
+                std::array::from_fn(|i| {
+                    u32::from_le_bytes(p[i * 4..i * 4 + 4].try_into().unwrap()) as f32
+                })
+            })?)
+        }
+        PixelFormat::R10g10b10a2 => rgba8(decode_pixels(data, width, height, |p: [u8; 4]| {
+            let v = u32::from_le_bytes(p);
+            [
+                (v >> 2) as u8,
+                (v >> 12) as u8,
+                (v >> 22) as u8,
+                (v >> 30) as u8 * 85,
+            ]
+        })?),
+        PixelFormat::R16F => rgba32f(decode_pixels(data, width, height, |p: [u8; 2]| {
+            [f16::from_le_bytes(p).to_f32(), 0.0, 0.0, 1.0]
+        })?),
+        PixelFormat::R11g11b10 => rgba32f(decode_pixels(data, width, height, |p: [u8; 4]| {
+            let v = u32::from_le_bytes(p);
+            // Unsigned floats with a 5 bit exponent and 6 or 5 bit mantissa, like half floats without the sign
+            let r = f16::from_bits(((v & 0x7FF) << 4) as u16);
+            let g = f16::from_bits((((v >> 11) & 0x7FF) << 4) as u16);
+            let b = f16::from_bits((((v >> 22) & 0x3FF) << 5) as u16);
+            [r.to_f32(), g.to_f32(), b.to_f32(), 1.0]
+        })?),
+        PixelFormat::A32R32G32B32F | PixelFormat::FloatRGBA => {
+            rgba32f(decode_pixels(data, width, height, |p: [u8; 16]| {
+                std::array::from_fn(|i| f32_at(&p, i))
+            })?)
+        }
+        PixelFormat::FloatRGB => rgba32f(decode_pixels(data, width, height, |p: [u8; 12]| {
+            [f32_at(&p, 0), f32_at(&p, 1), f32_at(&p, 2), 1.0]
+        })?),
+        PixelFormat::G32R32F => rgba32f(decode_pixels(data, width, height, |p: [u8; 8]| {
+            [f32_at(&p, 0), f32_at(&p, 1), 0.0, 1.0]
+        })?),
+        PixelFormat::A16B16G16R16F => rgba32f(decode_pixels(data, width, height, |p: [u8; 8]| {
+            std::array::from_fn(|i| f16_at(&p, i))
+        })?),
+        PixelFormat::G16R16F => rgba32f(decode_pixels(data, width, height, |p: [u8; 4]| {
+            [f16_at(&p, 0), f16_at(&p, 1), 0.0, 1.0]
+        })?),
+        f if f.is_hdr() => {
+            let (block_width, block_height, _) = f.block_size().unwrap();
+            rgba32f(astc::decode_astc_hdr(
+                data,
+                width,
+                height,
+                block_width,
+                block_height,
+            )?)
+        }
+        _ => anyhow::bail!("Pixel format {format:?} is not supported by the CPU decoder"),
+    };
+
+    Ok(DecodedImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// The `i`th little endian float of a pixel
+fn f32_at(pixel: &[u8], i: usize) -> f32 {
+    f32::from_le_bytes(pixel[i * 4..i * 4 + 4].try_into().unwrap())
+}
+
+/// The `i`th little endian half float of a pixel
+fn f16_at(pixel: &[u8], i: usize) -> f32 {
+    f16::from_le_bytes(pixel[i * 2..i * 2 + 2].try_into().unwrap()).to_f32()
+}
+
+fn block_count(width: u32, height: u32) -> usize {
+    width.div_ceil(4) as usize * height.div_ceil(4) as usize
+}
+
+/// Decodes a surface made of 4x4 blocks, cropping the blocks at the right and bottom edges
+fn decode_blocks<T: Copy + Default>(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_size: usize,
+    decode_block: impl Fn(&[u8], &mut [T; 16]),
+) -> anyhow::Result<Vec<T>> {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = width.div_ceil(4);
+    let block_count = blocks_x * height.div_ceil(4);
+    ensure!(
+        data.len() >= block_count * block_size,
+        "Insufficient data for {width}x{height} texture, expected {} bytes but got {}",
+        block_count * block_size,
+        data.len()
+    );
+
+    let mut pixels = vec![T::default(); width * height];
+    let mut block_pixels = [T::default(); 16];
+    for (i, block) in data.chunks_exact(block_size).take(block_count).enumerate() {
+        decode_block(block, &mut block_pixels);
+
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for y in 0..4.min(height - block_y) {
+            let row = (block_y + y) * width + block_x;
+            let columns = 4.min(width - block_x);
+            pixels[row..row + columns].copy_from_slice(&block_pixels[y * 4..y * 4 + columns]);
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// Decodes a surface of uncompressed pixels that are `N` bytes each
+fn decode_pixels<T, const N: usize>(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    decode_pixel: impl Fn([u8; N]) -> T,
+) -> anyhow::Result<Vec<T>> {
+    let count = width as usize * height as usize;
+    ensure!(
+        data.len() >= count * N,
+        "Insufficient data for {width}x{height} texture, expected {} bytes but got {}",
+        count * N,
+        data.len()
+    );
+
+    Ok(data
+        .chunks_exact(N)
+        .take(count)
+        .map(|p| decode_pixel(p.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,395 @@
+//! PowerVR PVRTC1 decoder, for both the 4 and 2 bits per pixel variants.
+//!
+//! Unlike the other block formats, the colors of neighbouring blocks are blended together, so the whole surface is
+//! decoded at once. Blocks are stored in Morton order, which requires power of two dimensions.
+
+use anyhow::ensure;
+
+/// Modulation weights of the standard and punchthrough modes, out of 8
+const STANDARD_WEIGHTS: [u8; 4] = [0, 3, 5, 8];
+const PUNCHTHROUGH_WEIGHTS: [u8; 4] = [0, 4, 4, 8];
+
+/// How 2bpp blocks in the interpolated mode fill in the pixels without a stored modulation value
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Interpolation {
+    /// Every pixel is stored with a single bit
+    None,
+    Both,
+    Horizontal,
+    Vertical,
+}
+
+struct Block {
+    /// RGBA with 5 bit colors and 4 bit alpha
+    a: [u32; 4],
+    b: [u32; 4],
+    modulation: u32,
+    /// Punchthrough for 4bpp, interpolated for 2bpp
+    mode: bool,
+}
+
+impl Block {
+    fn new(block: &[u8]) -> Self {
+        let modulation = u32::from_le_bytes(block[..4].try_into().unwrap());
+        let color = u32::from_le_bytes(block[4..8].try_into().unwrap());
+        let (a, b) = (color & 0xFFFF, color >> 16);
+
+        let a = if a & 0x8000 != 0 {
+            [
+                (a >> 10) & 0x1F,
+                (a >> 5) & 0x1F,
+                (a & 0x1E) | ((a >> 4) & 1),
+                0xF,
+            ]
+        } else {
+            [
+                ((a >> 7) & 0x1E) | ((a >> 11) & 1),
+                ((a >> 3) & 0x1E) | ((a >> 7) & 1),
+                ((a << 1) & 0x1C) | ((a >> 2) & 3),
+                (a >> 11) & 0xE,
+            ]
+        };
+        let b = if b & 0x8000 != 0 {
+            [(b >> 10) & 0x1F, (b >> 5) & 0x1F, b & 0x1F, 0xF]
+        } else {
+            [
+                ((b >> 7) & 0x1E) | ((b >> 11) & 1),
+                ((b >> 3) & 0x1E) | ((b >> 7) & 1),
+                ((b << 1) & 0x1E) | ((b >> 3) & 1),
+                (b >> 11) & 0xE,
+            ]
+        };
+
+        Self {
+            a,
+            b,
+            modulation,
+            mode: color & 1 != 0,
+        }
+    }
+}
+
+/// Index of a block in the Morton (Z) order, with the y bit below the x bit
+fn morton_index(x: usize, y: usize, min_dim: usize) -> usize {
+    let (mut offset, mut shift) = (0, 0);
+    let mut mask = 1;
+    while mask < min_dim {
+        offset |= ((y & mask) | ((x & mask) << 1)) << shift;
+        mask <<= 1;
+        shift += 1;
+    }
+
+    offset | (((x | y) >> shift) << (shift * 2))
+}
+
+/// Decodes a PVRTC1 surface into RGBA8 pixels
+pub fn decode_pvrtc(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    two_bpp: bool,
+) -> anyhow::Result<Vec<[u8; 4]>> {
+    let block_width = if two_bpp { 8 } else { 4 };
+    let block_height = 4;
+    let (width, height) = (width as usize, height as usize);
+    // Surfaces are at least 2x2 blocks
+    let blocks_x = width.div_ceil(block_width).max(2);
+    let blocks_y = height.div_ceil(block_height).max(2);
+    ensure!(
+        blocks_x.is_power_of_two() && blocks_y.is_power_of_two(),
+        "PVRTC textures must have power of two dimensions, got {width}x{height}"
+    );
+    let block_count = blocks_x * blocks_y;
+    ensure!(
+        data.len() >= block_count * 8,
+        "Insufficient data for {width}x{height} texture, expected {} bytes but got {}",
+        block_count * 8,
+        data.len()
+    );
+
+    let min_dim = blocks_x.min(blocks_y);
+    let blocks: Vec<Block> = (0..block_count)
+        .map(|i| {
+            let offset = morton_index(i % blocks_x, i / blocks_x, min_dim) * 8;
+            Block::new(&data[offset..offset + 8])
+        })
+        .collect();
+    let block_at = |x: isize, y: isize| {
+        let x = x.rem_euclid(blocks_x as isize) as usize;
+        let y = y.rem_euclid(blocks_y as isize) as usize;
+        &blocks[y * blocks_x + x]
+    };
+
+    // Modulation weight of every pixel, or `None` for punched through pixels
+    let (full_width, full_height) = (blocks_x * block_width, blocks_y * block_height);
+    let weights = if two_bpp {
+        modulation_2bpp(&blocks, blocks_x, full_width, full_height)
+    } else {
+        modulation_4bpp(&blocks, blocks_x, full_width, full_height)
+    };
+
+    // The colors of a block are centered on it, and blended bilinearly with the neighbouring blocks
+    let shift = two_bpp as u32;
+    let expand = |sum: [u32; 4]| {
+        [
+            (sum[0] >> (1 + shift)) + (sum[0] >> (6 + shift)),
+            (sum[1] >> (1 + shift)) + (sum[1] >> (6 + shift)),
+            (sum[2] >> (1 + shift)) + (sum[2] >> (6 + shift)),
+            (sum[3] >> shift) + (sum[3] >> (4 + shift)),
+        ]
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let gy = y as isize - block_height as isize / 2;
+        let (by, fy) = (
+            gy.div_euclid(block_height as isize),
+            gy.rem_euclid(block_height as isize) as u32,
+        );
+        for x in 0..width {
+            let gx = x as isize - block_width as isize / 2;
+            let (bx, fx) = (
+                gx.div_euclid(block_width as isize),
+                gx.rem_euclid(block_width as isize) as u32,
+            );
+
+            let (bw, bh) = (block_width as u32, block_height as u32);
+            let neighbours = [
+                (block_at(bx, by), (bw - fx) * (bh - fy)),
+                (block_at(bx + 1, by), fx * (bh - fy)),
+                (block_at(bx, by + 1), (bw - fx) * fy),
+                (block_at(bx + 1, by + 1), fx * fy),
+            ];
+            let mut sum_a = [0; 4];
+            let mut sum_b = [0; 4];
+            for (block, weight) in neighbours {
+                for c in 0..4 {
+                    sum_a[c] += block.a[c] * weight;
+                    sum_b[c] += block.b[c] * weight;
+                }
+            }
+            let (a, b) = (expand(sum_a), expand(sum_b));
+
+            let pixel = match weights[y * full_width + x] {
+                Some(w) => {
+                    let w = w as u32;
+                    std::array::from_fn(|c| ((a[c] * (8 - w) + b[c] * w) / 8) as u8)
+                }
+                None => {
+                    let mix = |c: usize| ((a[c] + b[c]) / 2) as u8;
+                    [mix(0), mix(1), mix(2), 0]
+                }
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn modulation_4bpp(
+    blocks: &[Block],
+    blocks_x: usize,
+    full_width: usize,
+    full_height: usize,
+) -> Vec<Option<u8>> {
+    let mut weights = vec![Some(0); full_width * full_height];
+    for (i, block) in blocks.iter().enumerate() {
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for p in 0..16 {
+            let value = (block.modulation >> (p * 2)) as usize & 3;
+            weights[(block_y + p / 4) * full_width + block_x + p % 4] = match block.mode {
+                true if value == 2 => None,
+                true => Some(PUNCHTHROUGH_WEIGHTS[value]),
+                false => Some(STANDARD_WEIGHTS[value]),
+            };
+        }
+    }
+
+    weights
+}
+
+fn modulation_2bpp(
+    blocks: &[Block],
+    blocks_x: usize,
+    full_width: usize,
+    full_height: usize,
+) -> Vec<Option<u8>> {
+    // Stored 2 bit values, single bit values are doubled
+    let mut values = vec![0u8; full_width * full_height];
+    let mut interpolation = vec![Interpolation::None; full_width * full_height];
+    for (i, block) in blocks.iter().enumerate() {
+        let (block_x, block_y) = (i % blocks_x * 8, i / blocks_x * 4);
+        let mut bits = block.modulation;
+
+        if !block.mode {
+            for p in 0..32 {
+                values[(block_y + p / 8) * full_width + block_x + p % 8] =
+                    if bits >> p & 1 != 0 { 3 } else { 0 };
+            }
+            continue;
+        }
+
+        // The lowest bit of the first value picks the mode, the lowest bit of the center value (bit 20) picks
+        // between horizontal and vertical. Those values only have their upper bit
+        let mode = if bits & 1 == 0 {
+            Interpolation::Both
+        } else if bits & (1 << 20) != 0 {
+            Interpolation::Vertical
+        } else {
+            Interpolation::Horizontal
+        };
+        if mode != Interpolation::Both {
+            bits = (bits & !(1 << 20)) | ((bits >> 1) & (1 << 20));
+        }
+        bits = (bits & !1) | ((bits >> 1) & 1);
+
+        // Values are stored in a checkerboard pattern
+        for y in 0..4 {
+            for x in 0..8 {
+                let index = (block_y + y) * full_width + block_x + x;
+                interpolation[index] = mode;
+                if (x ^ y) & 1 == 0 {
+                    values[index] = (bits & 3) as u8;
+                    bits >>= 2;
+                }
+            }
+        }
+    }
+
+    let value_at = |x: isize, y: isize| {
+        let x = x.rem_euclid(full_width as isize) as usize;
+        let y = y.rem_euclid(full_height as isize) as usize;
+        STANDARD_WEIGHTS[values[y * full_width + x] as usize]
+    };
+
+    let mut weights = Vec::with_capacity(full_width * full_height);
+    for y in 0..full_height as isize {
+        for x in 0..full_width as isize {
+            let mode = interpolation[y as usize * full_width + x as usize];
+            let weight = if mode == Interpolation::None || (x ^ y) & 1 == 0 {
+                value_at(x, y)
+            } else {
+                let (left, right) = (value_at(x - 1, y), value_at(x + 1, y));
+                let (up, down) = (value_at(x, y - 1), value_at(x, y + 1));
+                match mode {
+                    Interpolation::Both => (left + right + up + down + 2) / 4,
+                    Interpolation::Horizontal => (left + right).div_ceil(2),
+                    _ => (up + down).div_ceil(2),
+                }
+            };
+            weights.push(Some(weight));
+        }
+    }
+
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Color word with opaque black as color A and opaque white as color B, with or without the mode bit
+    const BLACK_WHITE: u32 = 0xFFFF_8000;
+    const BLACK_WHITE_MODE: u32 = 0xFFFF_8001;
+
+    fn surface(blocks: &[(u32, u32)]) -> Vec<u8> {
+        blocks
+            .iter()
+            .flat_map(|(modulation, color)| [modulation.to_le_bytes(), color.to_le_bytes()])
+            .flatten()
+            .collect()
+    }
+
+    fn gray(v: u8) -> [u8; 4] {
+        [v, v, v, 255]
+    }
+
+    #[test]
+    fn standard_4bpp() {
+        // The 2 bit value of every pixel is its column, weights of 0, 3, 5 and 8 eighths of white
+        let data = surface(&[(0xE4E4_E4E4, BLACK_WHITE); 4]);
+        let pixels = decode_pvrtc(&data, 8, 8, false).unwrap();
+        for (i, pixel) in pixels.iter().enumerate() {
+            assert_eq!(*pixel, gray([0, 95, 159, 255][i % 4]), "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn punchthrough_4bpp() {
+        // Value 2 is transparent with the average of both colors
+        let data = surface(&[(0xE4E4_E4E4, BLACK_WHITE_MODE); 4]);
+        let pixels = decode_pvrtc(&data, 8, 8, false).unwrap();
+        let expected = [gray(0), gray(127), [127, 127, 127, 0], gray(255)];
+        for (i, pixel) in pixels.iter().enumerate() {
+            assert_eq!(*pixel, expected[i % 4], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn blocks_are_in_morton_order() {
+        // Stored blocks are (0, 0), (0, 1), (1, 0) and (1, 1), every pixel of block i has the value i
+        let data = surface(&[
+            (0x0000_0000, BLACK_WHITE),
+            (0x5555_5555, BLACK_WHITE),
+            (0xAAAA_AAAA, BLACK_WHITE),
+            (0xFFFF_FFFF, BLACK_WHITE),
+        ]);
+        let pixels = decode_pvrtc(&data, 8, 8, false).unwrap();
+        assert_eq!(pixels[0], gray(0));
+        assert_eq!(pixels[4 * 8], gray(95));
+        assert_eq!(pixels[4], gray(159));
+        assert_eq!(pixels[4 * 8 + 4], gray(255));
+    }
+
+    #[test]
+    fn colors_blend_between_block_centers() {
+        // Block (0, 0) has an opaque red color A, every pixel uses color A only
+        let mut blocks = [(0, BLACK_WHITE); 4];
+        blocks[0].1 = 0xFFFF_FC00;
+        let pixels = decode_pvrtc(&surface(&blocks), 8, 8, false).unwrap();
+
+        // Pixels at a block center only use that block, halfway between the centers the blocks are averaged,
+        // wrapping around at the edges
+        assert_eq!(pixels[2 * 8 + 2], [255, 0, 0, 255]);
+        assert_eq!(pixels[2 * 8 + 6], [0, 0, 0, 255]);
+        assert_eq!(pixels[2 * 8 + 4], [127, 0, 0, 255]);
+        assert_eq!(pixels[2 * 8], [127, 0, 0, 255]);
+    }
+
+    #[test]
+    fn direct_2bpp() {
+        // Without the mode bit every pixel has a single bit, the left half of every block is set
+        let data = surface(&[(0x0F0F_0F0F, BLACK_WHITE); 4]);
+        let pixels = decode_pvrtc(&data, 16, 8, true).unwrap();
+        for (i, pixel) in pixels.iter().enumerate() {
+            let expected = if i % 8 < 4 { 255 } else { 0 };
+            assert_eq!(*pixel, gray(expected), "pixel {i}");
+        }
+    }
+
+    /// Decodes 2bpp blocks in the interpolated mode, where the stored values are 3 on even rows and 0 on odd rows.
+    ///
+    /// Returns the pixels that aren't stored on the first two rows.
+    fn interpolated_2bpp(modulation: u32) -> ([u8; 4], [u8; 4]) {
+        let data = surface(&[(modulation, BLACK_WHITE_MODE); 4]);
+        let pixels = decode_pvrtc(&data, 16, 8, true).unwrap();
+        for y in 0..8 {
+            for x in (y % 2..16).step_by(2) {
+                let expected = if y % 2 == 0 { 255 } else { 0 };
+                assert_eq!(pixels[y * 16 + x], gray(expected), "stored pixel {x}, {y}");
+            }
+        }
+
+        (pixels[1], pixels[16])
+    }
+
+    #[test]
+    fn interpolated_2bpp_modes() {
+        // The low bit of the first value selects averaging all 4 neighbours, its high bit is used as the value
+        assert_eq!(interpolated_2bpp(0x00FF_00FE), (gray(127), gray(127)));
+        // Otherwise the low bit of the value at bit 20 selects vertical over horizontal averaging
+        assert_eq!(interpolated_2bpp(0x00EF_00FF), (gray(255), gray(0)));
+        assert_eq!(interpolated_2bpp(0x00FF_00FF), (gray(0), gray(255)));
+    }
+}
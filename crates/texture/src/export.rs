@@ -0,0 +1,320 @@
+//! Writing decoded textures to PNG, TGA, OpenEXR and Radiance HDR files.
+//!
+//! LDR formats store 8 bit colors, either sRGB encoded or linear. Float formats always store linear colors, so sRGB
+//! images are linearized when written to them and float images are tonemapped and sRGB encoded when written to LDR
+//! formats.
+
+use std::{
+    io::{Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use image::{ExtendedColorType, ImageEncoder};
+
+use crate::{
+    channels::{Channel, NormalMapOptions},
+    cubemap::Unfold,
+    decode::{DecodedImage, Pixels},
+    format::ColorSpace,
+    structs::{TextureHeader, TextureType},
+    tonemap::{srgb_to_linear, Tonemap},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Tga,
+    Exr,
+    /// Radiance RGBE, has no alpha channel
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Guesses the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "tga" => Some(Self::Tga),
+            "exr" => Some(Self::Exr),
+            "hdr" => Some(Self::Hdr),
+            _ => None,
+        }
+    }
+
+    /// PNG for LDR images and OpenEXR for float images
+    pub fn preferred(image: &DecodedImage) -> Self {
+        if image.is_hdr() {
+            Self::Exr
+        } else {
+            Self::Png
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Tga => "tga",
+            Self::Exr => "exr",
+            Self::Hdr => "hdr",
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Self::Exr | Self::Hdr)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    /// Write the alpha channel, if the format supports one
+    pub alpha: bool,
+    /// Whether the 8 bit colors of the image are sRGB encoded. Ignored for float images, those are always linear
+    pub srgb: bool,
+    /// How float images are mapped to 8 bit colors for LDR formats
+    pub tonemap: Tonemap,
+    /// Treat the image as a normal map, see [`crate::channels::process_normal_map`]
+    pub normal_map: Option<NormalMapOptions>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            alpha: true,
+            srgb: true,
+            tonemap: Tonemap::default(),
+            normal_map: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Options matching the pixel format of a texture. Z is reconstructed for normal maps that only store X and Y
+    pub fn from_header(header: &TextureHeader) -> Self {
+        let normal_map = NormalMapOptions::from_header(header);
+        Self {
+            alpha: header.format.has_alpha() && normal_map.is_none(),
+            srgb: ColorSpace::from_header(header).is_srgb(),
+            tonemap: Tonemap::default(),
+            normal_map,
+        }
+    }
+}
+
+/// Writes a decoded image in the given format
+pub fn write_image<W: Write + Seek>(
+    image: &DecodedImage,
+    format: ImageFormat,
+    options: &ExportOptions,
+    writer: W,
+) -> anyhow::Result<()> {
+    let normal_map = options
+        .normal_map
+        .map(|normal_map| crate::channels::process_normal_map(image, &normal_map));
+    let image = normal_map.as_ref().unwrap_or(image);
+    let (width, height) = (image.width, image.height);
+    let channels = if options.alpha && format != ImageFormat::Hdr {
+        4
+    } else {
+        3
+    };
+
+    match format {
+        ImageFormat::Png | ImageFormat::Tga => {
+            let pixels = select_channels(&to_ldr(image, &options.tonemap), channels);
+            // Float images are sRGB encoded by the tonemapping
+            let srgb = options.srgb || image.is_hdr();
+
+            if format == ImageFormat::Png {
+                let mut encoder = png::Encoder::new(writer, width, height);
+                encoder.set_color(if channels == 4 {
+                    png::ColorType::Rgba
+                } else {
+                    png::ColorType::Rgb
+                });
+                encoder.set_depth(png::BitDepth::Eight);
+                if srgb {
+                    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+                } else {
+                    encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+                }
+
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&pixels)?;
+                writer.finish()?;
+            } else {
+                let color_type = if channels == 4 {
+                    ExtendedColorType::Rgba8
+                } else {
+                    ExtendedColorType::Rgb8
+                };
+                image::codecs::tga::TgaEncoder::new(writer)
+                    .write_image(&pixels, width, height, color_type)?;
+            }
+        }
+        ImageFormat::Exr | ImageFormat::Hdr => {
+            let pixels = select_channels(&to_linear(image, options.srgb), channels);
+            let color_type = if channels == 4 {
+                ExtendedColorType::Rgba32F
+            } else {
+                ExtendedColorType::Rgb32F
+            };
+            let bytes: &[u8] = bytemuck::cast_slice(&pixels);
+
+            if format == ImageFormat::Exr {
+                image::codecs::openexr::OpenExrEncoder::new(writer)
+                    .write_image(bytes, width, height, color_type)?;
+            } else {
+                image::codecs::hdr::HdrEncoder::new(writer)
+                    .write_image(bytes, width, height, color_type)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a decoded image to a file, the format is picked from its extension
+pub fn write_to_file(
+    image: &DecodedImage,
+    path: &Path,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let format = ImageFormat::from_path(path).with_context(|| {
+        format!(
+            "Unknown image format for {}, expected .png, .tga, .exr or .hdr",
+            path.display()
+        )
+    })?;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_image(image, format, options, file)
+}
+
+/// Decodes a Messiah texture file on the CPU and writes it to a file, with options matching its pixel format
+pub fn export_texture(data: &[u8], path: &Path) -> anyhow::Result<()> {
+    let header = crate::read_header(data)?;
+    let image = crate::decode(data)?;
+
+    write_to_file(&image, path, &ExportOptions::from_header(&header))
+}
+
+/// Decodes every mip level of a Messiah texture file on the CPU and writes them next to `path`, as `<name>_mip<level>`
+///
+/// Returns the paths of the written files, largest mip first.
+pub fn export_mips(data: &[u8], path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let header = crate::read_header(data)?;
+    let options = ExportOptions::from_header(&header);
+
+    let mut paths = vec![];
+    for (level, image) in crate::decode_mips(data)?.iter().enumerate() {
+        let path = suffixed_path(path, &format!("mip{level}"))?;
+        write_to_file(image, &path, &options)?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Decodes a Messiah texture file on the CPU and writes each of its channels as a grayscale image next to `path`, as
+/// `<name>_<channel>`, to split up mix maps that pack several masks together.
+///
+/// Returns the paths of the written files, in RGBA order.
+pub fn export_channels(data: &[u8], path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let header = crate::read_header(data)?;
+    let options = ExportOptions {
+        alpha: false,
+        normal_map: None,
+        ..ExportOptions::from_header(&header)
+    };
+    let image = crate::decode(data)?;
+
+    let channels = if header.format.has_alpha() {
+        &Channel::RGBA[..]
+    } else {
+        &Channel::RGBA[..3]
+    };
+
+    let mut paths = vec![];
+    for channel in channels {
+        let path = suffixed_path(path, channel.name())?;
+        write_to_file(&crate::channels::extract(&image, *channel), &path, &options)?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// `<name>_<suffix>.<extension>` in the directory of `path`
+fn suffixed_path(path: &Path, suffix: &str) -> anyhow::Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .context("Missing file name")?
+        .to_string_lossy()
+        .into_owned();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(path.with_file_name(format!("{stem}_{suffix}.{extension}")))
+}
+
+/// Decodes the full size faces of a Messiah cubemap on the CPU and writes them unfolded to a file.
+///
+/// Only the first cube of cube arrays is written.
+pub fn export_cubemap(data: &[u8], path: &Path, unfold: Unfold) -> anyhow::Result<()> {
+    let header = crate::read_header(data)?;
+    anyhow::ensure!(
+        matches!(
+            header.texture_type,
+            TextureType::Cube | TextureType::CubeArray
+        ),
+        "Texture is a {:?}, not a cubemap",
+        header.texture_type
+    );
+
+    let image = crate::cubemap::unfold(&crate::decode_layers(data, 0)?, unfold)?;
+    write_to_file(&image, path, &ExportOptions::from_header(&header))
+}
+
+/// RGBA8 pixels, float images are tonemapped and sRGB encoded
+fn to_ldr(image: &DecodedImage, tonemap: &Tonemap) -> Vec<u8> {
+    match &image.pixels {
+        Pixels::Rgba8(pixels) => pixels.clone(),
+        Pixels::Rgba32F(_) => crate::tonemap::tonemap(image, tonemap).to_rgba8(),
+    }
+}
+
+/// Linear RGBA32F pixels, 8 bit colors are linearized if they are sRGB encoded. Alpha is always linear
+fn to_linear(image: &DecodedImage, srgb: bool) -> Vec<f32> {
+    match &image.pixels {
+        Pixels::Rgba8(pixels) => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let color = |v: u8| {
+                    let v = v as f32 / 255.0;
+                    if srgb {
+                        srgb_to_linear(v)
+                    } else {
+                        v
+                    }
+                };
+                [color(p[0]), color(p[1]), color(p[2]), p[3] as f32 / 255.0]
+            })
+            .collect(),
+        Pixels::Rgba32F(pixels) => pixels.clone(),
+    }
+}
+
+/// Drops the alpha channel of RGBA pixels if `channels` is 3
+fn select_channels<T: Copy>(pixels: &[T], channels: usize) -> Vec<T> {
+    if channels == 4 {
+        return pixels.to_vec();
+    }
+
+    pixels
+        .chunks_exact(4)
+        .flat_map(|p| p[..channels].iter().copied())
+        .collect()
+}
@@ -1,8 +1,10 @@
 use binrw::binread;
 
+use crate::structs::TextureHeader;
+
 #[binread]
 #[br(repr(u8))]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PixelFormat {
     Unknown = 0,
     A32R32G32B32F = 1,
@@ -75,41 +77,72 @@ pub enum PixelFormat {
     R32g32b32a32ui = 69,
 }
 
+/// Whether the 8 bit colors of a texture are sRGB encoded or linear
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// The color space a texture was authored in, picked from its compression preset and LOD group.
+    ///
+    /// Normal maps, masks, LUTs and other data textures are linear, as are formats without sRGB encoded variants.
+    pub fn from_header(header: &TextureHeader) -> Self {
+        if header.format.supports_srgb()
+            && !header.compression_preset.is_data()
+            && !header.lod_group.is_data()
+        {
+            Self::Srgb
+        } else {
+            Self::Linear
+        }
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        *self == Self::Srgb
+    }
+}
+
 impl PixelFormat {
+    /// The matching WGPU format, always the linear variant. Use `add_srgb_suffix` for sRGB textures
+    #[cfg(feature = "gpu")]
     pub fn to_wgpu(&self) -> Option<wgpu::TextureFormat> {
         Some(match self {
             PixelFormat::Unknown => return None,
-            PixelFormat::A32R32G32B32F => return None,
-            // PixelFormat::A16B16G16R16F => return None,
+            PixelFormat::A32R32G32B32F => wgpu::TextureFormat::Rgba32Float,
+            PixelFormat::A16B16G16R16F => wgpu::TextureFormat::Rgba16Float,
             PixelFormat::R8G8B8A8 => wgpu::TextureFormat::Rgba8Unorm,
+            // Luminance, alpha and 565 formats have no (unswizzled) counterpart, those are expanded on the CPU
             PixelFormat::B5G6R5 => return None,
             PixelFormat::A8L8 => return None,
-            PixelFormat::G16R16 => return None,
-            PixelFormat::G16R16F => return None,
-            PixelFormat::G32R32F => return None,
+            PixelFormat::G16R16 => wgpu::TextureFormat::Rg16Unorm,
+            PixelFormat::G16R16F => wgpu::TextureFormat::Rg16Float,
+            PixelFormat::G32R32F => wgpu::TextureFormat::Rg32Float,
             // PixelFormat::R32F => wgpu::TextureFormat::R32Float,
             PixelFormat::R16F => wgpu::TextureFormat::R16Float,
-            PixelFormat::L8 => wgpu::TextureFormat::R8Unorm,
-            PixelFormat::L16 => wgpu::TextureFormat::R16Unorm,
-            PixelFormat::A8 => wgpu::TextureFormat::R8Unorm,
+            PixelFormat::L8 => return None,
+            PixelFormat::L16 => return None,
+            PixelFormat::A8 => return None,
+            // There are no 3 channel formats, decoded on the CPU instead
             PixelFormat::FloatRGB => return None,
             PixelFormat::FloatRGBA => wgpu::TextureFormat::Rgba32Float,
             PixelFormat::D24 => wgpu::TextureFormat::Depth24Plus,
             PixelFormat::D32 => wgpu::TextureFormat::Depth32Float,
-            PixelFormat::Bc1 => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
-            PixelFormat::Bc2 => wgpu::TextureFormat::Bc2RgbaUnormSrgb,
-            PixelFormat::Bc3 => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+            PixelFormat::Bc1 => wgpu::TextureFormat::Bc1RgbaUnorm,
+            PixelFormat::Bc2 => wgpu::TextureFormat::Bc2RgbaUnorm,
+            PixelFormat::Bc3 => wgpu::TextureFormat::Bc3RgbaUnorm,
             PixelFormat::Bc4 => wgpu::TextureFormat::Bc4RUnorm,
             PixelFormat::Bc5 => wgpu::TextureFormat::Bc5RgUnorm,
             PixelFormat::Bc6S => wgpu::TextureFormat::Bc6hRgbFloat,
             PixelFormat::Bc6U => wgpu::TextureFormat::Bc6hRgbUfloat,
-            PixelFormat::Bc7 => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+            PixelFormat::Bc7 => wgpu::TextureFormat::Bc7RgbaUnorm,
             PixelFormat::Pvrtc2Rgb => return None,
             PixelFormat::Pvrtc2Rgba => return None,
             PixelFormat::Pvrtc4Rgb => return None,
             PixelFormat::Etc1 => return None,
-            PixelFormat::Etc2Rgb => wgpu::TextureFormat::Etc2Rgb8UnormSrgb,
-            PixelFormat::Etc2Rgba => wgpu::TextureFormat::Etc2Rgb8A1UnormSrgb,
+            PixelFormat::Etc2Rgb => wgpu::TextureFormat::Etc2Rgb8Unorm,
+            PixelFormat::Etc2Rgba => wgpu::TextureFormat::Etc2Rgb8A1Unorm,
             // PixelFormat::AtcRgb => return None,
             PixelFormat::AtcRgbaE => return None,
             PixelFormat::AtcRgbaI => return None,
@@ -231,7 +264,6 @@ impl PixelFormat {
                 block: wgpu::AstcBlock::B12x12,
                 channel: wgpu::AstcChannel::Hdr,
             },
-            PixelFormat::A16B16G16R16F => return None,
             PixelFormat::Pvrtc4Rgba => return None,
             PixelFormat::R11g11b10 => wgpu::TextureFormat::Rg11b10Ufloat,
             PixelFormat::R32g32b32a32ui => wgpu::TextureFormat::Rgba32Uint,
@@ -239,26 +271,80 @@ impl PixelFormat {
         })
     }
 
-    pub fn astc_footprint(&self) -> Option<astc_decode::Footprint> {
+    /// Block width, height and size in bytes. Uncompressed formats have 1x1 blocks
+    ///
+    /// `Etc2Rgba` is reported with its EAC alpha block, punchthrough alpha textures use half the size
+    pub fn block_size(&self) -> Option<(u32, u32, u32)> {
         Some(match self {
-            PixelFormat::Astc4x4Ldr => astc_decode::Footprint::ASTC_4X4,
-            PixelFormat::Astc5x4Ldr => astc_decode::Footprint::ASTC_5X4,
-            PixelFormat::Astc5x5Ldr => astc_decode::Footprint::ASTC_5X5,
-            PixelFormat::Astc6x5Ldr => astc_decode::Footprint::ASTC_6X5,
-            PixelFormat::Astc6x6Ldr => astc_decode::Footprint::ASTC_6X6,
-            PixelFormat::Astc8x5Ldr => astc_decode::Footprint::ASTC_8X5,
-            PixelFormat::Astc8x6Ldr => astc_decode::Footprint::ASTC_8X6,
-            PixelFormat::Astc8x8Ldr => astc_decode::Footprint::ASTC_8X8,
-            PixelFormat::Astc10x5Ldr => astc_decode::Footprint::ASTC_10X5,
-            PixelFormat::Astc10x6Ldr => astc_decode::Footprint::ASTC_10X6,
-            PixelFormat::Astc10x8Ldr => astc_decode::Footprint::ASTC_10X8,
-            PixelFormat::Astc10x10Ldr => astc_decode::Footprint::ASTC_10X10,
-            PixelFormat::Astc12x10Ldr => astc_decode::Footprint::ASTC_12X10,
-            PixelFormat::Astc12x12Ldr => astc_decode::Footprint::ASTC_12X12,
-            _ => return None,
+            Self::A32R32G32B32F | Self::FloatRGBA | Self::R32g32b32a32ui => (1, 1, 16),
+            Self::FloatRGB => (1, 1, 12),
+            Self::A16B16G16R16F | Self::G32R32F => (1, 1, 8),
+            Self::R8G8B8A8
+            | Self::G16R16
+            | Self::G16R16F
+            | Self::D24
+            | Self::D32
+            | Self::Shadowdepth
+            | Self::Shadowdepth32
+            | Self::R10g10b10a2
+            | Self::R32u
+            | Self::R11g11b10 => (1, 1, 4),
+            Self::B5G6R5 | Self::A8L8 | Self::R16F | Self::L16 => (1, 1, 2),
+            Self::L8 | Self::A8 => (1, 1, 1),
+            Self::Bc1 | Self::Bc4 | Self::Etc1 | Self::Etc2Rgb => (4, 4, 8),
+            Self::Bc2
+            | Self::Bc3
+            | Self::Bc5
+            | Self::Bc6S
+            | Self::Bc6U
+            | Self::Bc7
+            | Self::Etc2Rgba
+            | Self::AtcRgbaE
+            | Self::AtcRgbaI => (4, 4, 16),
+            Self::Pvrtc2Rgb | Self::Pvrtc2Rgba => (8, 4, 8),
+            Self::Pvrtc4Rgb | Self::Pvrtc4Rgba => (4, 4, 8),
+            Self::Astc4x4Ldr | Self::Astc4x4Hdr => (4, 4, 16),
+            Self::Astc5x4Ldr | Self::Astc5x4Hdr => (5, 4, 16),
+            Self::Astc5x5Ldr | Self::Astc5x5Hdr => (5, 5, 16),
+            Self::Astc6x5Ldr | Self::Astc6x5Hdr => (6, 5, 16),
+            Self::Astc6x6Ldr | Self::Astc6x6Hdr => (6, 6, 16),
+            Self::Astc8x5Ldr | Self::Astc8x5Hdr => (8, 5, 16),
+            Self::Astc8x6Ldr | Self::Astc8x6Hdr => (8, 6, 16),
+            Self::Astc8x8Ldr | Self::Astc8x8Hdr => (8, 8, 16),
+            Self::Astc10x5Ldr | Self::Astc10x5Hdr => (10, 5, 16),
+            Self::Astc10x6Ldr | Self::Astc10x6Hdr => (10, 6, 16),
+            Self::Astc10x8Ldr | Self::Astc10x8Hdr => (10, 8, 16),
+            Self::Astc10x10Ldr | Self::Astc10x10Hdr => (10, 10, 16),
+            Self::Astc12x10Ldr | Self::Astc12x10Hdr => (12, 10, 16),
+            Self::Astc12x12Ldr | Self::Astc12x12Hdr => (12, 12, 16),
+            Self::Unknown => return None,
         })
     }
 
+    /// Size in bytes of a single `width`x`height` surface
+    pub fn surface_size(&self, width: u32, height: u32) -> Option<usize> {
+        let (block_width, block_height, block_bytes) = self.block_size()?;
+        let (mut blocks_x, mut blocks_y) =
+            (width.div_ceil(block_width), height.div_ceil(block_height));
+        if self.is_pvrtc() {
+            // PVRTC surfaces are at least 2x2 blocks
+            (blocks_x, blocks_y) = (blocks_x.max(2), blocks_y.max(2));
+        }
+
+        Some(blocks_x as usize * blocks_y as usize * block_bytes as usize)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.block_size().is_some_and(|(w, h, _)| w > 1 || h > 1)
+    }
+
+    pub fn is_pvrtc(&self) -> bool {
+        matches!(
+            self,
+            Self::Pvrtc2Rgb | Self::Pvrtc2Rgba | Self::Pvrtc4Rgb | Self::Pvrtc4Rgba
+        )
+    }
+
     pub fn is_astc(&self) -> bool {
         matches!(
             self,
@@ -293,6 +379,54 @@ impl PixelFormat {
         )
     }
 
+    /// Whether the format stores an alpha channel. BC1 is included for its punchthrough alpha
+    pub fn has_alpha(&self) -> bool {
+        matches!(
+            self,
+            Self::A32R32G32B32F
+                | Self::A16B16G16R16F
+                | Self::R8G8B8A8
+                | Self::A8L8
+                | Self::A8
+                | Self::FloatRGBA
+                | Self::Bc1
+                | Self::Bc2
+                | Self::Bc3
+                | Self::Bc7
+                | Self::Pvrtc2Rgba
+                | Self::Pvrtc4Rgba
+                | Self::Etc2Rgba
+                | Self::AtcRgbaE
+                | Self::AtcRgbaI
+                | Self::R10g10b10a2
+                | Self::R32g32b32a32ui
+        ) || self.is_astc()
+    }
+
+    /// Whether the format stores 8 bit colors that may be sRGB encoded, depending on the [`ColorSpace`] of the texture
+    pub fn supports_srgb(&self) -> bool {
+        matches!(
+            self,
+            Self::R8G8B8A8
+                | Self::B5G6R5
+                | Self::A8L8
+                | Self::L8
+                | Self::Bc1
+                | Self::Bc2
+                | Self::Bc3
+                | Self::Bc7
+                | Self::Pvrtc2Rgb
+                | Self::Pvrtc2Rgba
+                | Self::Pvrtc4Rgb
+                | Self::Pvrtc4Rgba
+                | Self::Etc1
+                | Self::Etc2Rgb
+                | Self::Etc2Rgba
+                | Self::AtcRgbaE
+                | Self::AtcRgbaI
+        ) || (self.is_astc() && !self.is_hdr())
+    }
+
     pub fn is_hdr(&self) -> bool {
         matches!(
             self,
@@ -312,4 +446,21 @@ impl PixelFormat {
                 | Self::Astc12x12Hdr
         )
     }
+
+    /// Whether the format decodes to float colors, which may lie outside of the 0-1 range
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            Self::A32R32G32B32F
+                | Self::A16B16G16R16F
+                | Self::G16R16F
+                | Self::G32R32F
+                | Self::R16F
+                | Self::FloatRGB
+                | Self::FloatRGBA
+                | Self::Bc6S
+                | Self::Bc6U
+                | Self::R11g11b10
+        ) || self.is_hdr()
+    }
 }
@@ -0,0 +1,230 @@
+//! How the layers, cube faces and depth slices of a texture are stored in its mip levels.
+//!
+//! Every mip level stores all of its surfaces back to back, array layers first and cube faces second. 3D textures
+//! store their depth slices instead, and those halve with every level like the width and height.
+
+use anyhow::{ensure, Context};
+use log::warn;
+
+use crate::{
+    decode::{self, DecodedImage},
+    format::{ColorSpace, PixelFormat},
+    structs::TextureType,
+    MipLevel,
+};
+
+/// Whether the first level of an `Etc2Rgba` texture holds half size surfaces without EAC alpha blocks.
+///
+/// Plain and cube textures have a known number of surfaces. The layer count of arrays and the depth of 3D textures
+/// isn't stored, so those only count as punchthrough when the data isn't a whole number of full size surfaces (or
+/// cubes).
+fn is_punchthrough(texture_type: TextureType, len: usize, surface_size: usize) -> bool {
+    match texture_type {
+        TextureType::Texture1D | TextureType::Texture2D => len < surface_size,
+        TextureType::Cube => len < surface_size * 6,
+        TextureType::CubeArray => !len.is_multiple_of(surface_size * 6),
+        TextureType::Texture3D | TextureType::Texture2DArray | TextureType::Array => {
+            !len.is_multiple_of(surface_size)
+        }
+    }
+}
+
+/// The surfaces of a texture file and how they are arranged
+pub struct Layout {
+    pub format: PixelFormat,
+    /// Picked with [`ColorSpace::from_header`], may be changed before writing a container
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    /// Depth of 3D textures, 1 otherwise
+    pub depth: u32,
+    /// Array layers, or cubes of cube arrays. 1 for textures that are no array
+    pub layers: u32,
+    /// 6 for cubemaps, 1 otherwise
+    pub faces: u32,
+    /// `Etc2Rgba` textures without EAC alpha blocks
+    pub punchthrough: bool,
+    pub is_array: bool,
+    /// Largest first
+    pub levels: Vec<MipLevel>,
+}
+
+impl Layout {
+    /// Reads the layout and every mip level of a texture file
+    pub fn read(data: &[u8]) -> anyhow::Result<Self> {
+        let header = crate::read_header(data)?;
+        let levels = crate::read_levels(data, &header)?;
+        let first = levels.first().context("Texture has no mips?")?;
+        let format = header.format;
+        ensure!(
+            format.block_size().is_some(),
+            "Unknown size of pixel format {format:?}"
+        );
+
+        let punchthrough = format == PixelFormat::Etc2Rgba
+            && is_punchthrough(
+                header.texture_type,
+                first.data.len(),
+                format
+                    .surface_size(first.width, first.height)
+                    .unwrap()
+                    .max(1),
+            );
+        let mut layout = Self {
+            format,
+            srgb: ColorSpace::from_header(&header).is_srgb(),
+            width: first.width,
+            height: first.height,
+            depth: 1,
+            layers: 1,
+            faces: 1,
+            punchthrough,
+            is_array: false,
+            levels: vec![],
+        };
+
+        let surfaces = first.data.len() / layout.surface_size(first.width, first.height).max(1);
+        match header.texture_type {
+            TextureType::Texture3D => layout.depth = surfaces.max(1) as u32,
+            TextureType::Cube => layout.faces = 6,
+            TextureType::CubeArray => {
+                layout.faces = 6;
+                layout.layers = (surfaces / 6).max(1) as u32;
+                layout.is_array = true;
+            }
+            TextureType::Texture2DArray | TextureType::Array => {
+                layout.layers = surfaces.max(1) as u32;
+                layout.is_array = true;
+            }
+            TextureType::Texture1D | TextureType::Texture2D => {}
+        }
+
+        // Smaller mips are optional, as long as the full size one is complete
+        for (i, level) in levels.into_iter().enumerate() {
+            let expected = layout.surface_size(level.width, level.height) * layout.surfaces(i);
+            if level.data.len() < expected {
+                ensure!(
+                    i > 0,
+                    "Texture is too small for {} surfaces, expected {expected} bytes but got {}",
+                    layout.surfaces(i),
+                    level.data.len()
+                );
+
+                warn!("Mip level {i} is too small, skipping the remaining levels");
+                break;
+            }
+
+            layout.levels.push(level);
+        }
+
+        Ok(layout)
+    }
+
+    /// Size in bytes of a single surface
+    pub fn surface_size(&self, width: u32, height: u32) -> usize {
+        let size = self.format.surface_size(width, height).unwrap_or(0);
+        if self.punchthrough {
+            size / 2
+        } else {
+            size
+        }
+    }
+
+    /// Block width, height and size in bytes
+    pub fn block_size(&self) -> (u32, u32, u32) {
+        let (width, height, bytes) = self.format.block_size().unwrap_or((1, 1, 1));
+        (
+            width,
+            height,
+            if self.punchthrough { bytes / 2 } else { bytes },
+        )
+    }
+
+    /// Number of surfaces (layers, faces or depth slices) stored in the given level
+    pub fn surfaces(&self, level: usize) -> usize {
+        if self.depth > 1 {
+            (self.depth >> level).max(1) as usize
+        } else {
+            (self.layers * self.faces) as usize
+        }
+    }
+
+    /// A single layer, face or depth slice of a mip level
+    pub fn surface(&self, level: usize, index: usize) -> &[u8] {
+        let size = self.surface_size(self.levels[level].width, self.levels[level].height);
+        &self.levels[level].data[index * size..(index + 1) * size]
+    }
+
+    /// Decodes a single layer, face or depth slice of a mip level on the CPU
+    pub fn decode(&self, level: usize, index: usize) -> anyhow::Result<DecodedImage> {
+        let mip = self
+            .levels
+            .get(level)
+            .with_context(|| format!("Texture has no mip level {level}"))?;
+        ensure!(
+            index < self.surfaces(level),
+            "Mip level {level} has no surface {index}"
+        );
+
+        decode::decode_surface(
+            self.format,
+            mip.width,
+            mip.height,
+            self.surface(level, index),
+        )
+    }
+
+    /// All surfaces of a mip level
+    pub fn level_data(&self, level: usize) -> &[u8] {
+        let size = self.surface_size(self.levels[level].width, self.levels[level].height);
+        &self.levels[level].data[..size * self.surfaces(level)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::texture_file;
+
+    /// Reads an 8x8 `Etc2Rgba` texture with a single level of `blocks` 4x4 blocks of `block_size` bytes
+    fn etc2_layout(texture_type: TextureType, blocks: usize, block_size: usize) -> Layout {
+        let level = vec![0; blocks * block_size];
+        Layout::read(&texture_file(
+            PixelFormat::Etc2Rgba,
+            texture_type,
+            (8, 8),
+            &[level],
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn punchthrough_is_detected_per_surface() {
+        let cube = etc2_layout(TextureType::Cube, 6 * 4, 8);
+        assert!(cube.punchthrough);
+        assert_eq!((cube.faces, cube.layers), (6, 1));
+        assert_eq!(cube.surface(0, 5).len(), 32);
+
+        let cube = etc2_layout(TextureType::Cube, 6 * 4, 16);
+        assert!(!cube.punchthrough);
+        assert_eq!(cube.surface(0, 5).len(), 64);
+
+        let array = etc2_layout(TextureType::Texture2DArray, 3 * 4, 8);
+        assert!(array.punchthrough);
+        assert_eq!(array.layers, 3);
+
+        let array = etc2_layout(TextureType::Texture2DArray, 3 * 4, 16);
+        assert!(!array.punchthrough);
+        assert_eq!(array.layers, 3);
+
+        let cube_array = etc2_layout(TextureType::CubeArray, 6 * 4, 8);
+        assert!(cube_array.punchthrough);
+        assert_eq!((cube_array.faces, cube_array.layers), (6, 1));
+    }
+
+    #[test]
+    fn plain_textures_are_punchthrough_when_half_size() {
+        assert!(etc2_layout(TextureType::Texture2D, 4, 8).punchthrough);
+        assert!(!etc2_layout(TextureType::Texture2D, 4, 16).punchthrough);
+    }
+}
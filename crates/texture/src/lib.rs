@@ -1,81 +1,266 @@
-use std::{borrow::Cow, io::Cursor};
+use std::io::Cursor;
 
 use anyhow::Context;
 use binrw::BinReaderExt;
-use log::debug;
+#[cfg(feature = "gpu")]
 use wgpu::util::DeviceExt;
 
-use crate::structs::TextureHeader;
+use crate::{
+    decode::DecodedImage,
+    structs::{MipHeader, TextureHeader},
+};
 
+pub mod channels;
+pub mod container;
+#[cfg(feature = "gpu")]
 pub mod converter;
+pub mod cubemap;
+pub mod decode;
+pub mod export;
 pub mod format;
+pub mod layout;
 pub mod structs;
+#[cfg(test)]
+mod testing;
+pub mod tonemap;
 
-/// Represents a Messiah texture loaded onto the GPU
+/// Parses the header of a Messiah texture file
+pub fn read_header(data: &[u8]) -> anyhow::Result<TextureHeader> {
+    let mut c = Cursor::new(data);
+    let _unk0: u32 = c.read_le()?;
+
+    Ok(c.read_le()?)
+}
+
+/// Returns the decompressed data of a mip level of a texture file
+pub fn read_mip(data: &[u8], mip: &MipHeader) -> anyhow::Result<Vec<u8>> {
+    let start = mip.data_offset.pos as usize;
+    let end = (mip.data_end.pos as usize).min(data.len());
+    let mut mip_data = data
+        .get(start..end)
+        .context("Mip data out of bounds")?
+        .to_vec();
+
+    Ok(gwynn_mpk::compression::decompress(&mut mip_data)?.into_owned())
+}
+
+/// A decompressed mip level, holding every array layer, cube face or depth slice of the texture
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Sizes of the mip levels of a texture, largest first
+pub fn level_sizes(header: &TextureHeader) -> Vec<(u32, u32)> {
+    let mut sizes: Vec<(u32, u32)> = vec![];
+    for mip in &header.mips {
+        let size = (mip.width as u32, mip.height as u32);
+        if !sizes.contains(&size) {
+            sizes.push(size);
+        }
+    }
+
+    sizes.sort_by_key(|(width, height)| std::cmp::Reverse(*width as u64 * *height as u64));
+    sizes
+}
+
+/// Reads and decompresses every mip level of a texture file, largest first.
+///
+/// Mip headers of the same size are treated as layers of the same level, in the order they are stored in.
+pub fn read_levels(data: &[u8], header: &TextureHeader) -> anyhow::Result<Vec<MipLevel>> {
+    let mut levels: Vec<MipLevel> = level_sizes(header)
+        .into_iter()
+        .map(|(width, height)| MipLevel {
+            width,
+            height,
+            data: vec![],
+        })
+        .collect();
+
+    for mip in &header.mips {
+        let level = levels
+            .iter_mut()
+            .find(|l| (l.width, l.height) == (mip.width as u32, mip.height as u32))
+            .unwrap();
+        level.data.extend_from_slice(&read_mip(data, mip)?);
+    }
+
+    Ok(levels)
+}
+
+/// Decodes the full size mip of a Messiah texture file on the CPU
+pub fn decode(data: &[u8]) -> anyhow::Result<DecodedImage> {
+    decode_mip(data, 0)
+}
+
+/// Decodes a single mip level of a Messiah texture file on the CPU, 0 being the full size one.
+///
+/// Only the first layer, cube face or depth slice of the level is decoded.
+pub fn decode_mip(data: &[u8], level: usize) -> anyhow::Result<DecodedImage> {
+    layout::Layout::read(data)?.decode(level, 0)
+}
+
+/// Decodes every layer, cube face or depth slice of a mip level on the CPU
+pub fn decode_layers(data: &[u8], level: usize) -> anyhow::Result<Vec<DecodedImage>> {
+    let layout = layout::Layout::read(data)?;
+    (0..layout.surfaces(level))
+        .map(|i| layout.decode(level, i))
+        .collect()
+}
+
+/// Decodes the first surface of every mip level of a Messiah texture file on the CPU, largest first
+pub fn decode_mips(data: &[u8]) -> anyhow::Result<Vec<DecodedImage>> {
+    let layout = layout::Layout::read(data)?;
+    (0..layout.levels.len())
+        .map(|level| layout.decode(level, 0))
+        .collect()
+}
+
+/// Represents a Messiah texture loaded onto the GPU.
+///
+/// Array layers and cube faces are stored as the layers of a 2D array texture. 3D textures are uploaded as an array
+/// of their depth slices, without mips.
+#[cfg(feature = "gpu")]
 pub struct Texture {
     pub texture: wgpu::Texture,
+    /// View of the first layer or face
     pub view: wgpu::TextureView,
     pub header: TextureHeader,
+    pub color_space: format::ColorSpace,
 }
 
+#[cfg(feature = "gpu")]
 impl Texture {
-    /// Load a texture from raw Messiah texture file data
+    /// Load a texture from raw Messiah texture file data, with all of its mip levels and layers
     pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) -> anyhow::Result<Self> {
-        let mut c = Cursor::new(&data);
-        let _unk0: u32 = c.read_le()?;
-
-        let header: TextureHeader = c.read_le()?;
-        let mip = header.mips.last().context("Texture has no mips?")?;
-
-        let mut data = data[mip.data_offset.pos as usize..].to_vec();
-        let texture_data = gwynn_mpk::compression::decompress(&mut data)?;
-        let texture_data = if header.format.is_astc()
-            && !device
-                .features()
-                .contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC)
-        {
-            debug!("Using ASTC software decoder");
-            let mut image = vec![0u8; header.width as usize * header.height as usize * 4];
-            astc_decode::astc_decode(
-                Cursor::new(&data),
-                header.width as _,
-                header.height as _,
-                header.format.astc_footprint().unwrap(),
-                |x, y, pixel| {
-                    let offset = (y * header.width as u32 + x) as usize * 4;
-                    image[offset..offset + 4].copy_from_slice(&pixel);
-                },
-            )?;
-
-            Cow::Owned(image)
-        } else {
-            texture_data
-        };
+        Self::load_with_color_space(device, queue, data, None)
+    }
 
-        let format = header.format.to_wgpu().with_context(|| {
-            format!(
-                "No suitable WGPU format found for format {:?}",
-                header.format
-            )
-        })?;
+    /// Like [`Texture::load`], but `color_space` overrides the one picked from the texture header
+    pub fn load_with_color_space(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        color_space: Option<format::ColorSpace>,
+    ) -> anyhow::Result<Self> {
+        let header = read_header(data)?;
+        let layout = layout::Layout::read(data)?;
+        let color_space = color_space.unwrap_or_else(|| format::ColorSpace::from_header(&header));
 
+        let native_format =
+            if header.format == format::PixelFormat::Etc2Rgba && !layout.punchthrough {
+                Some(wgpu::TextureFormat::Etc2Rgba8Unorm)
+            } else {
+                header.format.to_wgpu()
+            };
+
+        // Formats the device can't sample with filtering, like depth and 32 bit floats, are decoded on the CPU instead
+        let features = device.features();
+        let native_format = native_format.filter(|f| {
+            features.contains(f.required_features())
+                && f.sample_type(None, Some(features))
+                    == Some(wgpu::TextureSampleType::Float { filterable: true })
+        });
+        let software_decode = native_format.is_none();
+        let format = match native_format {
+            Some(format) if color_space.is_srgb() => format.add_srgb_suffix(),
+            Some(format) => format,
+            None => {
+                log::debug!("Decoding {:?} texture on the CPU", header.format);
+                if !header.format.is_float() && color_space.is_srgb() {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else if !header.format.is_float() {
+                    wgpu::TextureFormat::Rgba8Unorm
+                } else if features.contains(wgpu::Features::FLOAT32_FILTERABLE) {
+                    wgpu::TextureFormat::Rgba32Float
+                } else {
+                    wgpu::TextureFormat::Rgba16Float
+                }
+            }
+        };
+
+        let layer_count = layout.surfaces(0) as u32;
         let block_size = format.block_dimensions();
         let texture_size_aligned = wgpu::Extent3d {
             width: (header.width as f32 / block_size.0 as f32).ceil() as u32 * block_size.0,
             height: (header.height as f32 / block_size.1 as f32).ceil() as u32 * block_size.1,
-            ..Default::default()
+            depth_or_array_layers: layer_count,
         };
 
-        let block_count: u32 = (texture_size_aligned.width / block_size.0)
-            * (texture_size_aligned.height / block_size.1);
-        let block_size = format.block_copy_size(None).unwrap_or(1);
+        // The depth of 3D textures shrinks with every level, unlike the layer count of the array they're uploaded as
+        let level_count = if layout.depth > 1 {
+            1
+        } else {
+            layout.levels.len()
+        };
 
-        if texture_data.len() < (block_count * block_size) as usize {
-            anyhow::bail!("Insufficient data for texture conversion");
-        }
+        // Upload the levels as long as they form a regular mip chain
+        let block_copy_size = format.block_copy_size(None).unwrap_or(1);
+        let max_mips = texture_size_aligned.max_mips(wgpu::TextureDimension::D2) as usize;
+        let mut texture_data = vec![];
+        let mut mip_level_count = 0;
+        for (i, level) in layout
+            .levels
+            .iter()
+            .enumerate()
+            .take(level_count.min(max_mips))
+        {
+            let expected_size = (
+                (header.width as u32 >> i).max(1),
+                (header.height as u32 >> i).max(1),
+            );
+            let physical_size = texture_size_aligned
+                .mip_level_size(i as u32, wgpu::TextureDimension::D2)
+                .physical_size(format);
+            let surface_size = ((physical_size.width / block_size.0)
+                * (physical_size.height / block_size.1)
+                * block_copy_size) as usize;
+
+            let mut level_data = Vec::with_capacity(surface_size * layer_count as usize);
+            for layer in 0..layer_count as usize {
+                let surface = layout.surface(i, layer);
+                let surface = if software_decode {
+                    let image =
+                        decode::decode_surface(header.format, level.width, level.height, surface)?;
+                    match format {
+                        wgpu::TextureFormat::Rgba32Float => {
+                            bytemuck::cast_slice(&image.to_rgba32f()).to_vec()
+                        }
+                        wgpu::TextureFormat::Rgba16Float => image
+                            .to_rgba32f()
+                            .into_iter()
+                            .flat_map(|v| half::f16::from_f32(v).to_le_bytes())
+                            .collect(),
+                        _ => image.to_rgba8(),
+                    }
+                } else {
+                    surface.to_vec()
+                };
 
-        if format.has_depth_aspect() {
-            anyhow::bail!("Depth textures are not supported");
+                if surface.len() < surface_size {
+                    break;
+                }
+                level_data.extend_from_slice(&surface[..surface_size]);
+            }
+
+            // The full size level is taken from the texture header
+            if (i > 0 && (level.width, level.height) != expected_size)
+                || level_data.len() < surface_size * layer_count as usize
+            {
+                if i == 0 {
+                    anyhow::bail!("Insufficient data for texture conversion");
+                }
+
+                log::warn!(
+                    "Mip level {i} does not match the mip chain, skipping the remaining levels"
+                );
+                break;
+            }
+
+            texture_data.extend_from_slice(&level_data);
+            mip_level_count += 1;
         }
 
         let texture = device.create_texture_with_data(
@@ -83,27 +268,122 @@ impl Texture {
             &wgpu::TextureDescriptor {
                 label: None,
                 size: texture_size_aligned,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[format],
+                view_formats: &[format, format.remove_srgb_suffix()],
             },
-            wgpu::util::TextureDataOrder::LayerMajor,
-            texture_data.as_ref(),
+            wgpu::util::TextureDataOrder::MipMajor,
+            &texture_data,
         );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
 
         Ok(Self {
             texture,
             view,
             header,
+            color_space,
         })
     }
 
+    /// 2D view of a single mip level of a layer, cube face or depth slice
+    pub fn layer_view(&self, level: u32, layer: u32) -> wgpu::TextureView {
+        self.create_layer_view(level, layer, self.texture.format())
+    }
+
+    /// Like [`Texture::layer_view`], but sRGB textures are read as they are stored instead of being decoded to linear
+    pub fn encoded_layer_view(&self, level: u32, layer: u32) -> wgpu::TextureView {
+        self.create_layer_view(level, layer, self.texture.format().remove_srgb_suffix())
+    }
+
+    fn create_layer_view(
+        &self,
+        level: u32,
+        layer: u32,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(format),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    /// Number of layers, cube faces or depth slices
+    pub fn layer_count(&self) -> u32 {
+        self.texture.depth_or_array_layers()
+    }
+
+    pub fn is_cube(&self) -> bool {
+        matches!(
+            self.header.texture_type,
+            structs::TextureType::Cube | structs::TextureType::CubeArray
+        )
+    }
+
+    /// Size of a mip level, without the padding to the block size
+    pub fn mip_size(&self, level: u32) -> (u32, u32) {
+        (
+            (self.header.width as u32 >> level).max(1),
+            (self.header.height as u32 >> level).max(1),
+        )
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.texture.mip_level_count()
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
         self.header.width as f32 / self.header.height as f32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::PixelFormat, structs::TextureType, testing::texture_file};
+
+    #[test]
+    fn mips_decode_the_first_surface_of_multi_surface_levels() {
+        // An 8x8 punchthrough cubemap with 2 levels, only the first face has non-zero blocks
+        let level = |blocks: usize| {
+            let mut data = vec![0; 6 * blocks * 8];
+            data[..blocks * 8].fill(0xA5);
+            data
+        };
+        let data = texture_file(
+            PixelFormat::Etc2Rgba,
+            TextureType::Cube,
+            (8, 8),
+            &[level(4), level(1)],
+        );
+
+        let mips = decode_mips(&data).unwrap();
+        assert_eq!(mips.len(), 2);
+        for (mip, (size, blocks)) in mips.iter().zip([(8, 4), (4, 1)]) {
+            let face = decode::decode_surface(
+                PixelFormat::Etc2Rgba,
+                size,
+                size,
+                &level(blocks)[..blocks * 8],
+            )
+            .unwrap();
+            assert_eq!((mip.width, mip.height), (size, size));
+            assert_eq!(mip.to_rgba8(), face.to_rgba8());
+        }
+
+        assert_eq!(decode_layers(&data, 1).unwrap().len(), 6);
+        assert_eq!(decode_mip(&data, 1).unwrap().to_rgba8(), mips[1].to_rgba8());
+    }
+}
@@ -84,7 +84,7 @@ pub enum SampleQuality {
 
 #[binread]
 #[br(repr(u8))]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureType {
     Texture1D = 0,
     Texture2D = 1,
@@ -97,7 +97,7 @@ pub enum TextureType {
 
 #[binread]
 #[br(repr(u8))]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureCompression {
     Default = 0,
     NormalMap = 1,
@@ -125,7 +125,7 @@ pub enum TextureCompression {
 
 #[binread]
 #[br(repr(u8))]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureLodGroup {
     World = 0,
     WorldNormalMap = 1,
@@ -150,6 +150,51 @@ pub enum TextureLodGroup {
     ImageBaseReflection = 20,
 }
 
+impl TextureCompression {
+    /// Presets for normals, masks, lookup tables and other data that isn't sRGB encoded
+    pub fn is_data(&self) -> bool {
+        matches!(
+            self,
+            Self::NormalMap
+                | Self::DisplacementMap
+                | Self::HDR
+                | Self::NormalMapUncompress
+                | Self::NormalMapBC5
+                | Self::VectorMap
+                | Self::LightMap
+                | Self::MixMap
+                | Self::TerrainBlock
+                | Self::TerrainIndex
+                | Self::NormalMapCompact
+                | Self::BC6H
+                | Self::LightProfile
+                | Self::LUTHDR
+                | Self::LUTLOG
+                | Self::TerrainNormalMap
+        )
+    }
+}
+
+impl TextureLodGroup {
+    /// Groups of normal, specular and lookup textures that aren't sRGB encoded
+    pub fn is_data(&self) -> bool {
+        matches!(
+            self,
+            Self::WorldNormalMap
+                | Self::WorldSpecular
+                | Self::CharacterNormalMap
+                | Self::CharacterSpecular
+                | Self::WeaponNormalMap
+                | Self::WeaponSpecular
+                | Self::ShadowMap
+                | Self::LUT
+                | Self::TerrainBlockMap
+                | Self::TerrainIndexMap
+                | Self::TerrainLightMap
+        )
+    }
+}
+
 #[binread]
 #[br(repr(u8))]
 #[derive(Debug)]
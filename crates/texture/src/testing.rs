@@ -0,0 +1,48 @@
+//! Synthetic Messiah texture files and compressed blocks for tests.
+
+use crate::{format::PixelFormat, structs::TextureType};
+
+/// Builds a texture file with one uncompressed mip header per level, largest first
+pub(crate) fn texture_file(
+    format: PixelFormat,
+    texture_type: TextureType,
+    (width, height): (u16, u16),
+    levels: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut data = 0u32.to_le_bytes().to_vec();
+    // Filters and address modes, then the format, mip level, flags, compression preset, LOD group and mip generation
+    // preset
+    data.extend_from_slice(&[1, 1, 1, 1, 1, format as u8, 0, 0, 0, 0, 0]);
+    data.push(texture_type as u8);
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(&[0; 16]); // default_color
+    let size: usize = levels.iter().map(Vec::len).sum();
+    data.extend_from_slice(&(size as u32).to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&(levels.len() as u16).to_le_bytes());
+
+    for (i, level) in levels.iter().enumerate() {
+        data.extend_from_slice(&(16 + level.len() as u32).to_le_bytes()); // total_size
+        data.extend_from_slice(&(width >> i).max(1).to_le_bytes());
+        data.extend_from_slice(&(height >> i).max(1).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(level.len() as u32).to_le_bytes());
+        data.extend_from_slice(level);
+    }
+
+    data
+}
+
+/// Packs `(bit count, value)` fields into a 16 byte block, least significant bit first
+pub(crate) fn pack_bits(fields: &[(u32, u32)]) -> [u8; 16] {
+    let mut block = 0u128;
+    let mut position = 0;
+    for &(count, value) in fields {
+        assert!(value < 1 << count, "{value} doesn't fit in {count} bits");
+        block |= (value as u128) << position;
+        position += count;
+    }
+    assert_eq!(position, 128, "block has {position} bits");
+    block.to_le_bytes()
+}
@@ -0,0 +1,81 @@
+//! Mapping linear float images to displayable 8 bit sRGB previews.
+
+use crate::decode::{DecodedImage, Pixels};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Operator {
+    /// Clips everything above 1
+    #[default]
+    Clamp,
+    /// `c / (1 + c)`, keeps the highlights but desaturates them
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Tonemap {
+    pub operator: Operator,
+    /// Exposure adjustment in stops, applied before the operator
+    pub exposure: f32,
+}
+
+impl Tonemap {
+    pub fn new(operator: Operator, exposure: f32) -> Self {
+        Self { operator, exposure }
+    }
+
+    /// Maps a linear color channel to the 0-1 range
+    pub fn apply(&self, v: f32) -> f32 {
+        let v = (v * self.exposure.exp2()).max(0.0);
+        let v = match self.operator {
+            Operator::Clamp => v,
+            Operator::Reinhard => v / (1.0 + v),
+            Operator::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        };
+
+        // Also catches NaN
+        if v.is_finite() {
+            v.clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+pub(crate) fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Tonemaps a float image to sRGB encoded RGBA8. Alpha is clamped, and RGBA8 images are returned as is
+pub fn tonemap(image: &DecodedImage, tonemap: &Tonemap) -> DecodedImage {
+    let pixels = match &image.pixels {
+        Pixels::Rgba8(pixels) => pixels.clone(),
+        Pixels::Rgba32F(pixels) => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let color = |v: f32| linear_to_srgb(tonemap.apply(v));
+                [color(p[0]), color(p[1]), color(p[2]), p[3].clamp(0.0, 1.0)]
+            })
+            .map(|v| (v * 255.0).round() as u8)
+            .collect(),
+    };
+
+    DecodedImage {
+        width: image.width,
+        height: image.height,
+        pixels: Pixels::Rgba8(pixels),
+    }
+}